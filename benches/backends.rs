@@ -0,0 +1,262 @@
+//! Compare the backends and the fit policies on synthetic workloads and on captured traces.
+//!
+//! Run with `cargo bench --features std`; set `TRACE_FILE` to a trace written by `TraceRecorder`, encoded by `TraceEvent::to_bytes`, to replay it as well.
+//! Criterion reports the throughput of each backend in the group of each workload and the latency of the slowest operation of a replay in its `-worst` group;
+//! the slowest operation and the fragmentation left by each workload are also printed once before.
+//! There is no TLSF backend: the segregated fit with its bitmap of populated classes is the closest one the crate has.
+
+#[macro_use]
+extern crate criterion;
+extern crate boundary_tag_allocator;
+
+use std::alloc::Layout;
+use std::cmp;
+use std::env;
+use std::fs;
+use std::ptr::NonNull;
+use std::time::{Duration, Instant};
+
+use criterion::{BenchmarkId, Criterion, Throughput};
+
+use boundary_tag_allocator::{Allocator, BoundaryTag, BuddyAllocator, EntropySource, HostRegion, MemoryManager, MemoryRegion};
+use boundary_tag_allocator::{TraceEvent, TraceOp, TRACE_EVENT_SIZE};
+
+
+const HEAP_SIZE: usize = 1 << 20;
+const NUM_EVENTS: usize = 20_000;
+const NUM_SLOTS: usize = 512;
+
+static SIZE_CLASSES: [usize; 9] = [16, 32, 64, 128, 256, 512, 1024, 2048, 4096];
+
+
+#[derive(Debug, Clone, Copy)]
+enum Backend {
+    /// The segregated free lists, the default of `MemoryManager`.
+    SegregatedFit,
+    /// The address-ordered first fit of `set_address_ordered_free_lists`.
+    FirstFit,
+    /// The random fit of `set_random_fit` among a single candidate, which scans every free list for the smallest fitting block.
+    BestFit,
+    /// The segregated fit rounding the requests to `SIZE_CLASSES`.
+    SizeClasses,
+    Buddy,
+}
+
+
+const BACKENDS: [Backend; 5] = [Backend::SegregatedFit, Backend::FirstFit, Backend::BestFit, Backend::SizeClasses, Backend::Buddy];
+
+
+/// The random fit among one candidate always takes the first one, so no entropy is needed.
+struct NoEntropy;
+
+
+impl EntropySource for NoEntropy {
+    fn next_usize(&mut self) -> usize
+    {
+        0
+    }
+}
+
+
+/// Result of a replay: the time spent in the allocator, the slowest operation and the fragmentation at the end, if the backend tells it.
+struct Run {
+    elapsed: Duration,
+    worst: Duration,
+    failed: usize,
+    fragmentation: Option<usize>,
+}
+
+
+/// The xorshift generator, enough to make the workloads reproducible.
+struct Rng(u64);
+
+
+impl Rng {
+    fn next(&mut self) -> u64
+    {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize
+    {
+        (self.next() % n as u64) as usize
+    }
+}
+
+
+/// Allocate or free a random slot at each step, picking the size of each allocation by `size_of`.
+fn synthetic<F>(seed: u64, mut size_of: F) -> Vec<TraceEvent>
+    where F: FnMut(&mut Rng) -> usize
+{
+    let mut rng    = Rng(seed);
+    let mut live   = [false; NUM_SLOTS];
+    let mut events = Vec::with_capacity(NUM_EVENTS);
+    for _ in 0..NUM_EVENTS {
+        let slot  = rng.below(NUM_SLOTS);
+        let event =
+            if live[slot] {
+                TraceEvent { op: TraceOp::Free, size: 0, align: 1, id: slot as u32 }
+            } else {
+                TraceEvent { op: TraceOp::Alloc, size: size_of(&mut rng) as u32, align: 8, id: slot as u32 }
+            };
+        live[slot] = !live[slot];
+        events.push(event);
+    }
+
+    events
+}
+
+
+/// The events of the trace at `TRACE_FILE`, if it is set.
+fn captured() -> Option<Vec<TraceEvent>>
+{
+    let path  = env::var_os("TRACE_FILE")?;
+    let bytes = fs::read(path).expect("cannot read TRACE_FILE");
+    let events = bytes.chunks(TRACE_EVENT_SIZE).filter_map(|chunk| {
+        let mut event = [0; TRACE_EVENT_SIZE];
+        if chunk.len() != TRACE_EVENT_SIZE {
+            return None;
+        }
+        event.copy_from_slice(chunk);
+        TraceEvent::from_bytes(&event)
+    }).collect();
+
+    Some(events)
+}
+
+
+fn workloads() -> Vec<(&'static str, Vec<TraceEvent>)>
+{
+    let mut workloads = vec![
+        ("small", synthetic(1, |rng| 8 + rng.below(248))),
+        ("mixed", synthetic(2, |rng| if rng.below(10) < 8 { 8 + rng.below(248) } else { 1024 + rng.below(7168) })),
+        ("power-of-two", synthetic(3, |rng| 16 << rng.below(8))),
+    ];
+    if let Some(events) = captured() {
+        workloads.push(("captured", events));
+    }
+
+    workloads
+}
+
+
+/// Replay `events` on `allocator`, timing every operation; the allocations the allocator cannot serve are skipped.
+/// The allocations without a valid layout, such as the zero alignment of a corrupted trace, are skipped without being timed.
+/// The blocks left live are kept in `live`.
+fn replay_on<A: Allocator>(allocator: &mut A, events: &[TraceEvent], live: &mut [Option<(NonNull<u8>, Layout)>]) -> Run
+{
+    let mut run = Run { elapsed: Duration::new(0, 0), worst: Duration::new(0, 0), failed: 0, fragmentation: None };
+    for event in events {
+        let id = event.id as usize;
+        let (start, served) =
+            match event.op {
+                TraceOp::Alloc => {
+                    let layout =
+                        match Layout::from_size_align(cmp::max(event.size as usize, 1), event.align as usize) {
+                            Err(_) => continue,
+                            Ok(layout) => layout,
+                        };
+                    let start  = Instant::now();
+                    let ptr    = allocator.allocate(layout);
+                    live[id]   = ptr.map(|ptr| (ptr, layout));
+                    (start, ptr.is_some())
+                },
+                TraceOp::Free => {
+                    let start = Instant::now();
+                    if let Some((ptr, layout)) = live[id].take() {
+                        allocator.deallocate(ptr, layout);
+                    }
+                    (start, true)
+                },
+            };
+
+        let elapsed = start.elapsed();
+        run.elapsed += elapsed;
+        run.worst    = cmp::max(run.worst, elapsed);
+        if !served {
+            run.failed += 1;
+        }
+    }
+
+    run
+}
+
+
+fn free_live<A: Allocator>(allocator: &mut A, live: &mut [Option<(NonNull<u8>, Layout)>])
+{
+    for slot in live.iter_mut() {
+        if let Some((ptr, layout)) = slot.take() {
+            allocator.deallocate(ptr, layout);
+        }
+    }
+}
+
+
+/// Replay `events` on a fresh heap of `backend`.
+fn run(backend: Backend, events: &[TraceEvent]) -> Run
+{
+    let host     = HostRegion::new(HEAP_SIZE, 4096).unwrap();
+    let num_ids  = events.iter().map(|event| event.id as usize + 1).max().unwrap_or(0);
+    let mut live = vec![None; num_ids];
+
+    if let Backend::Buddy = backend {
+        let mut buddy = BuddyAllocator::new(&MemoryRegion::new(host.addr(), host.size()), 16).unwrap();
+        let run       = replay_on(&mut buddy, events, &mut live);
+        free_live(&mut buddy, &mut live);
+        return run;
+    }
+
+    let mut tags    = [BoundaryTag::from_memory(host.addr(), host.size())];
+    let mut entropy = NoEntropy;
+    let mut mman    = MemoryManager::new(&mut tags);
+    match backend {
+        Backend::FirstFit => mman.set_address_ordered_free_lists(true),
+        Backend::BestFit => mman.set_random_fit(1, &mut entropy),
+        Backend::SizeClasses => mman.set_size_classes(&SIZE_CLASSES),
+        _ => (),
+    }
+
+    // The fragmentation is taken with the blocks of the workload still in place.
+    let mut run = replay_on(&mut mman, events, &mut live);
+    run.fragmentation = Some(mman.fragmentation());
+    free_live(&mut mman, &mut live);
+    run
+}
+
+
+fn bench_backends(c: &mut Criterion)
+{
+    for (name, events) in workloads() {
+        println!("{}: {} events", name, events.len());
+        for &backend in BACKENDS.iter() {
+            let result        = run(backend, &events);
+            let fragmentation = result.fragmentation.map_or("-".to_string(), |percent| format!("{}%", percent));
+            println!("  {:?}: worst {:?}, {} failed, fragmentation {}", backend, result.worst, result.failed, fragmentation);
+        }
+
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Elements(events.len() as u64));
+        for &backend in BACKENDS.iter() {
+            group.bench_with_input(BenchmarkId::from_parameter(format!("{:?}", backend)), &events, |b, events| {
+                b.iter_custom(|iters| (0..iters).map(|_| run(backend, events).elapsed).sum());
+            });
+        }
+        group.finish();
+
+        // Criterion takes the slowest operation of each replay as the time of an iteration.
+        let mut group = c.benchmark_group(format!("{}-worst", name));
+        for &backend in BACKENDS.iter() {
+            group.bench_with_input(BenchmarkId::from_parameter(format!("{:?}", backend)), &events, |b, events| {
+                b.iter_custom(|iters| (0..iters).map(|_| run(backend, events).worst).sum());
+            });
+        }
+        group.finish();
+    }
+}
+
+
+criterion_group!(benches, bench_backends);
+criterion_main!(benches);
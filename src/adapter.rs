@@ -0,0 +1,135 @@
+use core::cell::RefCell;
+use core::ptr;
+use core::ptr::NonNull;
+
+use super::MemoryManager;
+
+
+/// Shared reference to a `MemoryManager` implementing `allocator_api2::alloc::Allocator`,
+/// so that the collections generic over the allocator, such as `hashbrown::HashMap::with_hasher_in`, can live on the heap.
+/// It is `Copy`, so every collection takes its own.
+pub struct HeapAdapter<'m, 'a: 'm> {
+    mman: &'m RefCell<MemoryManager<'a>>,
+}
+
+
+impl<'m, 'a: 'm> HeapAdapter<'m, 'a> {
+    pub fn new(mman: &'m RefCell<MemoryManager<'a>>) -> HeapAdapter<'m, 'a>
+    {
+        HeapAdapter {
+            mman,
+        }
+    }
+}
+
+
+impl<'m, 'a: 'm> Clone for HeapAdapter<'m, 'a> {
+    fn clone(&self) -> HeapAdapter<'m, 'a>
+    {
+        *self
+    }
+}
+
+
+impl<'m, 'a: 'm> Copy for HeapAdapter<'m, 'a> {}
+
+
+unsafe impl<'m, 'a: 'm> allocator_api2::alloc::Allocator for HeapAdapter<'m, 'a> {
+    fn allocate(&self, layout: allocator_api2::alloc::Layout) -> Result<NonNull<[u8]>, allocator_api2::alloc::AllocError>
+    {
+        // No block is allocated for a zero-sized layout; the aligned dangling pointer stands for it.
+        let addr =
+            if layout.size() == 0 {
+                layout.align()
+            } else {
+                match self.mman.borrow_mut().alloc_aligned_bytes(layout.size(), layout.align()) {
+                    None => return Err(allocator_api2::alloc::AllocError),
+                    Some(addr) => addr,
+                }
+            };
+
+        Ok(unsafe { NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(addr as *mut u8, layout.size())) })
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: allocator_api2::alloc::Layout)
+    {
+        if layout.size() != 0 {
+            self.mman.borrow_mut().free_bytes(ptr.as_ptr() as usize);
+        }
+    }
+
+    unsafe fn grow(&self, ptr: NonNull<u8>, old_layout: allocator_api2::alloc::Layout, new_layout: allocator_api2::alloc::Layout) -> Result<NonNull<[u8]>, allocator_api2::alloc::AllocError>
+    {
+        // `realloc_bytes` keeps only the heap alignment when it moves the block.
+        if old_layout.size() == 0 || self.mman.borrow().min_align < new_layout.align() {
+            let new_ptr = self.allocate(new_layout)?;
+            ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr() as *mut u8, old_layout.size());
+            self.deallocate(ptr, old_layout);
+            return Ok(new_ptr);
+        }
+
+        match self.mman.borrow_mut().realloc_bytes(ptr.as_ptr() as usize, new_layout.size()) {
+            None => Err(allocator_api2::alloc::AllocError),
+            Some(addr) => Ok(NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(addr as *mut u8, new_layout.size()))),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+
+    use super::*;
+    use BoundaryTag;
+    use HostRegion;
+
+    extern crate hashbrown;
+    use self::hashbrown::HashMap;
+    // The hasher of hashbrown is not built without its default features.
+    use std::collections::hash_map::RandomState;
+
+    #[test]
+    fn test_hashbrown_rehash()
+    {
+        let host = HostRegion::new(64 * 1024, 16).unwrap();
+        let mut tags = [BoundaryTag::from_memory(host.addr(), host.size())];
+        let mman     = RefCell::new(MemoryManager::new(&mut tags));
+        let initial  = mman.borrow().stats();
+
+        // Growing from empty rehashes the table at every power of two.
+        for round in 0..4 {
+            let mut map = HashMap::with_hasher_in(RandomState::new(), HeapAdapter::new(&mman));
+            for i in 0..1000u32 {
+                map.insert(i, i * round);
+            }
+            for i in (0..1000u32).filter(|i| i % 2 == 0) {
+                assert_eq!(map.remove(&i), Some(i * round));
+            }
+            map.shrink_to_fit();
+            assert_eq!(map.len(), 500);
+            assert!((1..1000u32).step_by(2).all(|i| map[&i] == i * round));
+        }
+
+        assert_eq!(mman.borrow().check_integrity(), Ok(()));
+        assert_eq!(mman.borrow().stats(), initial);
+    }
+
+    #[test]
+    fn test_vec_grow()
+    {
+        let host = HostRegion::new(64 * 1024, 16).unwrap();
+        let mut tags = [BoundaryTag::from_memory(host.addr(), host.size())];
+        let mman     = RefCell::new(MemoryManager::new(&mut tags));
+        {
+            let mut v = allocator_api2::vec::Vec::new_in(HeapAdapter::new(&mman));
+            for i in 0..4096u64 {
+                v.push(i);
+            }
+            assert_eq!(v.iter().sum::<u64>(), 4095 * 4096 / 2);
+            assert!(mman.borrow().contains(v.as_ptr() as usize));
+        }
+
+        assert_eq!(mman.borrow().stats().used_blocks, 0);
+    }
+}
@@ -0,0 +1,61 @@
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+
+
+/// Backtraces of the live allocations of a heap, kept outside of the heap itself.
+/// The table allocates from the global allocator, so it must not be given to a heap that is the global allocator.
+pub struct BacktraceTable {
+    entries: HashMap<usize, Backtrace>,
+}
+
+
+impl BacktraceTable {
+    pub fn new() -> BacktraceTable
+    {
+        BacktraceTable {
+            entries: HashMap::new(),
+        }
+    }
+
+    /// The backtrace of the allocation whose payload is at `addr`.
+    pub fn get(&self, addr: usize) -> Option<&Backtrace>
+    {
+        self.entries.get(&addr)
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.entries.is_empty()
+    }
+
+    pub(crate) fn record(&mut self, addr: usize)
+    {
+        self.entries.insert(addr, Backtrace::force_capture());
+    }
+
+    pub(crate) fn forget(&mut self, addr: usize)
+    {
+        self.entries.remove(&addr);
+    }
+
+    /// Follow a block moved by `MemoryManager::compact` or `MemoryManager::migrate_region`.
+    pub(crate) fn relocate(&mut self, old_addr: usize, new_addr: usize)
+    {
+        if let Some(backtrace) = self.entries.remove(&old_addr) {
+            self.entries.insert(new_addr, backtrace);
+        }
+    }
+}
+
+
+impl Default for BacktraceTable {
+    fn default() -> BacktraceTable
+    {
+        BacktraceTable::new()
+    }
+}
@@ -0,0 +1,296 @@
+use core::cmp;
+use core::mem;
+use core::ptr;
+use core::ptr::NonNull;
+use core::alloc::Layout;
+
+use super::Allocator;
+use super::round_up;
+use region::MemoryRegion;
+
+
+/// Number of the orders a buddy allocator can have.
+const MAX_ORDERS: usize = 32;
+
+
+/// Power-of-two buddy allocator.
+/// Every block is aligned to its own size relative to the start of the managed area,
+/// which makes it fit for page-sized allocations that must not fragment unboundedly.
+/// A layout aligned beyond the alignment of the start of that area cannot be allocated.
+/// Whether a block is free is recorded in a bitmap placed at the head of the region,
+/// so freeing a block finds its free buddy in constant time per order.
+pub struct BuddyAllocator {
+    base_addr: usize,
+    min_block_shift: usize,
+    max_order: usize,
+    bitmap_addr: usize,
+    free_lists: [Option<usize>; MAX_ORDERS],
+}
+
+
+impl BuddyAllocator {
+    /// Manage the region in blocks of `min_block_size` bytes or more, which must be a power of two large enough to hold two links.
+    /// Return `None` if the region cannot hold a single block besides the bitmap.
+    pub fn new(region: &MemoryRegion, min_block_size: usize) -> Option<BuddyAllocator>
+    {
+        debug_check!(min_block_size.is_power_of_two());
+        debug_check!(2 * mem::size_of::<usize>() <= min_block_size);
+
+        let min_block_shift = min_block_size.trailing_zeros() as usize;
+        let region_end      = region.addr() + region.size();
+
+        // Find the largest block that still fits in the region together with its bitmap.
+        let mut max_order = MAX_ORDERS;
+        let base_addr = loop {
+            if max_order == 0 {
+                return None;
+            }
+            max_order -= 1;
+
+            let base_addr = round_up(region.addr() + BuddyAllocator::bitmap_size(max_order), min_block_size);
+            if let Some(end) = base_addr.checked_add(min_block_size << max_order) {
+                if end <= region_end {
+                    break base_addr;
+                }
+            }
+        };
+
+        let mut buddy = BuddyAllocator {
+            base_addr,
+            min_block_shift,
+            max_order,
+            bitmap_addr: region.addr(),
+            free_lists: [None; MAX_ORDERS],
+        };
+
+        unsafe { ptr::write_bytes(region.addr() as *mut u8, 0, BuddyAllocator::bitmap_size(max_order)) };
+        buddy.push_free_block(base_addr, max_order);
+
+        Some(buddy)
+    }
+
+    /// The size of the largest block, which is the whole managed area.
+    pub fn capacity(&self) -> usize
+    {
+        1 << (self.min_block_shift + self.max_order)
+    }
+
+    fn bitmap_size(max_order: usize) -> usize
+    {
+        // Each order has a bit per block, which sums up to less than twice the blocks of the order 0.
+        let num_bits: usize = 2 << max_order;
+        num_bits.div_ceil(8)
+    }
+
+    fn order_of(&self, size: usize) -> usize
+    {
+        let size = cmp::max(size, 1 << self.min_block_shift);
+        size.next_power_of_two().trailing_zeros() as usize - self.min_block_shift
+    }
+
+    fn bit_index(&self, addr: usize, order: usize) -> usize
+    {
+        let num_blocks = 1 << self.max_order;
+        let offset     = 2 * num_blocks - ((2 * num_blocks) >> order);
+        offset + ((addr - self.base_addr) >> (self.min_block_shift + order))
+    }
+
+    fn is_free(&self, addr: usize, order: usize) -> bool
+    {
+        let i    = self.bit_index(addr, order);
+        let byte = unsafe { *((self.bitmap_addr + i / 8) as *const u8) };
+        byte & (1 << (i % 8)) != 0
+    }
+
+    fn set_free(&mut self, addr: usize, order: usize, is_free: bool)
+    {
+        let i    = self.bit_index(addr, order);
+        let byte = unsafe { &mut *((self.bitmap_addr + i / 8) as *mut u8) };
+        if is_free {
+            *byte |= 1 << (i % 8);
+        } else {
+            *byte &= !(1 << (i % 8));
+        }
+    }
+
+    fn push_free_block(&mut self, addr: usize, order: usize)
+    {
+        let next_addr = self.free_lists[order];
+        unsafe {
+            ptr::write(addr as *mut usize, next_addr.unwrap_or(0));
+            ptr::write((addr + mem::size_of::<usize>()) as *mut usize, 0);
+            if let Some(next_addr) = next_addr {
+                ptr::write((next_addr + mem::size_of::<usize>()) as *mut usize, addr);
+            }
+        }
+
+        self.free_lists[order] = Some(addr);
+        self.set_free(addr, order, true);
+    }
+
+    fn remove_free_block(&mut self, addr: usize, order: usize)
+    {
+        let (next_addr, prev_addr) = unsafe {
+            (ptr::read(addr as *const usize), ptr::read((addr + mem::size_of::<usize>()) as *const usize))
+        };
+
+        unsafe {
+            if prev_addr == 0 {
+                self.free_lists[order] = if next_addr == 0 { None } else { Some(next_addr) };
+            } else {
+                ptr::write(prev_addr as *mut usize, next_addr);
+            }
+
+            if next_addr != 0 {
+                ptr::write((next_addr + mem::size_of::<usize>()) as *mut usize, prev_addr);
+            }
+        }
+
+        self.set_free(addr, order, false);
+    }
+
+    fn alloc_block(&mut self, order: usize) -> Option<usize>
+    {
+        if self.max_order < order {
+            return None;
+        }
+
+        let mut current_order = order;
+        while self.free_lists[current_order].is_none() {
+            current_order += 1;
+            if self.max_order < current_order {
+                return None;
+            }
+        }
+
+        let addr = self.free_lists[current_order]?;
+        self.remove_free_block(addr, current_order);
+
+        // Return the upper halves to the free lists until the block has the requested order.
+        while order < current_order {
+            current_order -= 1;
+            self.push_free_block(addr + (1 << (self.min_block_shift + current_order)), current_order);
+        }
+
+        Some(addr)
+    }
+
+    fn free_block(&mut self, addr: usize, order: usize)
+    {
+        let mut addr  = addr;
+        let mut order = order;
+        while order < self.max_order {
+            let buddy_addr = self.base_addr + ((addr - self.base_addr) ^ (1 << (self.min_block_shift + order)));
+            if !self.is_free(buddy_addr, order) {
+                break;
+            }
+
+            self.remove_free_block(buddy_addr, order);
+            addr   = cmp::min(addr, buddy_addr);
+            order += 1;
+        }
+
+        self.push_free_block(addr, order);
+    }
+}
+
+
+impl Allocator for BuddyAllocator {
+    fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>>
+    {
+        // A block is aligned to its size from the start of the managed area only, so the start has to be aligned as well.
+        if !self.base_addr.is_multiple_of(layout.align()) {
+            return None;
+        }
+
+        let order = self.order_of(cmp::max(layout.size(), layout.align()));
+        self.alloc_block(order).map(|addr| unsafe { NonNull::new_unchecked(addr as *mut u8) })
+    }
+
+    fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout)
+    {
+        let order = self.order_of(cmp::max(layout.size(), layout.align()));
+        self.free_block(ptr.as_ptr() as usize, order);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use HostRegion;
+
+    fn allocate_region(size: usize) -> MemoryRegion
+    {
+        HostRegion::new(size, 16).unwrap().leak()
+    }
+
+    #[test]
+    fn test_new()
+    {
+        let region = allocate_region(64 * 1024);
+        let buddy  = BuddyAllocator::new(&region, 4096).unwrap();
+        assert_eq!(buddy.base_addr % 4096, 0);
+        assert!(region.addr() + BuddyAllocator::bitmap_size(buddy.max_order) <= buddy.base_addr);
+        assert!(buddy.base_addr + buddy.capacity() <= region.addr() + region.size());
+
+        assert!(BuddyAllocator::new(&allocate_region(4096), 4096).is_none());
+    }
+
+    #[test]
+    fn test_split_and_merge()
+    {
+        let region    = allocate_region(64 * 1024);
+        let mut buddy = BuddyAllocator::new(&region, 4096).unwrap();
+        let max_order = buddy.max_order;
+
+        let page1 = buddy.malloc::<[u8; 4096]>().unwrap();
+        let page2 = buddy.malloc::<[u8; 4096]>().unwrap();
+        let addr1 = (page1 as *const _) as usize;
+        let addr2 = (page2 as *const _) as usize;
+        assert_eq!(addr1 % 4096, 0);
+        assert_eq!((addr1 - buddy.base_addr) ^ (addr2 - buddy.base_addr), 4096);
+
+        buddy.free(page1);
+        buddy.free(page2);
+        assert_eq!(buddy.free_lists[max_order], Some(buddy.base_addr));
+        for order in 0..max_order {
+            assert!(buddy.free_lists[order].is_none());
+        }
+    }
+
+    #[test]
+    fn test_exhaustion()
+    {
+        let region    = allocate_region(64 * 1024);
+        let mut buddy = BuddyAllocator::new(&region, 4096).unwrap();
+
+        let count = buddy.capacity() / 4096;
+        for _ in 0..count {
+            assert!(buddy.malloc::<[u8; 4096]>().is_some());
+        }
+        assert!(buddy.malloc::<[u8; 4096]>().is_none());
+    }
+
+    #[test]
+    fn test_over_aligned()
+    {
+        let region    = HostRegion::new(128 * 1024, 64 * 1024).unwrap().leak();
+        let mut buddy = BuddyAllocator::new(&region, 16).unwrap();
+        let max_order = buddy.max_order;
+        assert_eq!(buddy.base_addr % 256, 0);
+
+        let small   = Layout::from_size_align(16, 16).unwrap();
+        let aligned = Layout::from_size_align(16, 256).unwrap();
+        let ptr1    = buddy.allocate(small).unwrap();
+        let ptr2    = buddy.allocate(aligned).unwrap();
+        assert_eq!(ptr2.as_ptr() as usize % 256, 0);
+        assert!(buddy.allocate(Layout::from_size_align(16, 2 * buddy.capacity()).unwrap()).is_none());
+
+        // The block is freed at the order it was allocated at, so the buddies merge back into one.
+        buddy.deallocate(ptr2, aligned);
+        buddy.deallocate(ptr1, small);
+        assert_eq!(buddy.free_lists[max_order], Some(buddy.base_addr));
+    }
+}
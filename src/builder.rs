@@ -0,0 +1,155 @@
+use core::ptr::Unique;
+
+use super::BoundaryTag;
+use super::MemoryManager;
+use policy::FitPolicy;
+
+
+/// Collects the configuration of a `MemoryManager` and creates it at once,
+/// so that the settings that must precede the first allocation cannot be applied too late.
+pub struct MemoryManagerBuilder<'a> {
+    tags: &'a mut [Unique<BoundaryTag>],
+    num_regions: usize,
+    policy: FitPolicy<'a>,
+    min_align: usize,
+    split_threshold: usize,
+    size_classes: &'a [usize],
+    quick_list_sizes: &'a [usize],
+    quick_list_len: usize,
+    deferred_coalescing: bool,
+}
+
+
+impl<'a> MemoryManagerBuilder<'a> {
+    /// Manage the regions of all the `tags`.
+    pub fn new(tags: &'a mut [Unique<BoundaryTag>]) -> MemoryManagerBuilder<'a>
+    {
+        let num_regions = tags.len();
+        MemoryManagerBuilder {
+            tags,
+            num_regions,
+            policy: FitPolicy::SegregatedFit,
+            min_align: 0,
+            split_threshold: 0,
+            size_classes: &[],
+            quick_list_sizes: &[],
+            quick_list_len: 0,
+            deferred_coalescing: false,
+        }
+    }
+
+    /// Manage only the first `num_regions` tags and keep the rest for `MemoryManager::absorb`.
+    pub fn num_regions(mut self, num_regions: usize) -> MemoryManagerBuilder<'a>
+    {
+        self.num_regions = num_regions;
+        self
+    }
+
+    pub fn fit_policy(mut self, policy: FitPolicy<'a>) -> MemoryManagerBuilder<'a>
+    {
+        self.policy = policy;
+        self
+    }
+
+    pub fn min_align(mut self, min_align: usize) -> MemoryManagerBuilder<'a>
+    {
+        self.min_align = min_align;
+        self
+    }
+
+    pub fn split_threshold(mut self, threshold: usize) -> MemoryManagerBuilder<'a>
+    {
+        self.split_threshold = threshold;
+        self
+    }
+
+    pub fn size_classes(mut self, size_classes: &'a [usize]) -> MemoryManagerBuilder<'a>
+    {
+        self.size_classes = size_classes;
+        self
+    }
+
+    pub fn quick_lists(mut self, sizes: &'a [usize], max_len: usize) -> MemoryManagerBuilder<'a>
+    {
+        self.quick_list_sizes = sizes;
+        self.quick_list_len   = max_len;
+        self
+    }
+
+    pub fn deferred_coalescing(mut self, enable: bool) -> MemoryManagerBuilder<'a>
+    {
+        self.deferred_coalescing = enable;
+        self
+    }
+
+    pub fn build(self) -> MemoryManager<'a>
+    {
+        let mut mman = MemoryManager::with_capacity(self.tags, self.num_regions);
+        mman.set_min_align(if self.min_align == 0 { 1 } else { self.min_align });
+        mman.set_split_threshold(self.split_threshold);
+        mman.set_size_classes(self.size_classes);
+        mman.set_quick_lists(self.quick_list_sizes, self.quick_list_len);
+        mman.set_deferred_coalescing(self.deferred_coalescing);
+
+        match self.policy {
+            FitPolicy::SegregatedFit => {},
+            FitPolicy::AddressOrdered => mman.set_address_ordered_free_lists(true),
+            FitPolicy::RandomFit(k, entropy) => mman.set_random_fit(k, entropy),
+        }
+
+        mman
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use core::mem;
+
+    use super::*;
+
+    use HostRegion;
+
+    fn allocate_memory() -> (usize, usize)
+    {
+        const SIZE: usize = 4096;
+        let addr = HostRegion::new(SIZE, 16).unwrap().leak().addr();
+
+        (addr, SIZE)
+    }
+
+    #[test]
+    fn test_build()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let classes  = [64, 256];
+        let mut mman = MemoryManagerBuilder::new(&mut tags)
+            .fit_policy(FitPolicy::AddressOrdered)
+            .min_align(64)
+            .split_threshold(128)
+            .size_classes(&classes)
+            .build();
+
+        assert!(mman.address_ordered);
+        assert_eq!(mman.request_size_of(100), 256);
+
+        let obj = mman.alloc_bytes(100).unwrap();
+        assert_eq!(obj % 64, 0);
+        mman.free_bytes(obj);
+        assert_eq!(mman.check_integrity(), Ok(()));
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+    }
+
+    #[test]
+    fn test_split_threshold()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManagerBuilder::new(&mut tags).split_threshold(size).build();
+
+        // The rest is below the threshold, so the whole region is taken.
+        let obj = mman.alloc_bytes(64).unwrap();
+        assert_eq!(mman.block_of(obj).unwrap().size, size - mem::size_of::<BoundaryTag>());
+    }
+}
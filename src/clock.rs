@@ -0,0 +1,86 @@
+#[cfg(any(test, feature = "std"))]
+use std::time::Instant;
+
+
+/// Source of timestamps for the time-aware features, such as `LifetimeRecorder`.
+/// A tick can be any unit as long as the timestamps never go backward, so a kernel can count the cycles:
+///
+/// ```ignore
+/// struct CycleCounter;
+///
+/// impl Clock for CycleCounter {
+///     fn now(&self) -> u64
+///     {
+///         unsafe { core::arch::x86_64::_rdtsc() }
+///     }
+/// }
+/// ```
+pub trait Clock {
+    fn now(&self) -> u64;
+
+    /// The ticks from `earlier`, a timestamp taken from this clock, until now.
+    fn elapsed(&self, earlier: u64) -> u64
+    {
+        self.now().saturating_sub(earlier)
+    }
+}
+
+
+impl<C: Clock> Clock for &C {
+    fn now(&self) -> u64
+    {
+        (**self).now()
+    }
+}
+
+
+/// `Clock` of the host counting the nanoseconds from its creation.
+#[cfg(any(test, feature = "std"))]
+pub struct StdClock {
+    origin: Instant,
+}
+
+
+#[cfg(any(test, feature = "std"))]
+impl StdClock {
+    pub fn new() -> StdClock
+    {
+        StdClock {
+            origin: Instant::now(),
+        }
+    }
+}
+
+
+#[cfg(any(test, feature = "std"))]
+impl Default for StdClock {
+    fn default() -> StdClock
+    {
+        StdClock::new()
+    }
+}
+
+
+#[cfg(any(test, feature = "std"))]
+impl Clock for StdClock {
+    fn now(&self) -> u64
+    {
+        self.origin.elapsed().as_nanos() as u64
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_std_clock()
+    {
+        let clock = StdClock::new();
+        let start = clock.now();
+        let later = <&StdClock as Clock>::now(&&clock);
+        assert!(start <= later);
+        assert!(clock.elapsed(later) <= clock.now());
+    }
+}
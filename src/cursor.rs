@@ -0,0 +1,255 @@
+use core::mem;
+use core::ptr::Unique;
+
+use error::CursorError;
+use manager::{BlockInfo, MemoryManager, MIN_PAYLOAD_SIZE};
+use tag::BoundaryTag;
+
+
+/// Position on the blocks of a `MemoryManager`, made by `MemoryManager::cursor`, to write a placement policy outside of the crate.
+/// Every operation checks that the chain and the free lists stay consistent and refuses to run otherwise.
+pub struct BlockCursor<'m, 'a: 'm, M: 'a = (), const MIN_ALIGN: usize = 1> {
+    mman: &'m mut MemoryManager<'a, M, MIN_ALIGN>,
+    region: usize,
+    tag: Option<Unique<BoundaryTag<M>>>,
+}
+
+
+impl<'m, 'a: 'm, M: 'a + Copy + Default, const MIN_ALIGN: usize> BlockCursor<'m, 'a, M, MIN_ALIGN> {
+    pub(crate) fn new(mman: &'m mut MemoryManager<'a, M, MIN_ALIGN>) -> BlockCursor<'m, 'a, M, MIN_ALIGN>
+    {
+        let mut cursor = BlockCursor {
+            mman,
+            region: 0,
+            tag: None,
+        };
+        cursor.seek_chain_head();
+        cursor
+    }
+
+    /// The block at the cursor, or `None` past the last block.
+    pub fn block(&self) -> Option<BlockInfo>
+    {
+        self.tag.map(|tag| {
+            let tag_ref = unsafe { tag.as_ref() };
+            BlockInfo {
+                addr: tag_ref.addr_free_area(),
+                size: tag_ref.free_area_size(),
+                is_alloc: tag_ref.is_alloc,
+            }
+        })
+    }
+
+    /// Move to the next block, going on to the next region at the end of a region.
+    /// Return `false` when the cursor goes past the last block.
+    pub fn move_next(&mut self) -> bool
+    {
+        let tag =
+            match self.tag {
+                None => return false,
+                Some(tag) => tag,
+            };
+
+        self.tag = BoundaryTag::<M>::next_tag_of(&tag);
+        if self.tag.is_none() {
+            self.region += 1;
+            self.seek_chain_head();
+        }
+
+        self.tag.is_some()
+    }
+
+    /// Move to the previous block in the same chain; the cursor stays where it is at the head of a chain.
+    pub fn move_prev(&mut self) -> bool
+    {
+        match self.tag.and_then(|tag| BoundaryTag::<M>::prev_tag_of(&tag, self.mman.link_key)) {
+            None => false,
+            Some(prev_tag) => {
+                self.tag = Some(prev_tag);
+                true
+            },
+        }
+    }
+
+    /// Keep `size` bytes, rounded up as an allocation would be, in the free block at the cursor and make the rest a free block after it.
+    pub fn split(&mut self, size: usize) -> Result<(), CursorError>
+    {
+        let tag      = self.free_tag()?;
+        let size     = self.mman.request_size_of(size);
+        let old_size = unsafe { tag.as_ref() }.free_area_size();
+        if old_size < size + mem::size_of::<BoundaryTag<M>>() + MIN_PAYLOAD_SIZE {
+            return Err(CursorError::TooSmall);
+        }
+
+        self.mman.unprotect_metadata();
+        self.mman.remove_free_tag(unsafe { tag.as_ref() });
+        let (tag, new_tag) = BoundaryTag::<M>::divide(tag, old_size - size - mem::size_of::<BoundaryTag<M>>(), self.mman.link_key);
+        self.mman.insert_free_tag(unsafe { tag.as_ref() });
+        if let Some(new_tag) = new_tag {
+            self.mman.insert_free_tag(unsafe { new_tag.as_ref() });
+        }
+        self.mman.protect_metadata();
+
+        debug_check!(self.mman.check_integrity().is_ok());
+        Ok(())
+    }
+
+    /// Merge the free block at the cursor with the free block after it.
+    pub fn merge_next(&mut self) -> Result<(), CursorError>
+    {
+        let tag      = self.free_tag()?;
+        let next_tag =
+            match BoundaryTag::<M>::next_tag_of(&tag) {
+                None => return Err(CursorError::NoNeighbor),
+                Some(next_tag) => next_tag,
+            };
+        if unsafe { next_tag.as_ref() }.is_alloc {
+            return Err(CursorError::WrongState);
+        }
+
+        self.mman.unprotect_metadata();
+        self.mman.remove_free_tag(unsafe { tag.as_ref() });
+        self.mman.remove_free_tag(unsafe { next_tag.as_ref() });
+        let tag = BoundaryTag::<M>::merge(tag, next_tag, self.mman.link_key);
+        self.mman.insert_free_tag(unsafe { tag.as_ref() });
+        self.mman.protect_metadata();
+        self.tag = Some(tag);
+
+        debug_check!(self.mman.check_integrity().is_ok());
+        Ok(())
+    }
+
+    /// Allocate the whole free block at the cursor and return the address of its payload, which `free_bytes` takes back.
+    pub fn mark_alloc(&mut self) -> Result<usize, CursorError>
+    {
+        let mut tag = self.free_tag()?;
+        self.mman.unprotect_metadata();
+        self.mman.remove_free_tag(unsafe { tag.as_ref() });
+        {
+            let tag_mut = unsafe { tag.as_mut() };
+            tag_mut.is_alloc  = true;
+            tag_mut.user_data = M::default();
+            tag_mut.update_checksum();
+        }
+        self.mman.protect_metadata();
+        self.mman.charge(unsafe { tag.as_ref() });
+        self.mman.update_level();
+
+        let tag_ref = unsafe { tag.as_ref() };
+        if let Some(ref mut observer) = self.mman.alloc_observer {
+            observer.on_alloc(tag_ref.addr_free_area(), tag_ref.free_area_size(), mem::align_of::<BoundaryTag<M>>());
+        }
+
+        debug_check!(self.mman.check_integrity().is_ok());
+        Ok(self.mman.payload_ptr(tag_ref))
+    }
+
+    /// Free the allocated block at the cursor; the cursor moves to the free block that takes it over after coalescing.
+    /// The block covering a hole of the region cannot be freed.
+    pub fn mark_free(&mut self) -> Result<(), CursorError>
+    {
+        let tag =
+            match self.tag {
+                None => return Err(CursorError::NoBlock),
+                Some(tag) => tag,
+            };
+        let tag_ref = unsafe { tag.as_ref() };
+        if !tag_ref.is_alloc || tag_ref.is_reserved {
+            return Err(CursorError::WrongState);
+        }
+
+        // The block may be merged into the one before it.
+        let addr = tag_ref.addr_free_area();
+        self.mman.free_bytes(addr);
+        let block = self.mman.block_of(addr);
+        self.tag  = block.map(|block| unsafe { BoundaryTag::<M>::new_from_addr(block.addr - mem::size_of::<BoundaryTag<M>>()) });
+
+        debug_check!(self.mman.check_integrity().is_ok());
+        Ok(())
+    }
+
+    fn free_tag(&self) -> Result<Unique<BoundaryTag<M>>, CursorError>
+    {
+        match self.tag {
+            None => Err(CursorError::NoBlock),
+            Some(tag) if unsafe { tag.as_ref() }.is_alloc => Err(CursorError::WrongState),
+            Some(tag) => Ok(tag),
+        }
+    }
+
+    /// Go to the head of the first chain from `region` on.
+    /// The regions joined to the chain of another region by `absorb` have a previous tag and are walked with that chain.
+    fn seek_chain_head(&mut self)
+    {
+        let regions = self.mman.regions();
+        while self.region < regions.len() {
+            let head = regions[self.region];
+            if unsafe { head.as_ref() }.is_first {
+                self.tag = Some(head);
+                return;
+            }
+            self.region += 1;
+        }
+
+        self.tag = None;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use core::mem;
+
+    use BoundaryTag;
+    use CursorError;
+    use HostRegion;
+    use MemoryManager;
+
+    #[test]
+    fn test_cursor()
+    {
+        let host     = HostRegion::new(4096, 16).unwrap();
+        let mut tags = [BoundaryTag::from_memory(host.addr(), host.size())];
+        let mut mman = MemoryManager::new(&mut tags);
+        let initial  = mman.stats();
+        let obj      = mman.alloc_bytes(64).unwrap();
+
+        {
+            let mut cursor = mman.cursor();
+            let head = cursor.block().unwrap();
+            assert!(!head.is_alloc);
+            assert_eq!(cursor.mark_free(), Err(CursorError::WrongState));
+            assert_eq!(cursor.split(head.size), Err(CursorError::TooSmall));
+
+            // Cut a 256 bytes block out of the head of the free block and allocate it.
+            assert_eq!(cursor.split(256), Ok(()));
+            assert_eq!(cursor.block().unwrap().size, 256);
+            let addr = cursor.mark_alloc().unwrap();
+            assert_eq!(addr, head.addr);
+            assert_eq!(cursor.mark_alloc(), Err(CursorError::WrongState));
+
+            assert!(cursor.move_next());
+            assert_eq!(cursor.block().unwrap().size, head.size - 256 - mem::size_of::<BoundaryTag>());
+            assert_eq!(cursor.merge_next(), Err(CursorError::WrongState));
+            assert!(cursor.move_next());
+            assert_eq!(cursor.block().unwrap().addr, obj);
+            assert!(!cursor.move_next());
+            assert_eq!(cursor.block(), None);
+        }
+        assert_eq!(mman.check_integrity(), Ok(()));
+        assert_eq!(mman.stats().used_blocks, 2);
+
+        {
+            // The freed head is merged with the free block after it.
+            let mut cursor = mman.cursor();
+            assert_eq!(cursor.mark_free(), Ok(()));
+            assert_eq!(cursor.block().unwrap().size, initial.free_bytes - 64 - mem::size_of::<BoundaryTag>());
+            assert!(cursor.move_next());
+            assert!(cursor.move_prev());
+            assert!(!cursor.move_prev());
+        }
+
+        mman.free_bytes(obj);
+        assert_eq!(mman.stats(), initial);
+    }
+}
@@ -0,0 +1,185 @@
+use core::cmp;
+
+use region::MemoryRegion;
+
+
+/// Type of the RAM usable by the operating system.
+pub const E820_USABLE: u32 = 1;
+pub const E820_RESERVED: u32 = 2;
+/// Type of the ACPI tables, usable once the kernel has parsed them.
+pub const E820_ACPI_RECLAIMABLE: u32 = 3;
+pub const E820_ACPI_NVS: u32 = 4;
+pub const E820_BAD_MEMORY: u32 = 5;
+
+
+/// Entry of the BIOS e820 memory map, laid out as `INT 15h, AX=E820h` writes it.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct E820Entry {
+    pub base: u64,
+    pub length: u64,
+    pub kind: u32,
+}
+
+
+/// Write the usable ranges of `entries` into `out` in ascending order and return how many were written.
+/// The adjacent and overlapping usable ranges are merged, and every range of another type is cut out of them,
+/// because the firmwares report a reserved range inside a usable one rather than splitting it.
+/// The ranges above the address space are clipped, and the ranges not fitting into `out` are dropped.
+pub fn e820_regions(entries: &[E820Entry], out: &mut [MemoryRegion]) -> usize
+{
+    let mut count = 0;
+    for entry in entries.iter().filter(|entry| entry.kind == E820_USABLE) {
+        if let Some((begin, end)) = range_of(entry) {
+            count = insert_range(out, count, begin, end);
+        }
+    }
+
+    for entry in entries.iter().filter(|entry| entry.kind != E820_USABLE) {
+        if let Some((begin, end)) = range_of(entry) {
+            count = remove_range(out, count, begin, end);
+        }
+    }
+
+    count
+}
+
+
+fn range_of(entry: &E820Entry) -> Option<(usize, usize)>
+{
+    // The fields are copied out because a packed field cannot be borrowed.
+    let (base, length) = (entry.base, entry.length);
+    let limit = usize::MAX as u64;
+    let begin = cmp::min(base, limit);
+    let end   = cmp::min(base.saturating_add(length), limit);
+    if begin < end { Some((begin as usize, end as usize)) } else { None }
+}
+
+
+fn bounds_of(region: &MemoryRegion) -> (usize, usize)
+{
+    (region.addr(), region.addr() + region.size())
+}
+
+
+/// Add the range to the sorted ranges in `out[..count]`, merging it with the ones it overlaps or touches.
+fn insert_range(out: &mut [MemoryRegion], count: usize, begin: usize, end: usize) -> usize
+{
+    let mut begin = begin;
+    let mut end   = end;
+    let mut kept  = 0;
+    for i in 0..count {
+        let (b, e) = bounds_of(&out[i]);
+        if e < begin || end < b {
+            out.swap(kept, i);
+            kept += 1;
+        } else {
+            begin = cmp::min(begin, b);
+            end   = cmp::max(end, e);
+        }
+    }
+
+    if kept == out.len() {
+        return kept;
+    }
+
+    out[kept] = MemoryRegion::new(begin, end - begin);
+    let mut i = kept;
+    while 0 < i && begin < out[i - 1].addr() {
+        out.swap(i - 1, i);
+        i -= 1;
+    }
+
+    kept + 1
+}
+
+
+/// Cut the range out of the sorted ranges in `out[..count]`.
+fn remove_range(out: &mut [MemoryRegion], count: usize, begin: usize, end: usize) -> usize
+{
+    let mut count = count;
+    let mut i     = 0;
+    while i < count {
+        let (b, e) = bounds_of(&out[i]);
+        if e <= begin || end <= b {
+            i += 1;
+        } else if b < begin && end < e {
+            // The range splits the region in two, the latter needing a slot of its own.
+            out[i] = MemoryRegion::new(b, begin - b);
+            i += 1;
+            if count < out.len() {
+                out[count] = MemoryRegion::new(end, e - end);
+                let mut j = count;
+                while i < j {
+                    out.swap(j - 1, j);
+                    j -= 1;
+                }
+                count += 1;
+                i     += 1;
+            }
+        } else if b < begin {
+            out[i] = MemoryRegion::new(b, begin - b);
+            i += 1;
+        } else if end < e {
+            out[i] = MemoryRegion::new(end, e - end);
+            i += 1;
+        } else {
+            for j in i..count - 1 {
+                out.swap(j, j + 1);
+            }
+            count -= 1;
+        }
+    }
+
+    count
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(base: u64, length: u64, kind: u32) -> E820Entry
+    {
+        E820Entry {
+            base,
+            length,
+            kind,
+        }
+    }
+
+    #[test]
+    fn test_e820_regions()
+    {
+        // Unsorted as some firmwares report them, with a reserved hole inside the last usable range.
+        let entries = [
+            entry(0x0010_0000, 0x0010_0000, E820_USABLE),
+            entry(0x0000_0000, 0x0009_FC00, E820_USABLE),
+            entry(0x0009_FC00, 0x0000_0400, E820_RESERVED),
+            entry(0x000F_0000, 0x0001_0000, E820_RESERVED),
+            entry(0x0800_0000, 0x0100_0000, E820_USABLE),
+            entry(0x0020_0000, 0x07DE_0000, E820_USABLE),
+            entry(0x07FE_0000, 0x0002_0000, E820_ACPI_RECLAIMABLE),
+            entry(0x0880_0000, 0x0000_1000, E820_ACPI_NVS),
+            entry(0x0900_0000, 0x0000_0000, E820_USABLE),
+        ];
+
+        let mut out = [
+            MemoryRegion::new(0, 0), MemoryRegion::new(0, 0), MemoryRegion::new(0, 0),
+            MemoryRegion::new(0, 0), MemoryRegion::new(0, 0),
+        ];
+        let count   = e820_regions(&entries, &mut out);
+        let regions = out[..count].iter().map(bounds_of).collect::<std::vec::Vec<_>>();
+        assert_eq!(regions, [
+            (0x0000_0000, 0x0009_FC00),
+            (0x0010_0000, 0x07FE_0000),
+            (0x0800_0000, 0x0880_0000),
+            (0x0880_1000, 0x0900_0000),
+        ]);
+
+        // The split part is dropped when `out` is full.
+        let mut out = [MemoryRegion::new(0, 0), MemoryRegion::new(0, 0), MemoryRegion::new(0, 0)];
+        assert_eq!(e820_regions(&entries, &mut out), 3);
+        assert_eq!(bounds_of(&out[2]), (0x0800_0000, 0x0880_0000));
+    }
+}
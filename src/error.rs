@@ -0,0 +1,85 @@
+/// Failure of an allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AllocError {
+    /// No free block can serve the request, and all the free blocks together could not either.
+    Exhausted { largest_free: usize, total_free: usize },
+    /// The free blocks together have room for the request but none of them alone is large enough,
+    /// so compacting or trimming the heap may let the request through.
+    Fragmented { largest_free: usize, total_free: usize },
+    /// The request would exceed the quota set by `MemoryManager::set_quota`.
+    QuotaExceeded,
+    /// The request would leave less free memory than the floor of its priority set by `MemoryManager::set_priority_floors`.
+    BelowFloor,
+    /// The region given to `MemoryManager::malloc_in_region` is not managed.
+    NoRegion,
+}
+
+
+/// Inconsistency found by `MemoryManager::check_integrity`, carrying the address of the offending tag.
+#[derive(Debug, PartialEq, Eq)]
+pub enum IntegrityError {
+    BrokenLink(usize),
+    UncoalescedNeighbors(usize),
+    AllocatedInFreeList(usize),
+    WrongSizeClass(usize),
+    UnorderedFreeList(usize),
+    /// The bitmap bit of the size class disagrees with its free list.
+    BitmapMismatch(usize),
+}
+
+
+/// Failure found by `MemoryManager::self_test`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SelfTestError {
+    /// The scratch region cannot hold the blocks of the test.
+    ScratchTooSmall,
+    /// An allocation of the given size failed although the scratch region had room for it.
+    AllocFailed(usize),
+    /// The block at the address is not aligned as requested or is outside of the scratch region.
+    BadBlock(usize),
+    /// The pattern written into the block at the address was overwritten.
+    Corrupted(usize),
+    Integrity(IntegrityError),
+    /// Freeing every block did not bring the heap back to its initial state.
+    NotRestored,
+}
+
+
+/// Reason a region cannot be managed, found by `BoundaryTag::try_from_memory` or `BoundaryTag::with_holes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionError {
+    /// The region has only the given bytes left once aligned, too few to hold a tag and the smallest block.
+    TooSmall(usize),
+    /// The hole of the given index given to `BoundaryTag::with_holes` is empty, out of order, or out of the region.
+    BadHole(usize),
+}
+
+
+/// Operation of a `BlockCursor` refused because it would break the chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorError {
+    /// The cursor is past the last block.
+    NoBlock,
+    /// The operation needs a free block but the block is allocated, or the other way around.
+    WrongState,
+    /// The block is too small to be split at the given size.
+    TooSmall,
+    /// The block has no next block to merge with.
+    NoNeighbor,
+}
+
+
+/// `MemoryManager::grow_in_place` cannot grow the block without moving it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CannotGrow;
+
+
+/// Reason `MemoryManager::try_remove_region` keeps a region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoveRegionError {
+    /// The region is not managed.
+    NoRegion,
+    /// The region still holds the given number of allocated blocks.
+    InUse(usize),
+}
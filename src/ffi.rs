@@ -0,0 +1,151 @@
+use core::ffi::c_void;
+use core::mem;
+use core::ptr;
+
+use super::LockedHeap;
+
+
+// The values of Linux and the BSDs.
+const EINVAL: i32 = 22;
+const ENOMEM: i32 = 12;
+
+
+pub unsafe fn malloc(heap: &LockedHeap, size: usize) -> *mut c_void
+{
+    // `malloc(0)` returns a unique pointer that can be passed to `free`.
+    match heap.lock().alloc_bytes(size) {
+        None => ptr::null_mut(),
+        Some(addr) => addr as *mut c_void,
+    }
+}
+
+pub unsafe fn free(heap: &LockedHeap, ptr: *mut c_void)
+{
+    if !ptr.is_null() {
+        heap.lock().free_bytes(ptr as usize);
+    }
+}
+
+pub unsafe fn calloc(heap: &LockedHeap, n: usize, size: usize) -> *mut c_void
+{
+    let size =
+        match n.checked_mul(size) {
+            None => return ptr::null_mut(),
+            Some(size) => size,
+        };
+
+    let ptr = malloc(heap, size);
+    if !ptr.is_null() {
+        ptr::write_bytes(ptr as *mut u8, 0, size);
+    }
+    ptr
+}
+
+pub unsafe fn realloc(heap: &LockedHeap, ptr: *mut c_void, size: usize) -> *mut c_void
+{
+    if ptr.is_null() {
+        return malloc(heap, size);
+    }
+
+    if size == 0 {
+        free(heap, ptr);
+        return ptr::null_mut();
+    }
+
+    match heap.lock().realloc_bytes(ptr as usize, size) {
+        None => ptr::null_mut(),
+        Some(addr) => addr as *mut c_void,
+    }
+}
+
+pub unsafe fn posix_memalign(heap: &LockedHeap, memptr: *mut *mut c_void, align: usize, size: usize) -> i32
+{
+    if !align.is_power_of_two() || !align.is_multiple_of(mem::size_of::<*mut c_void>()) {
+        return EINVAL;
+    }
+
+    match heap.lock().alloc_aligned_bytes(size, align) {
+        None => ENOMEM,
+        Some(addr) => {
+            *memptr = addr as *mut c_void;
+            0
+        },
+    }
+}
+
+
+/// Export `malloc`, `free`, `calloc`, `realloc` and `posix_memalign` allocating from the `LockedHeap` at `$heap`,
+/// so that the C code linked into the program shares the heap.
+///
+/// ```ignore
+/// static_heap!(HEAP, 1024 * 1024, global_allocator);
+/// export_c_allocator!(HEAP);
+/// ```
+#[macro_export]
+macro_rules! export_c_allocator {
+    ($heap:path) => {
+        #[no_mangle]
+        pub unsafe extern "C" fn malloc(size: usize) -> *mut ::core::ffi::c_void
+        {
+            $crate::ffi::malloc(&$heap, size)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn free(ptr: *mut ::core::ffi::c_void)
+        {
+            $crate::ffi::free(&$heap, ptr)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn calloc(n: usize, size: usize) -> *mut ::core::ffi::c_void
+        {
+            $crate::ffi::calloc(&$heap, n, size)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn realloc(ptr: *mut ::core::ffi::c_void, size: usize) -> *mut ::core::ffi::c_void
+        {
+            $crate::ffi::realloc(&$heap, ptr, size)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn posix_memalign(memptr: *mut *mut ::core::ffi::c_void, align: usize, size: usize) -> i32
+        {
+            $crate::ffi::posix_memalign(&$heap, memptr, align, size)
+        }
+    };
+}
+
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+
+    // The functions are tested without exporting them, which would replace the allocator of the test harness.
+    static_heap!(C_HEAP, 4096);
+
+    #[test]
+    fn test_c_functions()
+    {
+        unsafe {
+            let ptr = calloc(&C_HEAP, 16, 8) as *mut u64;
+            assert!((0..16).all(|i| *ptr.offset(i) == 0));
+            *ptr = 0xAF;
+
+            let ptr = realloc(&C_HEAP, ptr as *mut c_void, 1024) as *mut u64;
+            assert_eq!(*ptr, 0xAF);
+            assert!(realloc(&C_HEAP, ptr as *mut c_void, 0).is_null());
+
+            let mut aligned = ptr::null_mut();
+            assert_eq!(posix_memalign(&C_HEAP, &mut aligned, 3, 64), EINVAL);
+            assert_eq!(posix_memalign(&C_HEAP, &mut aligned, 256, 64), 0);
+            assert_eq!(aligned as usize % 256, 0);
+            free(&C_HEAP, aligned);
+            free(&C_HEAP, ptr::null_mut());
+
+            assert!(malloc(&C_HEAP, 8192).is_null());
+        }
+
+        assert_eq!(C_HEAP.lock().check_integrity(), Ok(()));
+    }
+}
@@ -0,0 +1,190 @@
+use core::mem;
+
+use super::MemoryManager;
+use super::round_up;
+
+
+/// Bump allocator over a single block borrowed from a `MemoryManager`.
+/// Everything allocated since `begin_frame` is discarded at once by `end_frame`,
+/// which suits the per-frame scratch data of game and graphics loops.
+pub struct FrameArena<'m, 'a: 'm> {
+    mman: &'m mut MemoryManager<'a>,
+    addr: usize,
+    size: usize,
+    top: usize,
+    frame_top: usize,
+}
+
+
+/// Checkpoint of a `FrameArena` taken by `mark`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mark {
+    top: usize,
+}
+
+
+impl<'m, 'a: 'm> FrameArena<'m, 'a> {
+    /// Borrow a block of `size` bytes from `mman`, or return `None` if it cannot be allocated.
+    pub fn new(mman: &'m mut MemoryManager<'a>, size: usize) -> Option<FrameArena<'m, 'a>>
+    {
+        let addr = mman.alloc_bytes(size)?;
+
+        Some(FrameArena {
+            mman,
+            addr,
+            size,
+            top: 0,
+            frame_top: 0,
+        })
+    }
+
+    pub fn capacity(&self) -> usize
+    {
+        self.size
+    }
+
+    /// The number of bytes in use, including the padding for alignment.
+    pub fn used(&self) -> usize
+    {
+        self.top
+    }
+
+    pub fn begin_frame(&mut self)
+    {
+        self.frame_top = self.top;
+    }
+
+    /// Discard everything allocated since the last `begin_frame`.
+    pub fn end_frame(&mut self)
+    {
+        self.top = self.frame_top;
+    }
+
+    /// Take a checkpoint to discard the allocations made after it by `rollback`.
+    pub fn mark(&self) -> Mark
+    {
+        Mark {
+            top: self.top,
+        }
+    }
+
+    /// Discard everything allocated since `mark` was taken.
+    /// The marks taken after `mark` become invalid.
+    pub fn rollback(&mut self, mark: Mark)
+    {
+        debug_check!(mark.top <= self.top);
+        self.top = mark.top;
+    }
+
+    pub fn alloc<'b, T>(&mut self) -> Option<&'b mut T>
+    {
+        let begin = round_up(self.addr + self.top, mem::align_of::<T>());
+        let end   = begin + mem::size_of::<T>();
+        if self.addr + self.size < end {
+            return None;
+        }
+
+        self.top = end - self.addr;
+        Some(unsafe { &mut *(begin as *mut T) })
+    }
+}
+
+
+impl<'m, 'a: 'm> Drop for FrameArena<'m, 'a> {
+    fn drop(&mut self)
+    {
+        self.mman.free_bytes(self.addr);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use core::mem;
+
+    use super::*;
+    use BoundaryTag;
+
+    use HostRegion;
+
+    fn allocate_memory() -> (usize, usize)
+    {
+        const SIZE: usize = 4096;
+        let addr = HostRegion::new(SIZE, 16).unwrap().leak().addr();
+
+        (addr, SIZE)
+    }
+
+    #[test]
+    fn test_frame()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags  = [BoundaryTag::from_memory(addr, size)];
+        let mut mman  = MemoryManager::new(&mut tags);
+        let mut arena = FrameArena::new(&mut mman, 1024).unwrap();
+
+        let persistent = arena.alloc::<u64>().unwrap();
+        *persistent    = 0xAF;
+        let used       = arena.used();
+
+        for _ in 0..4 {
+            arena.begin_frame();
+            let scratch = arena.alloc::<[u8; 300]>().unwrap();
+            scratch[0]  = 1;
+            assert!(used < arena.used());
+            arena.end_frame();
+            assert_eq!(arena.used(), used);
+        }
+
+        assert_eq!(*persistent, 0xAF);
+        assert!(arena.alloc::<[u8; 2048]>().is_none());
+    }
+
+    #[test]
+    fn test_rollback()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags  = [BoundaryTag::from_memory(addr, size)];
+        let mut mman  = MemoryManager::new(&mut tags);
+        let mut arena = FrameArena::new(&mut mman, 1024).unwrap();
+
+        let _      = arena.alloc::<[u8; 100]>().unwrap();
+        let mark1  = arena.mark();
+        let _      = arena.alloc::<[u8; 100]>().unwrap();
+        let mark2  = arena.mark();
+        let first  = arena.alloc::<[u8; 100]>().unwrap() as *const _ as usize;
+
+        arena.rollback(mark2);
+        let second = arena.alloc::<[u8; 100]>().unwrap() as *const _ as usize;
+        assert_eq!(first, second);
+
+        arena.rollback(mark1);
+        assert_eq!(arena.mark(), mark1);
+    }
+
+    #[test]
+    fn test_alignment()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags  = [BoundaryTag::from_memory(addr, size)];
+        let mut mman  = MemoryManager::new(&mut tags);
+        let mut arena = FrameArena::new(&mut mman, 256).unwrap();
+
+        let _ = arena.alloc::<u8>().unwrap();
+        let x = arena.alloc::<u64>().unwrap();
+        assert_eq!((x as *const u64) as usize % mem::align_of::<u64>(), 0);
+    }
+
+    #[test]
+    fn test_drop_returns_block()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        {
+            let _ = FrameArena::new(&mut mman, 1024).unwrap();
+        }
+
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+    }
+}
@@ -0,0 +1,124 @@
+use core::mem::MaybeUninit;
+
+use super::MemoryManager;
+
+
+const SCRATCH_SIZE: usize = 64 * 1024;
+const NUM_SLOTS: usize = 32;
+
+
+/// Interpret `data` as a sequence of allocations, frees and reallocations on a scratch heap and panic at the first inconsistency,
+/// so that a fuzz target is only `fuzz_target!(|data: &[u8]| fuzz_ops(data));`.
+///
+/// Every operation takes one byte for the kind and the slot, followed by the operands of the kind:
+/// `alloc` takes two bytes of size and one of align, `free` none and `realloc` two bytes of size.
+pub fn fuzz_ops(data: &[u8])
+{
+    let mut scratch: [MaybeUninit<u8>; SCRATCH_SIZE] = unsafe { MaybeUninit::uninit().assume_init() };
+    let mut mman: MemoryManager = MemoryManager::from_buffer(&mut scratch).unwrap();
+    let initial = mman.stats();
+
+    // The address and the size of the block in each slot.
+    let mut slots: [Option<(usize, usize)>; NUM_SLOTS] = [None; NUM_SLOTS];
+
+    let mut bytes = data.iter().cloned();
+    while let Some(op) = bytes.next() {
+        let slot = (op >> 2) as usize % NUM_SLOTS;
+        match op & 0b11 {
+            0 => {
+                let size  = read_size(&mut bytes);
+                let align = 1 << (bytes.next().unwrap_or(0) % 12);
+                if let Some((addr, size)) = slots[slot].take() {
+                    verify(addr, size);
+                    mman.free_bytes(addr);
+                }
+                if let Some(addr) = mman.alloc_aligned_bytes(size, align) {
+                    assert_eq!(addr % align, 0);
+                    fill(addr, size);
+                    slots[slot] = Some((addr, size));
+                }
+            },
+            1 => {
+                if let Some((addr, size)) = slots[slot].take() {
+                    verify(addr, size);
+                    mman.free_bytes(addr);
+                }
+            },
+            2 => {
+                let new_size = read_size(&mut bytes);
+                if let Some((addr, size)) = slots[slot] {
+                    verify(addr, size);
+                    if let Some(new_addr) = mman.realloc_bytes(addr, new_size) {
+                        verify_pattern(new_addr, size.min(new_size), pattern(addr));
+                        fill(new_addr, new_size);
+                        slots[slot] = Some((new_addr, new_size));
+                    }
+                }
+            },
+            _ => {
+                assert_eq!(mman.check_integrity(), Ok(()));
+            },
+        }
+    }
+
+    for slot in slots.iter_mut() {
+        if let Some((addr, size)) = slot.take() {
+            verify(addr, size);
+            mman.free_bytes(addr);
+        }
+    }
+
+    assert_eq!(mman.check_integrity(), Ok(()));
+    assert_eq!(mman.stats(), initial);
+}
+
+
+fn read_size<I>(bytes: &mut I) -> usize
+    where I: Iterator<Item = u8>
+{
+    let low  = bytes.next().unwrap_or(0) as usize;
+    let high = bytes.next().unwrap_or(0) as usize;
+    (high << 8 | low) % (SCRATCH_SIZE / 2)
+}
+
+
+/// Each block is filled with a byte derived from its address, so a block overlapping another is caught by `verify`.
+fn pattern(addr: usize) -> u8
+{
+    (addr >> 3) as u8
+}
+
+
+fn fill(addr: usize, size: usize)
+{
+    unsafe { (addr as *mut u8).write_bytes(pattern(addr), size) };
+}
+
+
+fn verify(addr: usize, size: usize)
+{
+    verify_pattern(addr, size, pattern(addr));
+}
+
+
+fn verify_pattern(addr: usize, size: usize, expected: u8)
+{
+    assert!((0..size).all(|i| unsafe { *((addr + i) as *const u8) } == expected), "block at 0x{:x} was overwritten", addr);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzz_ops()
+    {
+        fuzz_ops(&[]);
+        fuzz_ops(&[0, 0x10, 0x00, 3, 4, 0x20, 0, 0, 2, 0x00, 0x04, 3, 1, 5]);
+
+        // Truncated operands are read as zeros.
+        fuzz_ops(&[0]);
+        fuzz_ops(&[0xFF; 64]);
+    }
+}
@@ -0,0 +1,222 @@
+use core::cmp;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::ptr::NonNull;
+use core::slice;
+
+use super::MemoryManager;
+
+
+/// Growable array in a block of a `MemoryManager`, like a `Vec` for the users without `alloc`.
+/// The buffer grows in place into the free block after it when it can, and its capacity includes the slack of the heap,
+/// so a heap with `set_slack` moves it rarely.
+pub struct GrowableBuffer<'m, 'a: 'm, T> {
+    mman: &'m mut MemoryManager<'a>,
+    ptr: NonNull<T>,
+    len: usize,
+    cap: usize,
+}
+
+
+impl<'m, 'a: 'm, T> GrowableBuffer<'m, 'a, T> {
+    /// Create an empty buffer, which allocates no block until the first element is pushed.
+    pub fn new(mman: &'m mut MemoryManager<'a>) -> GrowableBuffer<'m, 'a, T>
+    {
+        GrowableBuffer {
+            mman,
+            ptr: NonNull::dangling(),
+            len: 0,
+            // The zero-sized elements never need a block.
+            cap: if mem::size_of::<T>() == 0 { usize::MAX } else { 0 },
+        }
+    }
+
+    /// Create a buffer holding `capacity` elements at least, or return `None` if the block cannot be allocated.
+    pub fn with_capacity(mman: &'m mut MemoryManager<'a>, capacity: usize) -> Option<GrowableBuffer<'m, 'a, T>>
+    {
+        let mut buffer = GrowableBuffer::new(mman);
+        if buffer.reserve(capacity) { Some(buffer) } else { None }
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize
+    {
+        self.cap
+    }
+
+    /// Make room for `additional` more elements, growing in place first and moving the elements otherwise.
+    /// Return `false` if the heap cannot hold them, leaving the buffer as it is.
+    pub fn reserve(&mut self, additional: usize) -> bool
+    {
+        let required =
+            match self.len.checked_add(additional) {
+                None => return false,
+                Some(required) => required,
+            };
+        if required <= self.cap {
+            return true;
+        }
+
+        let new_cap = cmp::max(required, cmp::max(self.cap * 2, 4));
+        let new_size =
+            match new_cap.checked_mul(mem::size_of::<T>()) {
+                None => return false,
+                Some(new_size) => new_size,
+            };
+
+        if self.cap != 0 {
+            if let Ok(usable) = self.mman.grow_in_place(self.ptr.as_ptr() as usize, new_size) {
+                self.cap = usable / mem::size_of::<T>();
+                return true;
+            }
+        }
+
+        // `realloc_bytes` would move the block to the heap alignment only, which is not enough for every `T`.
+        let addr =
+            match self.mman.alloc_aligned_bytes(new_size, mem::align_of::<T>()) {
+                None => return false,
+                Some(addr) => addr,
+            };
+        unsafe { ptr::copy_nonoverlapping(self.ptr.as_ptr(), addr as *mut T, self.len) };
+        if self.cap != 0 {
+            self.mman.free_bytes(self.ptr.as_ptr() as usize);
+        }
+
+        self.ptr = unsafe { NonNull::new_unchecked(addr as *mut T) };
+        self.cap = self.mman.usable_size(addr) / mem::size_of::<T>();
+        true
+    }
+
+    /// Append `value`, or give it back if the buffer cannot grow.
+    pub fn push(&mut self, value: T) -> Result<(), T>
+    {
+        if !self.reserve(1) {
+            return Err(value);
+        }
+
+        unsafe { ptr::write(self.ptr.as_ptr().add(self.len), value) };
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<T>
+    {
+        if self.len == 0 {
+            return None;
+        }
+
+        self.len -= 1;
+        Some(unsafe { ptr::read(self.ptr.as_ptr().add(self.len)) })
+    }
+
+    /// Give the bytes beyond the elements back to the heap, keeping the block in place.
+    pub fn shrink_to_fit(&mut self)
+    {
+        if mem::size_of::<T>() == 0 || self.cap == 0 || self.len == self.cap {
+            return;
+        }
+
+        if self.len == 0 {
+            self.mman.free_bytes(self.ptr.as_ptr() as usize);
+            self.ptr = NonNull::dangling();
+            self.cap = 0;
+            return;
+        }
+
+        let usable = self.mman.shrink_in_place(self.ptr.as_ptr() as usize, self.len * mem::size_of::<T>());
+        self.cap = usable / mem::size_of::<T>();
+    }
+
+    /// Drop every element, keeping the block.
+    pub fn clear(&mut self)
+    {
+        while let Some(value) = self.pop() {
+            drop(value);
+        }
+    }
+}
+
+
+impl<'m, 'a: 'm, T> Deref for GrowableBuffer<'m, 'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T]
+    {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+
+impl<'m, 'a: 'm, T> DerefMut for GrowableBuffer<'m, 'a, T> {
+    fn deref_mut(&mut self) -> &mut [T]
+    {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+
+impl<'m, 'a: 'm, T> Drop for GrowableBuffer<'m, 'a, T> {
+    fn drop(&mut self)
+    {
+        self.clear();
+        if mem::size_of::<T>() != 0 && self.cap != 0 {
+            self.mman.free_bytes(self.ptr.as_ptr() as usize);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use BoundaryTag;
+
+    use HostRegion;
+
+    #[test]
+    fn test_growable_buffer()
+    {
+        let host = HostRegion::new(16 * 1024, 16).unwrap();
+        let mut tags = [BoundaryTag::from_memory(host.addr(), host.size())];
+        let mut mman = MemoryManager::new(&mut tags);
+        let initial  = mman.stats();
+        {
+            let mut buffer = GrowableBuffer::new(&mut mman);
+            for i in 0..100u64 {
+                assert_eq!(buffer.push(i), Ok(()));
+            }
+            assert_eq!(buffer.len(), 100);
+            assert!(100 <= buffer.capacity());
+            assert_eq!(buffer.iter().sum::<u64>(), 4950);
+
+            // The blocks are carved from the tail of the free block, so nothing free follows the buffer and it moves.
+            assert!(buffer.reserve(200));
+            assert!(300 <= buffer.capacity());
+            assert_eq!(buffer.iter().sum::<u64>(), 4950);
+
+            buffer.shrink_to_fit();
+            assert!(100 <= buffer.capacity() && buffer.capacity() < 300);
+
+            // The tail given back follows the buffer now, so it grows in place.
+            let addr = buffer.as_ptr() as usize;
+            assert!(buffer.reserve(100));
+            assert_eq!(buffer.as_ptr() as usize, addr);
+            assert!(!buffer.reserve(4096));
+            buffer[99] = 0xAF;
+            assert_eq!(buffer.pop(), Some(0xAF));
+        }
+
+        assert_eq!(mman.stats(), initial);
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+}
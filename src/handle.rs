@@ -0,0 +1,155 @@
+use super::MemoryManager;
+
+
+/// Stable reference to an allocation made through a `HandleTable`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handle {
+    index: usize,
+}
+
+
+/// Allocations resolved through a relocation table instead of by raw pointers.
+/// The callers only keep the handles, so the blocks can be moved by updating a table entry.
+pub struct HandleTable<'h, 'a: 'h> {
+    mman: &'h mut MemoryManager<'a>,
+    entries: &'h mut [Option<usize>],
+}
+
+
+impl<'h, 'a: 'h> HandleTable<'h, 'a> {
+    /// Use `entries` as the relocation table; the number of the entries limits the number of the live handles.
+    pub fn new(mman: &'h mut MemoryManager<'a>, entries: &'h mut [Option<usize>]) -> HandleTable<'h, 'a>
+    {
+        for entry in entries.iter_mut() {
+            *entry = None;
+        }
+
+        HandleTable {
+            mman,
+            entries,
+        }
+    }
+
+    pub fn alloc(&mut self, size: usize) -> Option<Handle>
+    {
+        let index = self.entries.iter().position(|entry| entry.is_none())?;
+
+        self.mman.alloc_movable_bytes(size).map(|addr| {
+            self.entries[index] = Some(addr);
+            Handle {
+                index,
+            }
+        })
+    }
+
+    pub fn free(&mut self, handle: Handle)
+    {
+        if let Some(addr) = self.entries[handle.index].take() {
+            self.mman.free_bytes(addr);
+        }
+    }
+
+    /// The current address of the allocation; it is valid only until the blocks are moved.
+    pub fn resolve(&self, handle: Handle) -> Option<usize>
+    {
+        self.entries[handle.index]
+    }
+
+    pub fn get<'b, T>(&self, handle: Handle) -> Option<&'b mut T>
+    {
+        self.resolve(handle).map(|addr| unsafe { &mut *(addr as *mut T) })
+    }
+
+    /// Compact the heap and update the entries of the moved blocks.
+    /// The addresses resolved before become invalid.
+    pub fn compact(&mut self) -> usize
+    {
+        let entries = &mut self.entries;
+        self.mman.compact(|old_addr, new_addr, _| HandleTable::relocate(entries, old_addr, new_addr))
+    }
+
+    /// Update the entry pointing to the block moved from `old_addr` to `new_addr`.
+    fn relocate(entries: &mut [Option<usize>], old_addr: usize, new_addr: usize)
+    {
+        if let Some(entry) = entries.iter_mut().find(|entry| **entry == Some(old_addr)) {
+            *entry = Some(new_addr);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use BoundaryTag;
+
+    use HostRegion;
+
+    fn allocate_memory() -> (usize, usize)
+    {
+        const SIZE: usize = 4096;
+        let addr = HostRegion::new(SIZE, 16).unwrap().leak().addr();
+
+        (addr, SIZE)
+    }
+
+    #[test]
+    fn test_alloc_and_resolve()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags    = [BoundaryTag::from_memory(addr, size)];
+        let mut entries = [None; 2];
+        let mut mman    = MemoryManager::new(&mut tags);
+        let mut table   = HandleTable::new(&mut mman, &mut entries);
+
+        let h1 = table.alloc(64).unwrap();
+        let h2 = table.alloc(64).unwrap();
+        assert!(table.alloc(64).is_none());
+        assert!(h1 != h2);
+
+        *table.get::<u64>(h1).unwrap() = 0xAF;
+        assert_eq!(*table.get::<u64>(h1).unwrap(), 0xAF);
+
+        table.free(h1);
+        assert!(table.resolve(h1).is_none());
+        assert!(table.alloc(64).is_some());
+    }
+
+    #[test]
+    fn test_relocate()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags    = [BoundaryTag::from_memory(addr, size)];
+        let mut entries = [None; 1];
+        let mut mman    = MemoryManager::new(&mut tags);
+        let mut table   = HandleTable::new(&mut mman, &mut entries);
+
+        let h        = table.alloc(64).unwrap();
+        let old_addr = table.resolve(h).unwrap();
+        HandleTable::relocate(table.entries, old_addr, old_addr - 64);
+        assert_eq!(table.resolve(h), Some(old_addr - 64));
+    }
+
+    #[test]
+    fn test_compact()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags    = [BoundaryTag::from_memory(addr, size)];
+        let mut entries = [None; 4];
+        let mut mman    = MemoryManager::new(&mut tags);
+        let mut table   = HandleTable::new(&mut mman, &mut entries);
+
+        let h1 = table.alloc(64).unwrap();
+        let h2 = table.alloc(64).unwrap();
+        let h3 = table.alloc(64).unwrap();
+        *table.get::<u64>(h1).unwrap() = 1;
+        *table.get::<u64>(h3).unwrap() = 3;
+        let old_addr = table.resolve(h3).unwrap();
+
+        table.free(h2);
+        assert_eq!(table.compact(), 2);
+        assert!(table.resolve(h3).unwrap() < old_addr);
+        assert_eq!(*table.get::<u64>(h1).unwrap(), 1);
+        assert_eq!(*table.get::<u64>(h3).unwrap(), 3);
+    }
+}
@@ -0,0 +1,96 @@
+use core::mem;
+use std::alloc::Layout;
+
+use region::MemoryRegion;
+
+
+/// Buffer allocated from the host allocator to run a heap on it, such as in the unit tests of the crates using this allocator.
+/// The buffer is freed when this is dropped, so the heap must be dropped first.
+pub struct HostRegion {
+    addr: usize,
+    layout: Layout,
+}
+
+
+impl HostRegion {
+    /// Allocate `size` bytes aligned to `align`, or return `None` if the host cannot.
+    pub fn new(size: usize, align: usize) -> Option<HostRegion>
+    {
+        let layout =
+            match Layout::from_size_align(size, align) {
+                Err(_) => return None,
+                Ok(layout) => layout,
+            };
+
+        if size == 0 {
+            return None;
+        }
+
+        let ptr = unsafe { std::alloc::alloc(layout) };
+        if ptr.is_null() {
+            None
+        } else {
+            Some(HostRegion {
+                addr: ptr as usize,
+                layout,
+            })
+        }
+    }
+
+    pub fn addr(&self) -> usize
+    {
+        self.addr
+    }
+
+    pub fn size(&self) -> usize
+    {
+        self.layout.size()
+    }
+
+    pub fn region(&self) -> MemoryRegion
+    {
+        MemoryRegion::new(self.addr, self.layout.size())
+    }
+
+    /// Give up the buffer without freeing it, for the heaps that live until the end of the program.
+    pub fn leak(self) -> MemoryRegion
+    {
+        let region = self.region();
+        mem::forget(self);
+        region
+    }
+}
+
+
+impl Drop for HostRegion {
+    fn drop(&mut self)
+    {
+        unsafe { std::alloc::dealloc(self.addr as *mut u8, self.layout) };
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use BoundaryTag;
+    use MemoryManager;
+
+    #[test]
+    fn test_host_region()
+    {
+        assert!(HostRegion::new(0, 16).is_none());
+        assert!(HostRegion::new(4096, 3).is_none());
+
+        let host = HostRegion::new(4096, 64).unwrap();
+        assert_eq!(host.addr() % 64, 0);
+        assert_eq!(host.size(), 4096);
+
+        let mut tags = [BoundaryTag::from_memory(host.addr(), host.size())];
+        let mut mman = MemoryManager::new(&mut tags);
+        let obj      = mman.alloc_bytes(1024).unwrap();
+        assert!(host.addr() < obj && obj + 1024 <= host.addr() + host.size());
+        mman.free_bytes(obj);
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+}
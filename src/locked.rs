@@ -0,0 +1,424 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::ptr::NonNull;
+#[cfg(not(loom))]
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(loom)]
+use loom::sync::atomic::{AtomicBool, Ordering};
+
+use super::MemoryManager;
+use super::Allocator;
+
+
+/// `MemoryManager` behind a spin lock, usable as the `#[global_allocator]`.
+/// A heap made by `with_buffer` takes its buffer at the first lock, so it can be a `static` without any init call.
+pub struct LockedHeap {
+    is_locked: AtomicBool,
+    buffer: *mut u8,
+    buffer_size: usize,
+    #[cfg(not(loom))]
+    mman: UnsafeCell<MemoryManager<'static>>,
+    // Loom checks that every access to the manager happens under the lock.
+    #[cfg(loom)]
+    mman: loom::cell::UnsafeCell<MemoryManager<'static>>,
+}
+
+
+// The manager is only reached through the lock.
+unsafe impl Sync for LockedHeap {}
+
+
+impl LockedHeap {
+    /// Create a heap without memory; `init` has to be called before the first allocation.
+    #[cfg(not(loom))]
+    pub const fn empty() -> LockedHeap
+    {
+        LockedHeap::with_buffer(ptr::null_mut(), 0)
+    }
+
+    /// Create a heap managing the `size` bytes at `buffer`, which must not be used by anything else.
+    #[cfg(not(loom))]
+    pub const fn with_buffer(buffer: *mut u8, size: usize) -> LockedHeap
+    {
+        LockedHeap {
+            is_locked: AtomicBool::new(false),
+            buffer,
+            buffer_size: size,
+            mman: UnsafeCell::new(MemoryManager::empty()),
+        }
+    }
+
+    // The loom types cannot be made in a const context.
+    #[cfg(loom)]
+    pub fn empty() -> LockedHeap
+    {
+        LockedHeap::with_buffer(ptr::null_mut(), 0)
+    }
+
+    #[cfg(loom)]
+    pub fn with_buffer(buffer: *mut u8, size: usize) -> LockedHeap
+    {
+        LockedHeap {
+            is_locked: AtomicBool::new(false),
+            buffer,
+            buffer_size: size,
+            mman: loom::cell::UnsafeCell::new(MemoryManager::empty()),
+        }
+    }
+
+    /// Give the memory at `addr` of `size` bytes to the heap.
+    ///
+    /// # Safety
+    /// The same as `MemoryManager::init`.
+    pub unsafe fn init(&self, addr: usize, size: usize) -> bool
+    {
+        self.lock().init(addr, size)
+    }
+
+    /// A handle to the heap for a subsystem to keep.
+    pub fn handle(&self) -> HeapRef<'_>
+    {
+        HeapRef {
+            heap: self,
+        }
+    }
+
+    pub fn lock(&self) -> LockedHeapGuard<'_>
+    {
+        while self.is_locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            // Loom has to run the holder of the lock to get out of the loop.
+            #[cfg(loom)]
+            loom::thread::yield_now();
+        }
+
+        self.guard()
+    }
+
+    /// Lock the heap unless it is locked already, such as by the code that was interrupted.
+    pub fn try_lock(&self) -> Option<LockedHeapGuard<'_>>
+    {
+        if self.is_locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            return None;
+        }
+
+        Some(self.guard())
+    }
+
+    /// Wrap the manager after the lock is taken, giving it the buffer at the first time.
+    fn guard(&self) -> LockedHeapGuard<'_>
+    {
+        let mman = unsafe { &mut *self.mman_ptr() };
+        if mman.num_regions == 0 && !self.buffer.is_null() {
+            unsafe { mman.init(self.buffer as usize, self.buffer_size) };
+        }
+
+        LockedHeapGuard {
+            heap: self,
+        }
+    }
+
+    #[cfg(not(loom))]
+    fn mman_ptr(&self) -> *mut MemoryManager<'static>
+    {
+        self.mman.get()
+    }
+
+    #[cfg(loom)]
+    fn mman_ptr(&self) -> *mut MemoryManager<'static>
+    {
+        self.mman.with_mut(|ptr| ptr)
+    }
+}
+
+
+unsafe impl GlobalAlloc for LockedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8
+    {
+        match self.lock().alloc_aligned_bytes(layout.size(), layout.align()) {
+            None => ptr::null_mut(),
+            Some(addr) => addr as *mut u8,
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _: Layout)
+    {
+        self.lock().free_bytes(ptr as usize);
+    }
+}
+
+
+// Lets the collections of `allocator_api2` use the heap on a stable toolchain, such as `Vec::new_in(&HEAP)`.
+#[cfg(feature = "allocator-api2")]
+unsafe impl allocator_api2::alloc::Allocator for LockedHeap {
+    fn allocate(&self, layout: allocator_api2::alloc::Layout) -> Result<NonNull<[u8]>, allocator_api2::alloc::AllocError>
+    {
+        // No block is allocated for a zero-sized layout; the aligned dangling pointer stands for it.
+        let addr =
+            if layout.size() == 0 {
+                layout.align()
+            } else {
+                match self.lock().alloc_aligned_bytes(layout.size(), layout.align()) {
+                    None => return Err(allocator_api2::alloc::AllocError),
+                    Some(addr) => addr,
+                }
+            };
+
+        Ok(unsafe { NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(addr as *mut u8, layout.size())) })
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: allocator_api2::alloc::Layout)
+    {
+        if layout.size() != 0 {
+            self.lock().free_bytes(ptr.as_ptr() as usize);
+        }
+    }
+}
+
+
+/// Exclusive access to the manager of a `LockedHeap`, released when dropped.
+pub struct LockedHeapGuard<'h> {
+    heap: &'h LockedHeap,
+}
+
+
+impl<'h> Deref for LockedHeapGuard<'h> {
+    type Target = MemoryManager<'static>;
+
+    fn deref(&self) -> &MemoryManager<'static>
+    {
+        unsafe { &*self.heap.mman_ptr() }
+    }
+}
+
+
+impl<'h> DerefMut for LockedHeapGuard<'h> {
+    fn deref_mut(&mut self) -> &mut MemoryManager<'static>
+    {
+        unsafe { &mut *self.heap.mman_ptr() }
+    }
+}
+
+
+impl<'h> Drop for LockedHeapGuard<'h> {
+    fn drop(&mut self)
+    {
+        self.heap.is_locked.store(false, Ordering::Release);
+    }
+}
+
+
+/// Shared handle to a `LockedHeap`, which many subsystems can keep and allocate through at once.
+/// Every call takes the lock only for its duration, so no `&mut MemoryManager` outlives it and two handles never alias one.
+/// It is `Copy`, and a handle to a `static` heap is `HeapRef<'static>`.
+pub struct HeapRef<'h> {
+    heap: &'h LockedHeap,
+}
+
+
+impl<'h> HeapRef<'h> {
+    /// The heap behind the handle, such as to lock it for several operations in a row.
+    pub fn heap(&self) -> &'h LockedHeap
+    {
+        self.heap
+    }
+}
+
+
+impl<'h> Clone for HeapRef<'h> {
+    fn clone(&self) -> HeapRef<'h>
+    {
+        *self
+    }
+}
+
+
+impl<'h> Copy for HeapRef<'h> {}
+
+
+impl<'h> Allocator for HeapRef<'h> {
+    fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>>
+    {
+        self.heap.lock().allocate(layout)
+    }
+
+    fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout)
+    {
+        self.heap.lock().deallocate(ptr, layout)
+    }
+}
+
+
+/// Buffer for `static_heap!`, aligned for the tags.
+#[repr(C, align(16))]
+pub struct HeapBuffer<T>(pub UnsafeCell<T>);
+
+
+// The buffer is only reached through its `LockedHeap`.
+unsafe impl<T> Sync for HeapBuffer<T> {}
+
+
+/// Declare a `static` `LockedHeap` named `$name` over a static buffer of `$size` bytes.
+/// Append `global_allocator` to make it the global allocator as well.
+///
+/// ```ignore
+/// static_heap!(HEAP, 64 * 1024, global_allocator);
+/// ```
+#[macro_export]
+macro_rules! static_heap {
+    ($name:ident, $size:expr) => {
+        static $name: $crate::LockedHeap = {
+            static BUFFER: $crate::HeapBuffer<[u8; $size]> = $crate::HeapBuffer(::core::cell::UnsafeCell::new([0; $size]));
+            $crate::LockedHeap::with_buffer(&BUFFER as *const _ as *mut u8, $size)
+        };
+    };
+    ($name:ident, $size:expr, global_allocator) => {
+        #[global_allocator]
+        static $name: $crate::LockedHeap = {
+            static BUFFER: $crate::HeapBuffer<[u8; $size]> = $crate::HeapBuffer(::core::cell::UnsafeCell::new([0; $size]));
+            $crate::LockedHeap::with_buffer(&BUFFER as *const _ as *mut u8, $size)
+        };
+    };
+}
+
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use core::alloc::{GlobalAlloc, Layout};
+
+    use super::*;
+    use Allocator;
+
+    static_heap!(TEST_HEAP, 4096);
+
+    #[test]
+    fn test_static_heap()
+    {
+        unsafe {
+            let layout = Layout::from_size_align(256, 64).unwrap();
+            let ptr    = TEST_HEAP.alloc(layout);
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % 64, 0);
+            assert!(TEST_HEAP.lock().contains(ptr as usize));
+
+            TEST_HEAP.dealloc(ptr, layout);
+            assert!(TEST_HEAP.alloc(Layout::from_size_align(8192, 8).unwrap()).is_null());
+        }
+
+        assert_eq!(TEST_HEAP.lock().check_integrity(), Ok(()));
+    }
+
+    #[test]
+    #[cfg(feature = "allocator-api2")]
+    fn test_allocator_api2()
+    {
+        static_heap!(API2_HEAP, 4096);
+
+        let mut v = allocator_api2::vec::Vec::new_in(&API2_HEAP);
+        for i in 0..64u32 {
+            v.push(i);
+        }
+        assert_eq!(v.iter().sum::<u32>(), 2016);
+        assert!(API2_HEAP.lock().contains(v.as_ptr() as usize));
+
+        drop(v);
+        assert_eq!(API2_HEAP.lock().check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_heap_ref()
+    {
+        static_heap!(SHARED_HEAP, 4096);
+
+        let mut net = SHARED_HEAP.handle();
+        let mut fs  = net;
+        let layout  = Layout::from_size_align(64, 16).unwrap();
+        let packet  = net.allocate(layout).unwrap();
+        let inode   = fs.allocate(layout).unwrap();
+        assert_ne!(packet, inode);
+        assert_eq!(SHARED_HEAP.lock().stats().used_blocks, 2);
+
+        // A block can be freed through another handle than the one which allocated it.
+        fs.deallocate(packet, layout);
+        net.deallocate(inode, layout);
+        assert_eq!(SHARED_HEAP.lock().stats().used_blocks, 0);
+        assert_eq!(SHARED_HEAP.lock().check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_init()
+    {
+        let heap = LockedHeap::empty();
+        assert!(heap.lock().alloc_bytes(8).is_none());
+
+        let mut buffer = [0u64; 128];
+        assert!(unsafe { heap.init(buffer.as_mut_ptr() as usize, 1024) });
+        assert!(heap.lock().alloc_bytes(8).is_some());
+    }
+}
+
+
+// Run with `RUSTFLAGS="--cfg loom" cargo test --release loom`.
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use std::boxed::Box;
+
+    use loom::thread;
+
+    use super::*;
+    use HostRegion;
+
+    /// Make a heap which the threads of a loom model can share; the heap is leaked, the buffer is not.
+    fn leak_heap(host: &HostRegion) -> &'static LockedHeap
+    {
+        Box::leak(Box::new(LockedHeap::with_buffer(host.addr() as *mut u8, host.size())))
+    }
+
+    #[test]
+    fn loom_concurrent_alloc_free()
+    {
+        loom::model(|| {
+            let host    = HostRegion::new(4096, 16).unwrap();
+            let heap    = leak_heap(&host);
+            let initial = heap.lock().stats();
+
+            let worker = move |fill: u8| {
+                let addr = heap.lock().alloc_bytes(64).unwrap();
+                unsafe { (addr as *mut u8).write_bytes(fill, 64) };
+                assert!((0..64).all(|i| unsafe { *((addr + i) as *const u8) } == fill));
+                heap.lock().free_bytes(addr);
+            };
+            let t1 = thread::spawn(move || worker(1));
+            let t2 = thread::spawn(move || worker(2));
+            t1.join().unwrap();
+            t2.join().unwrap();
+
+            assert_eq!(heap.lock().check_integrity(), Ok(()));
+            assert_eq!(heap.lock().stats(), initial);
+        });
+    }
+
+    #[test]
+    fn loom_remote_free()
+    {
+        // A block freed by another thread than the one which allocated it, such as a buffer handed over to an interrupt handler.
+        loom::model(|| {
+            let host = HostRegion::new(4096, 16).unwrap();
+            let heap = leak_heap(&host);
+
+            let producer = thread::spawn(move || heap.lock().alloc_bytes(128).unwrap());
+            let consumer = thread::spawn(move || heap.lock().alloc_bytes(32).unwrap());
+
+            let addr = producer.join().unwrap();
+            let freer = thread::spawn(move || heap.lock().free_bytes(addr));
+            let other = consumer.join().unwrap();
+            freer.join().unwrap();
+
+            let mut mman = heap.lock();
+            assert_eq!(mman.check_integrity(), Ok(()));
+            assert_eq!(mman.stats().used_blocks, 1);
+            mman.free_bytes(other);
+        });
+    }
+}
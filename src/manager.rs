@@ -0,0 +1,5424 @@
+use core::cell::Cell;
+use core::cmp;
+use core::fmt;
+use core::mem;
+use core::mem::MaybeUninit;
+use core::ops::Range;
+#[cfg(feature = "track-caller")]
+use core::panic::Location;
+use core::pin::Pin;
+use core::ptr;
+use core::ptr::NonNull;
+use core::ptr::Unique;
+use core::slice;
+use core::alloc::Layout;
+#[cfg(feature = "zeroize")]
+use core::sync::atomic::compiler_fence;
+#[cfg(feature = "zeroize")]
+use core::sync::atomic::Ordering;
+
+use super::Allocator;
+use super::round_up;
+#[cfg(feature = "std")]
+use backtraces::BacktraceTable;
+use cursor::BlockCursor;
+use error::{AllocError, CannotGrow, IntegrityError, RemoveRegionError, SelfTestError};
+#[cfg(all(feature = "mte", target_arch = "aarch64"))]
+use mte;
+#[cfg(all(feature = "mte", target_arch = "aarch64"))]
+use mte::untag;
+use owned::Owned;
+use policy::{EntropySource, FitMode, PolicyObserver};
+use region::{MemoryRegion, MemorySource, PhysToVirt};
+use shadow::ShadowMap;
+use tag::{BoundaryTag, LinkKey};
+
+
+/// Zero the bytes by volatile writes, which the compiler cannot drop as dead stores.
+#[cfg(feature = "zeroize")]
+fn wipe_bytes(addr: usize, size: usize)
+{
+    for i in 0..size {
+        unsafe { ptr::write_volatile((addr + i) as *mut u8, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+
+/// Without `mte` the addresses carry no tag.
+#[cfg(not(all(feature = "mte", target_arch = "aarch64")))]
+fn untag(addr: usize) -> usize
+{
+    addr
+}
+
+
+/// Receiver of every allocation and free of a heap, such as a tracer or a leak checker.
+pub trait AllocObserver {
+    /// A block of `size` bytes aligned to `align` was allocated at `addr`.
+    fn on_alloc(&mut self, addr: usize, size: usize, align: usize);
+
+    /// The block at `addr` was freed.
+    fn on_free(&mut self, addr: usize);
+
+    /// The block at `old_addr` was moved to `new_addr` by `compact` or `migrate_region`.
+    fn on_move(&mut self, _old_addr: usize, _new_addr: usize)
+    {
+    }
+}
+
+
+impl<T: AllocObserver + ?Sized> AllocObserver for &mut T {
+    fn on_alloc(&mut self, addr: usize, size: usize, align: usize)
+    {
+        (**self).on_alloc(addr, size, align);
+    }
+
+    fn on_free(&mut self, addr: usize)
+    {
+        (**self).on_free(addr);
+    }
+
+    fn on_move(&mut self, old_addr: usize, new_addr: usize)
+    {
+        (**self).on_move(old_addr, new_addr);
+    }
+}
+
+
+/// Several observers in the single slot of a heap, such as a tracer and a leak checker at once, told of every event in order.
+/// A tuple holds them when they are known at compile time, and an array of `&mut AllocObserver` when they are chosen at runtime.
+macro_rules! impl_alloc_observer_for_tuple {
+    ($($name:ident),+) => {
+        #[allow(non_snake_case)]
+        impl<$($name: AllocObserver),+> AllocObserver for ($($name,)+) {
+            fn on_alloc(&mut self, addr: usize, size: usize, align: usize)
+            {
+                let ($(ref mut $name,)+) = *self;
+                $($name.on_alloc(addr, size, align);)+
+            }
+
+            fn on_free(&mut self, addr: usize)
+            {
+                let ($(ref mut $name,)+) = *self;
+                $($name.on_free(addr);)+
+            }
+
+            fn on_move(&mut self, old_addr: usize, new_addr: usize)
+            {
+                let ($(ref mut $name,)+) = *self;
+                $($name.on_move(old_addr, new_addr);)+
+            }
+        }
+    };
+}
+
+impl_alloc_observer_for_tuple!(A, B);
+impl_alloc_observer_for_tuple!(A, B, C);
+impl_alloc_observer_for_tuple!(A, B, C, D);
+
+
+impl<O: AllocObserver, const N: usize> AllocObserver for [O; N] {
+    fn on_alloc(&mut self, addr: usize, size: usize, align: usize)
+    {
+        for observer in self.iter_mut() {
+            observer.on_alloc(addr, size, align);
+        }
+    }
+
+    fn on_free(&mut self, addr: usize)
+    {
+        for observer in self.iter_mut() {
+            observer.on_free(addr);
+        }
+    }
+
+    fn on_move(&mut self, old_addr: usize, new_addr: usize)
+    {
+        for observer in self.iter_mut() {
+            observer.on_move(old_addr, new_addr);
+        }
+    }
+}
+
+
+/// Guard of the tags of a heap, such as a kernel mapping the pages of the tags read-only between the operations of the allocator,
+/// so that a stray write into the metadata faults at once instead of corrupting the heap silently.
+/// The ranges cover the whole heap; the protector has to leave writable the pages holding allocated payloads.
+pub trait MetadataProtector {
+    fn protect(&mut self, range: Range<usize>);
+
+    fn unprotect(&mut self, range: Range<usize>);
+}
+
+
+/// Virtual memory operations for the guard pages placed by `MemoryManager::set_guard_pages`.
+pub trait VmHook {
+    /// Make the page at `addr` inaccessible, so that any access faults.
+    fn unmap(&mut self, addr: usize);
+
+    /// Make the page at `addr` accessible again before the block around it is freed.
+    fn remap(&mut self, addr: usize);
+}
+
+
+/// Page permission changes of the W^X policy for the blocks of `MemoryManager::alloc_exec`.
+pub trait ExecHook {
+    /// Make the pages at `addr` readable and executable but not writable.
+    fn make_executable(&mut self, addr: usize, len: usize);
+
+    /// Make the pages at `addr` readable and writable but not executable.
+    fn make_writable(&mut self, addr: usize, len: usize);
+}
+
+
+/// Receiver of the allocations refused by the budgets set by `MemoryManager::set_budgets`,
+/// such as a network stack dropping its buffers instead of starving the rest of the kernel.
+pub trait BudgetObserver {
+    fn on_budget_exceeded(&mut self, tag: usize);
+}
+
+
+/// Receiver of the changes of the memory level of a heap, such as a cache that shrinks itself when the memory runs low.
+pub trait WatermarkObserver {
+    fn on_level_change(&mut self, level: MemoryLevel);
+}
+
+
+/// How much free memory is left compared with the watermarks set by `MemoryManager::set_watermarks`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryLevel {
+    Normal,
+    Low,
+    Critical,
+}
+
+
+/// Finds the tag of a block in its user data for `MemoryManager::set_budgets`.
+pub type BudgetTagOf<M> = fn(&M) -> usize;
+
+
+/// Cap of the payload bytes allocated under a tag, set by `MemoryManager::set_budgets`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    pub limit: usize,
+    /// Payload bytes allocated under the tag now.
+    pub used: usize,
+}
+
+
+impl Budget {
+    pub const fn new(limit: usize) -> Budget
+    {
+        Budget {
+            limit,
+            used: 0,
+        }
+    }
+}
+
+
+/// Thresholds of `MemoryManager::set_adaptive_policy`.
+pub(crate) struct AdaptivePolicy {
+    high: usize,
+    low: usize,
+    interval: usize,
+    countdown: usize,
+}
+
+
+/// Identifier of a region of a heap, which stays the same as long as the region is managed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionId(pub(crate) usize);
+
+
+impl RegionId {
+    /// The index of the region in the order the regions were given to the manager.
+    pub fn index(&self) -> usize
+    {
+        self.0
+    }
+}
+
+
+/// Priority of an allocation by `MemoryManager::try_alloc_with_priority`; the other allocations are `Normal`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Low,
+    Normal,
+    /// Never refused by a floor, for the critical paths the floors keep the headroom for.
+    High,
+}
+
+
+/// Upper bound of the candidate count for randomized free block selection.
+const MAX_RANDOM_FIT_CANDIDATES: usize = 8;
+
+
+/// Number of the size classes indexed by the free list bitmap.
+const NUM_SIZE_CLASSES: usize = 64;
+
+
+/// Smallest payload a block can have so that it can hold the free list links once it is freed.
+pub(crate) const MIN_PAYLOAD_SIZE: usize = 2 * mem::size_of::<usize>();
+
+
+/// Index of the size class `size` belongs to; the class `c` holds the sizes in `[2^c, 2^(c + 1))`.
+fn size_class(size: usize) -> usize
+{
+    debug_check!(size != 0);
+    mem::size_of::<usize>() * 8 - 1 - size.leading_zeros() as usize
+}
+
+
+/// Number of the quick lists a heap can have.
+const MAX_QUICK_LISTS: usize = 4;
+
+
+/// LIFO cache of the freed blocks of a single payload size.
+/// The cached blocks stay marked as allocated and are linked through their payloads.
+pub(crate) struct QuickList {
+    size: usize,
+    max_len: usize,
+    len: Cell<usize>,
+    head_tag_addr: Cell<Option<usize>>,
+}
+
+
+impl QuickList {
+    const fn new(size: usize, max_len: usize) -> QuickList
+    {
+        QuickList {
+            size,
+            max_len,
+            len: Cell::new(0),
+            head_tag_addr: Cell::new(None),
+        }
+    }
+
+    fn push<M: Copy + Default>(&self, tag: &BoundaryTag<M>) -> bool
+    {
+        if self.size != tag.free_area_size() || self.max_len <= self.len.get() {
+            return false;
+        }
+
+        tag.set_payload_link(self.head_tag_addr.get());
+        self.head_tag_addr.set(Some(tag.addr()));
+        self.len.set(self.len.get() + 1);
+        true
+    }
+
+    fn pop<M: Copy + Default>(&self) -> Option<Unique<BoundaryTag<M>>>
+    {
+        self.head_tag_addr.get().map(|addr| {
+            let tag = unsafe { BoundaryTag::<M>::new_from_addr(addr) };
+            self.head_tag_addr.set(unsafe { tag.as_ref() }.payload_link());
+            self.len.set(self.len.get() - 1);
+            tag
+        })
+    }
+}
+
+
+/// A heap of one or more regions.
+/// The manager borrows its tag slice and its hooks for `'a`.
+/// `empty` and `init_with_slots` only place the tag slice inside the managed memory, so the lifetime stays,
+/// and the manager is a `MemoryManager<'static>` only if that memory and the hooks are `'static` too.
+pub struct MemoryManager<'a, M: 'a = (), const MIN_ALIGN: usize = 1> {
+    pub(crate) tags: &'a mut [Unique<BoundaryTag<M>>],
+    pub(crate) num_regions: usize,
+    /// The key of the previous links stored in the tags of this heap.
+    pub(crate) link_key: LinkKey,
+    pub(crate) random_fit: Option<(usize, &'a mut dyn EntropySource)>,
+    pub(crate) deferred_coalescing: bool,
+    pub(crate) pending_tag_addr: Cell<Option<usize>>,
+    pub(crate) quick_lists: [QuickList; MAX_QUICK_LISTS],
+    pub(crate) hot_size: Option<usize>,
+    pub(crate) free_lists: [Option<usize>; NUM_SIZE_CLASSES],
+    pub(crate) free_list_bitmap: usize,
+    pub(crate) address_ordered: bool,
+    pub(crate) huge_threshold: usize,
+    pub(crate) huge_source: Option<&'a mut dyn MemorySource>,
+    pub(crate) size_classes: &'a [usize],
+    pub(crate) min_align: usize,
+    pub(crate) split_threshold: usize,
+    pub(crate) low_floor: usize,
+    pub(crate) normal_floor: usize,
+    pub(crate) slack_percent: usize,
+    pub(crate) slack_bytes: usize,
+    pub(crate) max_bytes: usize,
+    pub(crate) max_blocks: usize,
+    pub(crate) used_bytes: usize,
+    pub(crate) used_blocks: usize,
+    pub(crate) free_list_bytes: usize,
+    pub(crate) low_watermark: usize,
+    pub(crate) critical_watermark: usize,
+    pub(crate) level: MemoryLevel,
+    pub(crate) watermark_observer: Option<&'a mut dyn WatermarkObserver>,
+    pub(crate) alloc_observer: Option<&'a mut dyn AllocObserver>,
+    pub(crate) metadata_protector: Option<(usize, usize, &'a mut dyn MetadataProtector)>,
+    pub(crate) unprotect_depth: usize,
+    pub(crate) guard_pages: Option<(usize, usize, &'a mut dyn VmHook)>,
+    pub(crate) phys_to_virt: Option<&'a dyn PhysToVirt>,
+    pub(crate) dma_regions: &'a [DmaRegion],
+    pub(crate) cache_maintenance: Option<(usize, &'a mut dyn CacheMaintenance)>,
+    pub(crate) exec_regions: &'a [RegionId],
+    pub(crate) exec_hook: Option<(usize, &'a mut dyn ExecHook)>,
+    /// The range the searches of the free blocks are limited to during `malloc_in_region`.
+    pub(crate) target_region: Option<(usize, usize)>,
+    /// The offset from the payload to the aligned point during `alloc_aligned_offset`.
+    pub(crate) align_offset: usize,
+    pub(crate) region_preference: &'a [RegionId],
+    pub(crate) shadow: Option<ShadowMap<'a>>,
+    #[cfg(feature = "zeroize")]
+    pub(crate) zeroize_all: bool,
+    pub(crate) budgets: Option<(&'a mut [Budget], BudgetTagOf<M>)>,
+    pub(crate) budget_observer: Option<&'a mut dyn BudgetObserver>,
+    pub(crate) adaptive_policy: Option<AdaptivePolicy>,
+    pub(crate) policy_observer: Option<&'a mut dyn PolicyObserver>,
+    #[cfg(feature = "std")]
+    pub(crate) backtraces: Option<&'a mut BacktraceTable>,
+}
+
+
+/// Block found by `MemoryManager::block_of`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockInfo {
+    /// Address of the payload.
+    pub addr: usize,
+    pub size: usize,
+    pub is_alloc: bool,
+}
+
+
+/// Block still allocated, found by `MemoryManager::report_leaks`.
+#[derive(Debug, Clone, Copy)]
+pub struct Leak {
+    pub block: BlockInfo,
+    /// Where the block was allocated.
+    #[cfg(feature = "track-caller")]
+    pub location: Option<&'static Location<'static>>,
+}
+
+
+/// Usage of a heap found by `MemoryManager::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    pub used_bytes: usize,
+    pub used_blocks: usize,
+    /// Bytes in the free lists; the blocks cached in the quick lists or pending for coalescing are not counted.
+    pub free_bytes: usize,
+    pub largest_free_block: usize,
+}
+
+
+/// Free range pinned by `MemoryManager::reserve`.
+/// The caller may write into it before it is either committed or canceled.
+#[must_use]
+#[derive(Debug)]
+pub struct Reservation {
+    addr: usize,
+    capacity: usize,
+}
+
+
+impl Reservation {
+    pub fn addr(&self) -> usize
+    {
+        self.addr
+    }
+
+    /// The bytes that can be written from `addr`, at least the size of the reserved layout.
+    pub fn capacity(&self) -> usize
+    {
+        self.capacity
+    }
+}
+
+
+/// Cache operations of a CPU whose caches are not coherent with the DMA, called by `MemoryManager::sync_for_device` and `sync_for_cpu`.
+pub trait CacheMaintenance {
+    /// Write the dirty cache lines of the range back to the memory, so that a device reads what the CPU wrote.
+    fn clean(&mut self, addr: usize, len: usize);
+
+    /// Discard the cache lines of the range, so that the CPU reads what a device wrote.
+    fn invalidate(&mut self, addr: usize, len: usize);
+}
+
+
+/// Region a device can reach, given to `MemoryManager::set_dma_regions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmaRegion {
+    pub region: RegionId,
+    /// The caches are coherent with the DMA in the region, such as a region mapped uncached, so no cache maintenance is needed.
+    pub coherent: bool,
+}
+
+
+/// Buffer allocated by `MemoryManager::alloc_dma`, freed by `MemoryManager::free_dma`.
+#[must_use]
+#[derive(Debug)]
+pub struct DmaBuffer {
+    pub vaddr: usize,
+    pub paddr: usize,
+    pub len: usize,
+    coherent: bool,
+}
+
+
+impl DmaBuffer {
+    pub fn is_coherent(&self) -> bool
+    {
+        self.coherent
+    }
+}
+
+
+impl<'a, M: 'a + Copy + Default> MemoryManager<'a, M> {
+    pub fn new(tags: &'a mut [Unique<BoundaryTag<M>>]) -> MemoryManager<'a, M>
+    {
+        let num_regions = tags.len();
+        MemoryManager::with_capacity(tags, num_regions)
+    }
+
+    /// Manage the regions of the first `num_regions` tags.
+    /// The rest of `tags` is the room for the regions taken over by `absorb`.
+    pub fn with_capacity(tags: &'a mut [Unique<BoundaryTag<M>>], num_regions: usize) -> MemoryManager<'a, M>
+    {
+        MemoryManager::with_min_align(tags, num_regions)
+    }
+
+    /// Create a manager without any region, so that it can be placed in a `static` before the memory is known.
+    /// `init` has to be called before the first allocation.
+    pub const fn empty() -> MemoryManager<'a, M>
+    {
+        MemoryManager::empty_with_min_align()
+    }
+
+    /// Create a manager of the memory at `addr` of `size` bytes, keeping all the bookkeeping inside the memory itself,
+    /// so that the callers do not have to prepare the tags.
+    /// Return `None` if the memory is too small to hold a block.
+    ///
+    /// # Safety
+    /// The memory must be valid for reads and writes and must not be used by anything else for `'a`.
+    pub unsafe fn from_raw_parts(addr: usize, size: usize) -> Option<MemoryManager<'a, M>>
+    {
+        let mut mman = MemoryManager::empty();
+        if mman.init(addr, size) { Some(mman) } else { None }
+    }
+
+    /// Create a manager of a plain byte buffer, such as a `static mut` array, without any address arithmetic.
+    /// Return `None` if the buffer is too small to hold a block.
+    pub fn from_buffer(buffer: &'a mut [MaybeUninit<u8>]) -> Option<MemoryManager<'a, M>>
+    {
+        // The buffer is borrowed exclusively for `'a`, so nothing else can touch the memory.
+        unsafe { MemoryManager::from_raw_parts(buffer.as_mut_ptr() as usize, buffer.len()) }
+    }
+
+    /// Create a manager of the memory between the symbols defined by the linker script, such as `__heap_start` and `__heap_end`.
+    ///
+    /// # Safety
+    /// The memory between `start` and `end` must meet the requirements of `from_raw_parts`.
+    ///
+    /// ```ignore
+    /// extern "C" {
+    ///     static mut __heap_start: u8;
+    ///     static mut __heap_end: u8;
+    /// }
+    ///
+    /// let mman = unsafe { MemoryManager::from_linker_symbols(&mut __heap_start, &mut __heap_end) };
+    /// ```
+    pub unsafe fn from_linker_symbols(start: *mut u8, end: *mut u8) -> Option<MemoryManager<'a, M>>
+    {
+        if end < start {
+            return None;
+        }
+
+        MemoryManager::from_raw_parts(start as usize, end as usize - start as usize)
+    }
+
+    /// Create a manager whose tag links are mangled with `secret`, which should come from a random source.
+    /// Without a secret the key of a heap is derived from its address, which can be guessed.
+    #[cfg(feature = "harden")]
+    pub fn with_link_secret(tags: &'a mut [Unique<BoundaryTag<M>>], secret: usize) -> MemoryManager<'a, M>
+    {
+        let mut mman = MemoryManager::new(tags);
+        mman.set_link_secret(secret);
+        mman
+    }
+}
+
+
+impl<'a, M: 'a + Copy + Default, const MIN_ALIGN: usize> MemoryManager<'a, M, MIN_ALIGN> {
+    const VALID_MIN_ALIGN: () = assert!(MIN_ALIGN.is_power_of_two(), "MIN_ALIGN must be a power of two");
+
+    /// `with_capacity` of a manager aligning every payload to `MIN_ALIGN` at least, such as `MemoryManager::<(), 64>`.
+    /// A `MIN_ALIGN` that is not a power of two is rejected at compile time.
+    pub fn with_min_align(tags: &'a mut [Unique<BoundaryTag<M>>], num_regions: usize) -> MemoryManager<'a, M, MIN_ALIGN>
+    {
+        debug_check!(num_regions != 0 && num_regions <= tags.len());
+
+        let mut mman     = MemoryManager::empty_with_min_align();
+        mman.tags        = tags;
+        mman.num_regions = num_regions;
+        mman.link_key    = LinkKey::of_addr(unsafe { mman.tags[0].as_ref() }.addr());
+
+        // A region made by `BoundaryTag::with_holes` has several free blocks between its holes.
+        for i in 0..mman.num_regions {
+            BoundaryTag::<M>::rekey_chain(mman.tags[i], LinkKey::NONE, mman.link_key);
+            let mut tag_addr = Some(unsafe { mman.tags[i].as_ref() }.addr());
+            while let Some(addr) = tag_addr {
+                let tag_ref = unsafe { &*(addr as *const BoundaryTag<M>) };
+                if !tag_ref.is_alloc {
+                    mman.insert_free_tag(tag_ref);
+                }
+                tag_addr = tag_ref.next_tag_addr();
+            }
+        }
+
+        mman
+    }
+
+    /// `empty` of a manager aligning every payload to `MIN_ALIGN` at least.
+    pub const fn empty_with_min_align() -> MemoryManager<'a, M, MIN_ALIGN>
+    {
+        let () = Self::VALID_MIN_ALIGN;
+
+        MemoryManager {
+            tags: &mut [],
+            num_regions: 0,
+            link_key: LinkKey::NONE,
+            random_fit: None,
+            deferred_coalescing: false,
+            pending_tag_addr: Cell::new(None),
+            quick_lists: [QuickList::new(0, 0), QuickList::new(0, 0), QuickList::new(0, 0), QuickList::new(0, 0)],
+            hot_size: None,
+            free_lists: [None; NUM_SIZE_CLASSES],
+            free_list_bitmap: 0,
+            address_ordered: false,
+            huge_threshold: usize::MAX,
+            huge_source: None,
+            size_classes: &[],
+            min_align: if MIN_ALIGN < mem::align_of::<BoundaryTag<M>>() { mem::align_of::<BoundaryTag<M>>() } else { MIN_ALIGN },
+            split_threshold: MIN_PAYLOAD_SIZE,
+            low_floor: 0,
+            normal_floor: 0,
+            slack_percent: 0,
+            slack_bytes: 0,
+            max_bytes: usize::MAX,
+            max_blocks: usize::MAX,
+            used_bytes: 0,
+            used_blocks: 0,
+            free_list_bytes: 0,
+            low_watermark: 0,
+            critical_watermark: 0,
+            level: MemoryLevel::Normal,
+            watermark_observer: None,
+            alloc_observer: None,
+            metadata_protector: None,
+            unprotect_depth: 0,
+            guard_pages: None,
+            phys_to_virt: None,
+            dma_regions: &[],
+            cache_maintenance: None,
+            exec_regions: &[],
+            exec_hook: None,
+            target_region: None,
+            align_offset: 0,
+            region_preference: &[],
+            shadow: None,
+            #[cfg(feature = "zeroize")]
+            zeroize_all: false,
+            budgets: None,
+            budget_observer: None,
+            adaptive_policy: None,
+            policy_observer: None,
+            #[cfg(feature = "std")]
+            backtraces: None,
+        }
+    }
+
+    /// Give the memory at `addr` of `size` bytes to a manager created by `empty`.
+    /// The tag slice is placed at the head of the memory.
+    /// Return `false` if the memory is too small to hold a block.
+    ///
+    /// # Safety
+    /// The memory must be valid for reads and writes and must not be used by anything else for `'a`.
+    pub unsafe fn init(&mut self, addr: usize, size: usize) -> bool
+    {
+        self.init_with_slots(addr, size, 1)
+    }
+
+    /// `init` reserving `max_regions` slots in the tag slice, so that `add_region` can take more memory later
+    /// without any bookkeeping outside of the managed memory.
+    ///
+    /// # Safety
+    /// The same as `init`.
+    pub unsafe fn init_with_slots(&mut self, addr: usize, size: usize, max_regions: usize) -> bool
+    {
+        debug_check!(self.num_regions == 0 && max_regions != 0);
+
+        let begin     = round_up(addr, mem::align_of::<BoundaryTag<M>>());
+        let end       = (addr + size) & !(mem::align_of::<BoundaryTag<M>>() - 1);
+        let tags_size = Self::tags_size_of(max_regions);
+        if end < begin || end - begin < tags_size + mem::size_of::<BoundaryTag<M>>() + MIN_PAYLOAD_SIZE {
+            return false;
+        }
+
+        self.tags        = Self::tags_in_place(begin, end - begin, max_regions);
+        self.num_regions = 1;
+        if self.link_key == LinkKey::NONE {
+            self.link_key = LinkKey::of_addr(begin);
+        }
+        let tag = self.tags[0];
+        self.insert_free_tag(tag.as_ref());
+        true
+    }
+
+    /// The bytes taken by a tag slice of `slots` slots at the head of a region.
+    fn tags_size_of(slots: usize) -> usize
+    {
+        round_up(slots * mem::size_of::<Unique<BoundaryTag<M>>>(), mem::align_of::<BoundaryTag<M>>())
+    }
+
+    /// Make the memory at `addr` of `size` bytes a region whose tag slice of `slots` slots is placed at its head.
+    /// `addr` and `size` must be aligned for `BoundaryTag`.
+    unsafe fn tags_in_place(addr: usize, size: usize, slots: usize) -> &'a mut [Unique<BoundaryTag<M>>]
+    {
+        let tags_size = Self::tags_size_of(slots);
+        let tags      = slice::from_raw_parts_mut(addr as *mut Unique<BoundaryTag<M>>, slots);
+        ptr::write(&mut tags[0], BoundaryTag::<M>::with_user_data(addr + tags_size, size - tags_size));
+        for slot in tags[1..].iter_mut() {
+            ptr::write(slot, Unique::dangling());
+        }
+        tags
+    }
+
+    /// Add the memory at `addr` of `size` bytes, such as the memory found after boot, to the managed regions.
+    /// A region physically adjacent to a managed one joins its chain; any other one takes a spare slot of the tag slice.
+    /// Return the region holding the memory, or `None` if the memory cannot hold a block or no slot is left.
+    ///
+    /// # Safety
+    /// The memory must be valid for reads and writes and must not be used by anything else for `'a`.
+    pub unsafe fn add_region(&mut self, addr: usize, size: usize) -> Option<RegionId>
+    {
+        let head =
+            match BoundaryTag::<M>::try_with_user_data(addr, size) {
+                Err(_) => return None,
+                Ok(region_tag) => region_tag.tag,
+            };
+
+        let adjacent = self.adjacent_region_of(head.as_ref());
+        if adjacent.is_none() && self.tags.len() <= self.num_regions {
+            return None;
+        }
+
+        self.unprotect_metadata();
+        self.insert_free_tag(head.as_ref());
+        let index =
+            match adjacent {
+                None => {
+                    self.tags[self.num_regions] = head;
+                    self.num_regions += 1;
+                    self.num_regions - 1
+                },
+                Some((j, true)) => {
+                    let last = BoundaryTag::<M>::new_from_addr(self.tags[j].as_ref().last_of_chain().addr());
+                    self.join(last, head);
+                    j
+                },
+                Some((j, false)) => {
+                    let last = BoundaryTag::<M>::new_from_addr(head.as_ref().last_of_chain().addr());
+                    let next = self.tags[j];
+                    self.join(last, next);
+                    self.tags[j] = head;
+                    j
+                },
+            };
+        self.protect_metadata();
+        self.update_level();
+
+        Some(RegionId(index))
+    }
+
+    /// Mangle the tag links of this heap with `secret`, which should come from a random source, re-encoding the links already stored.
+    #[cfg(feature = "harden")]
+    pub fn set_link_secret(&mut self, secret: usize)
+    {
+        let key = LinkKey::new(secret);
+        self.unprotect_metadata();
+        for i in 0..self.num_regions {
+            BoundaryTag::<M>::rekey_chain(self.tags[i], self.link_key, key);
+        }
+        self.link_key = key;
+        self.protect_metadata();
+    }
+
+    /// Align every returned pointer to `min_align`, which must be a power of two, such as the cache line size.
+    /// This must be set before the first allocation because the blocks cached in the quick lists are not realigned.
+    pub fn set_min_align(&mut self, min_align: usize)
+    {
+        debug_check!(min_align.is_power_of_two());
+        self.min_align = cmp::max(cmp::max(min_align, MIN_ALIGN), mem::align_of::<BoundaryTag<M>>());
+    }
+
+    /// Split a free block only if the rest would have a payload of `threshold` bytes at least; otherwise the whole block is taken.
+    /// A larger threshold leaves fewer tiny free blocks at the cost of internal fragmentation.
+    pub fn set_split_threshold(&mut self, threshold: usize)
+    {
+        self.split_threshold = round_up(cmp::max(threshold, MIN_PAYLOAD_SIZE), mem::align_of::<BoundaryTag<M>>());
+    }
+
+    /// Refuse the low priority allocations that would leave less than `low` free bytes and the normal ones below `normal`,
+    /// keeping the headroom for the high priority ones without a separate heap.
+    /// The blocks cached in the quick lists or pending for coalescing are not counted as free.
+    pub fn set_priority_floors(&mut self, low: usize, normal: usize)
+    {
+        debug_check!(normal <= low);
+        self.low_floor    = low;
+        self.normal_floor = normal;
+    }
+
+    /// Over-allocate every block by `percent` of the requested size plus `bytes`, so that growing a buffer by small reallocations stays in place.
+    /// The extra bytes are left out when the heap cannot afford them; `usable_size` tells what a block got.
+    pub fn set_slack(&mut self, percent: usize, bytes: usize)
+    {
+        self.slack_percent = percent;
+        self.slack_bytes   = bytes;
+    }
+
+    fn slack_of(&self, size: usize) -> usize
+    {
+        (size.saturating_mul(self.slack_percent) / 100).saturating_add(self.slack_bytes)
+    }
+
+    /// Limit the payload bytes and the number of the blocks allocated at a time,
+    /// so that a runaway subsystem cannot exhaust the memory needed by the rest.
+    /// The blocks already allocated are not affected even if they exceed the new quota.
+    pub fn set_quota(&mut self, max_bytes: usize, max_blocks: usize)
+    {
+        self.max_bytes  = max_bytes;
+        self.max_blocks = max_blocks;
+    }
+
+    /// Trace the failure of an allocation and return the error.
+    #[cfg_attr(not(any(feature = "defmt", feature = "log")), allow(unused_variables))]
+    fn on_alloc_error(&self, size: usize, align: usize, error: AllocError) -> AllocError
+    {
+        #[cfg(feature = "defmt")]
+        defmt::warn!("alloc failed size={=usize} align={=usize} error={} free={=usize}", size, align, error, self.free_list_bytes);
+        #[cfg(feature = "log")]
+        log::warn!("allocation of {} bytes aligned to {} failed: {:?}, {} bytes free", size, align, error, self.free_list_bytes);
+
+        error
+    }
+
+    /// Tell whether a request of `request_size` bytes failed because of the fragmentation or the exhaustion of the free lists.
+    fn shortage_error(&self, request_size: usize) -> AllocError
+    {
+        let largest_free = self.largest_free_block();
+        let total_free   = self.free_list_bytes;
+        if request_size <= total_free {
+            AllocError::Fragmented { largest_free, total_free }
+        } else {
+            AllocError::Exhausted { largest_free, total_free }
+        }
+    }
+
+    pub(crate) fn charge(&mut self, tag: &BoundaryTag<M>)
+    {
+        self.used_bytes  += tag.free_area_size();
+        self.used_blocks += 1;
+
+        if let Some(ref mut shadow) = self.shadow {
+            shadow.mark(tag.addr_free_area(), tag.free_area_size(), true);
+        }
+        self.account(&tag.user_data, tag.free_area_size(), true);
+
+        #[cfg(feature = "defmt")]
+        defmt::trace!("alloc addr={=usize:#x} size={=usize} used={=usize}(+{=usize})", tag.addr_free_area(), tag.free_area_size(), self.used_bytes, tag.free_area_size());
+
+        #[cfg(feature = "std")]
+        {
+            if let Some(ref mut table) = self.backtraces {
+                table.record(tag.addr_free_area());
+            }
+        }
+    }
+
+    fn uncharge(&mut self, tag: &BoundaryTag<M>)
+    {
+        self.used_bytes  -= tag.free_area_size();
+        self.used_blocks -= 1;
+
+        if let Some(ref mut shadow) = self.shadow {
+            shadow.mark(tag.addr_free_area(), tag.free_area_size(), false);
+        }
+        self.account(&tag.user_data, tag.free_area_size(), false);
+
+        if let Some(ref mut observer) = self.alloc_observer {
+            observer.on_free(tag.addr_free_area());
+        }
+
+        #[cfg(feature = "defmt")]
+        defmt::trace!("free addr={=usize:#x} size={=usize} used={=usize}(-{=usize})", tag.addr_free_area(), tag.free_area_size(), self.used_bytes, tag.free_area_size());
+
+        #[cfg(feature = "std")]
+        {
+            if let Some(ref mut table) = self.backtraces {
+                table.forget(tag.addr_free_area());
+            }
+        }
+    }
+
+    /// Call `protector` around every operation that writes the tags, starting with protecting the heap now.
+    /// The range is taken from the regions at this point, so the regions added later are not covered.
+    pub fn set_metadata_protector(&mut self, protector: &'a mut dyn MetadataProtector)
+    {
+        let (begin, end) = self.heap_span();
+        protector.protect(begin..end);
+        self.metadata_protector = Some((begin, end, protector));
+    }
+
+    /// The range from the lowest tag to the end of the highest payload of the regions.
+    fn heap_span(&self) -> (usize, usize)
+    {
+        let begin = self.regions().iter().map(|tag| unsafe { tag.as_ref() }.addr()).min().unwrap_or(0);
+        let end   = self.regions().iter().map(|tag| {
+            let last = unsafe { tag.as_ref() }.last_of_chain();
+            last.addr_free_area() + last.free_area_size()
+        }).max().unwrap_or(0);
+
+        (begin, end)
+    }
+
+    /// The number of words `set_shadow_map` needs to cover the regions managed now.
+    pub fn shadow_map_words(&self) -> usize
+    {
+        let (begin, end) = self.heap_span();
+        ShadowMap::words_for(end - begin, mem::align_of::<BoundaryTag<M>>())
+    }
+
+    /// Keep a bit per granule of the heap in `bits`, set for the payloads of the allocated blocks,
+    /// so that `is_allocated` and `is_valid_range` answer without walking the blocks.
+    /// The range is taken from the regions at this point, so the regions added later are not covered.
+    /// Return `false` if `bits` is shorter than `shadow_map_words`, leaving the heap without a shadow map.
+    pub fn set_shadow_map(&mut self, bits: &'a mut [usize]) -> bool
+    {
+        if bits.len() < self.shadow_map_words() {
+            return false;
+        }
+
+        let (begin, _) = self.heap_span();
+        let mut shadow = ShadowMap::new(begin, mem::align_of::<BoundaryTag<M>>(), bits);
+        self.for_each_allocated(|tag| shadow.mark(tag.addr_free_area(), tag.free_area_size(), true));
+        self.shadow = Some(shadow);
+        true
+    }
+
+    /// Call `f` with every allocated block, after taking the cached blocks back because they look allocated in their tags.
+    /// The blocks covering the holes of a region are skipped.
+    fn for_each_allocated<F>(&mut self, mut f: F)
+        where F: FnMut(&BoundaryTag<M>)
+    {
+        self.unprotect_metadata();
+        self.flush_caches();
+        self.protect_metadata();
+
+        for tag in self.regions().iter() {
+            let mut tag_addr = Some(unsafe { tag.as_ref() }.addr());
+            while let Some(addr) = tag_addr {
+                let tag_ref = unsafe { &*(addr as *const BoundaryTag<M>) };
+                if tag_ref.is_alloc && !tag_ref.is_reserved {
+                    f(tag_ref);
+                }
+                tag_addr = tag_ref.next_tag_addr();
+            }
+        }
+    }
+
+    /// Whether `addr` points into the payload of an allocated block.
+    /// Without a shadow map the blocks are walked, and the blocks cached in the quick lists or pending for coalescing count as allocated.
+    pub fn is_allocated(&self, addr: usize) -> bool
+    {
+        let addr = untag(addr);
+        match self.shadow {
+            Some(ref shadow) if shadow.covers(addr) => shadow.is_set(addr),
+            _ => self.block_of(addr).is_some_and(|block| block.is_alloc && block.addr <= addr),
+        }
+    }
+
+    /// Whether the `len` bytes at `addr` are all inside the payload of a single allocated block,
+    /// such as a buffer handed over by untrusted code.
+    pub fn is_valid_range(&self, addr: usize, len: usize) -> bool
+    {
+        let addr = untag(addr);
+        if addr.checked_add(len).is_none() {
+            return false;
+        }
+
+        match self.shadow {
+            // The tags between the payloads are never set, so a run of set granules is a single payload.
+            Some(ref shadow) if shadow.covers(addr) => shadow.is_range_set(addr, len),
+            _ => self.block_of(addr).is_some_and(|block| block.is_alloc && block.addr <= addr && addr + len <= block.addr + block.size),
+        }
+    }
+
+    /// Place every payload of `threshold` bytes or more right before a page of `page_size` bytes unmapped by `hook`,
+    /// so that an overflow of a large buffer faults at once.
+    /// Each such block takes up to two more pages, one for the guard and one to align it.
+    pub fn set_guard_pages(&mut self, threshold: usize, page_size: usize, hook: &'a mut dyn VmHook)
+    {
+        debug_check!(page_size.is_power_of_two());
+        self.guard_pages = Some((threshold, page_size, hook));
+    }
+
+    /// Translate the addresses of the heap by `mapping` when a device needs them, such as the direct map of a higher-half kernel.
+    /// Without a mapping the physical addresses are the same as the virtual ones.
+    pub fn set_phys_to_virt(&mut self, mapping: &'a dyn PhysToVirt)
+    {
+        self.phys_to_virt = Some(mapping);
+    }
+
+    /// The physical address of `addr` in the heap.
+    pub fn phys_addr_of(&self, addr: usize) -> usize
+    {
+        let addr = untag(addr);
+        match self.phys_to_virt {
+            None => addr,
+            Some(mapping) => mapping.virt_to_phys(addr),
+        }
+    }
+
+    /// The address in the heap of the physical address `paddr`, such as a buffer given back by a device.
+    pub fn virt_addr_of(&self, paddr: usize) -> usize
+    {
+        match self.phys_to_virt {
+            None => paddr,
+            Some(mapping) => mapping.phys_to_virt(paddr),
+        }
+    }
+
+    /// Make the tags writable until the matching `protect_metadata`; the calls nest.
+    pub(crate) fn unprotect_metadata(&mut self)
+    {
+        self.unprotect_depth += 1;
+        if self.unprotect_depth == 1 {
+            if let Some((begin, end, ref mut protector)) = self.metadata_protector {
+                protector.unprotect(begin..end);
+            }
+        }
+    }
+
+    pub(crate) fn protect_metadata(&mut self)
+    {
+        self.unprotect_depth -= 1;
+        if self.unprotect_depth == 0 {
+            if let Some((begin, end, ref mut protector)) = self.metadata_protector {
+                protector.protect(begin..end);
+            }
+        }
+    }
+
+    /// Report the memory level as low when the free bytes drop below `low` and as critical below `critical`.
+    pub fn set_watermarks(&mut self, low: usize, critical: usize)
+    {
+        debug_check!(critical <= low);
+        self.low_watermark      = low;
+        self.critical_watermark = critical;
+        self.level              = self.memory_level();
+    }
+
+    /// Notify `observer` every time the memory level changes, including the recovery to the normal level.
+    pub fn set_watermark_observer(&mut self, observer: &'a mut dyn WatermarkObserver)
+    {
+        self.watermark_observer = Some(observer);
+    }
+
+    pub fn set_alloc_observer(&mut self, observer: &'a mut dyn AllocObserver)
+    {
+        self.alloc_observer = Some(observer);
+    }
+
+    /// Cap the payload bytes of each tag by `budgets`, indexed by the tag `tag_of` finds in the user data of a block,
+    /// so that a subsystem is refused by `alloc_bytes_with_data` once it reaches its budget.
+    /// The blocks whose tag is out of `budgets` are not capped; the blocks already allocated are counted now.
+    pub fn set_budgets(&mut self, budgets: &'a mut [Budget], tag_of: BudgetTagOf<M>)
+    {
+        for budget in budgets.iter_mut() {
+            budget.used = 0;
+        }
+
+        self.for_each_allocated(|tag| {
+            if let Some(budget) = budgets.get_mut(tag_of(&tag.user_data)) {
+                budget.used += tag.free_area_size();
+            }
+        });
+        self.budgets = Some((budgets, tag_of));
+    }
+
+    /// Notify `observer` of every allocation refused by the budgets.
+    pub fn set_budget_observer(&mut self, observer: &'a mut dyn BudgetObserver)
+    {
+        self.budget_observer = Some(observer);
+    }
+
+    /// The budget of `tag`, with the bytes allocated under it now.
+    pub fn budget(&self, tag: usize) -> Option<Budget>
+    {
+        self.budgets.as_ref().and_then(|(budgets, _)| budgets.get(tag).cloned())
+    }
+
+    fn account(&mut self, data: &M, size: usize, is_alloc: bool)
+    {
+        if let Some((ref mut budgets, tag_of)) = self.budgets {
+            if let Some(budget) = budgets.get_mut(tag_of(data)) {
+                if is_alloc {
+                    budget.used += size;
+                } else {
+                    budget.used -= size;
+                }
+            }
+        }
+    }
+
+    /// Whether `size` more bytes fit the budget of the tag of `data`; the observer is told when they do not.
+    fn within_budget(&mut self, data: &M, size: usize) -> bool
+    {
+        let request_size = self.request_size_of(size);
+        let tag =
+            match self.budgets {
+                None => return true,
+                Some((ref budgets, tag_of)) => {
+                    let tag = tag_of(data);
+                    match budgets.get(tag) {
+                        Some(budget) if budget.limit < budget.used.saturating_add(request_size) => tag,
+                        _ => return true,
+                    }
+                },
+            };
+
+        if let Some(ref mut observer) = self.budget_observer {
+            observer.on_budget_exceeded(tag);
+        }
+        false
+    }
+
+    /// Record a backtrace of every allocation from now on into `table`, for the leak reports on the host.
+    /// Capturing a backtrace is slow, so this is meant for tests and debugging sessions.
+    #[cfg(feature = "std")]
+    pub fn set_backtrace_table(&mut self, table: &'a mut BacktraceTable)
+    {
+        self.backtraces = Some(table);
+    }
+
+    pub fn stats(&self) -> HeapStats
+    {
+        HeapStats {
+            used_bytes: self.used_bytes,
+            used_blocks: self.used_blocks,
+            free_bytes: self.free_list_bytes,
+            largest_free_block: self.largest_free_block(),
+        }
+    }
+
+    /// The stats of a single region, telling a bank that is exhausted from one that is fragmented.
+    /// The blocks cached in the quick lists or pending for coalescing are counted as used here, and the holes of the region are not counted.
+    pub fn region_stats(&self, region: RegionId) -> Option<HeapStats>
+    {
+        if self.num_regions <= region.0 {
+            return None;
+        }
+
+        let mut stats = HeapStats {
+            used_bytes: 0,
+            used_blocks: 0,
+            free_bytes: 0,
+            largest_free_block: 0,
+        };
+        let mut tag_addr = Some(unsafe { self.tags[region.0].as_ref() }.addr());
+        while let Some(addr) = tag_addr {
+            let tag_ref = unsafe { &*(addr as *const BoundaryTag<M>) };
+            if tag_ref.is_reserved {
+                // The hole is neither used nor free.
+            } else if tag_ref.is_alloc {
+                stats.used_bytes  += tag_ref.free_area_size();
+                stats.used_blocks += 1;
+            } else {
+                stats.free_bytes         += tag_ref.free_area_size();
+                stats.largest_free_block  = cmp::max(stats.largest_free_block, tag_ref.free_area_size());
+            }
+            tag_addr = tag_ref.next_tag_addr();
+        }
+
+        Some(stats)
+    }
+
+    /// Write the stats in the Prometheus text exposition format.
+    pub fn stats_text<W>(&self, out: &mut W) -> fmt::Result
+        where W: fmt::Write
+    {
+        let stats   = self.stats();
+        let metrics = [
+            ("heap_used_bytes", "Bytes in the allocated blocks.", stats.used_bytes),
+            ("heap_used_blocks", "Number of the allocated blocks.", stats.used_blocks),
+            ("heap_free_bytes", "Bytes in the free lists.", stats.free_bytes),
+            ("heap_largest_free_block_bytes", "Size of the largest free block.", stats.largest_free_block),
+            ("heap_memory_level", "0 for normal, 1 for low and 2 for critical.", self.memory_level() as usize),
+        ];
+
+        for &(name, help, value) in metrics.iter() {
+            writeln!(out, "# HELP {} {}", name, help)?;
+            writeln!(out, "# TYPE {} gauge", name)?;
+            writeln!(out, "{} {}", name, value)?;
+        }
+
+        Ok(())
+    }
+
+    /// The largest free block is in the largest populated size class, so only that class is scanned.
+    fn largest_free_block(&self) -> usize
+    {
+        if self.free_list_bitmap == 0 {
+            return 0;
+        }
+
+        let class        = mem::size_of::<usize>() * 8 - 1 - self.free_list_bitmap.leading_zeros() as usize;
+        let mut largest  = 0;
+        let mut tag_addr = self.free_lists[class];
+        while let Some(addr) = tag_addr {
+            let tag_ref = unsafe { &*(addr as *const BoundaryTag<M>) };
+            largest  = cmp::max(largest, tag_ref.free_area_size());
+            tag_addr = tag_ref.payload_link();
+        }
+
+        largest
+    }
+
+    /// The memory level for the bytes in the free lists.
+    /// The blocks cached in the quick lists or pending for coalescing are not counted as free.
+    pub fn memory_level(&self) -> MemoryLevel
+    {
+        if self.free_list_bytes < self.critical_watermark {
+            MemoryLevel::Critical
+        } else if self.free_list_bytes < self.low_watermark {
+            MemoryLevel::Low
+        } else {
+            MemoryLevel::Normal
+        }
+    }
+
+    pub(crate) fn update_level(&mut self)
+    {
+        self.adapt_policy();
+
+        let level = self.memory_level();
+        if level == self.level {
+            return;
+        }
+
+        #[cfg(feature = "log")]
+        {
+            if level == MemoryLevel::Normal {
+                log::trace!("memory level changed from {:?} to {:?}, {} bytes free", self.level, level, self.free_list_bytes);
+            } else {
+                log::warn!("memory level changed from {:?} to {:?}, {} bytes free", self.level, level, self.free_list_bytes);
+            }
+        }
+
+        self.level = level;
+        if let Some(ref mut observer) = self.watermark_observer {
+            observer.on_level_change(level);
+        }
+    }
+
+    /// Round each request up to the smallest of `size_classes`, which must be sorted in ascending order.
+    /// Fewer distinct block sizes make the freed blocks easier to reuse at the cost of internal fragmentation.
+    /// The requests larger than every class are not rounded.
+    pub fn set_size_classes(&mut self, size_classes: &'a [usize])
+    {
+        debug_check!(size_classes.windows(2).all(|w| w[0] < w[1]));
+        self.size_classes = size_classes;
+    }
+
+    pub(crate) fn request_size_of(&self, size: usize) -> usize
+    {
+        let size = cmp::max(size, MIN_PAYLOAD_SIZE);
+        let size =
+            match self.size_classes.iter().find(|&&class_size| size <= class_size) {
+                None => size,
+                Some(&class_size) => class_size,
+            };
+
+        round_up(size, mem::align_of::<BoundaryTag<M>>())
+    }
+
+    /// The head tags of the managed regions.
+    pub(crate) fn regions(&self) -> &[Unique<BoundaryTag<M>>]
+    {
+        &self.tags[..self.num_regions]
+    }
+
+    /// The region containing `addr`, a tag or a payload.
+    pub fn region_of(&self, addr: usize) -> Option<RegionId>
+    {
+        let addr = untag(addr);
+        (0..self.num_regions).find(|&i| {
+            let (begin, end) = self.region_bounds(i);
+            begin <= addr && addr < end
+        }).map(RegionId)
+    }
+
+    /// The address range of the region, from its head tag to the end of its last payload.
+    pub fn region_range(&self, region: RegionId) -> Option<Range<usize>>
+    {
+        if self.num_regions <= region.0 {
+            return None;
+        }
+
+        let (begin, end) = self.region_bounds(region.0);
+        Some(begin..end)
+    }
+
+    fn region_bounds(&self, i: usize) -> (usize, usize)
+    {
+        let head = unsafe { self.tags[i].as_ref() };
+        let last = head.last_of_chain();
+        (head.addr(), last.addr_free_area() + last.free_area_size())
+    }
+
+    /// Search the free blocks of the regions in the order of `preference` first, such as an internal SRAM before an external SDRAM,
+    /// and the other regions only when none of them has room.
+    /// Each preferred region costs a scan of the free lists; the cached blocks are still taken wherever they are.
+    pub fn set_region_preference(&mut self, preference: &'a [RegionId])
+    {
+        self.region_preference = preference;
+    }
+
+    /// Allocate a block satisfying `layout` from `region` only, such as a buffer that has to be in a tightly-coupled memory.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn malloc_in_region(&mut self, region: RegionId, layout: Layout) -> Result<usize, AllocError>
+    {
+        if self.num_regions <= region.0 {
+            return Err(AllocError::NoRegion);
+        }
+
+        self.target_region = Some(self.region_bounds(region.0));
+        let result = self.try_alloc(layout);
+        self.target_region = None;
+        result
+    }
+
+    /// Move the movable blocks of `region` into the other regions, so that `try_remove_region` can take the region away.
+    /// `on_move` receives the old and the new payload addresses and the payload size of every moved block, as by `compact`,
+    /// and the blocks are reported as moved to the observers rather than freed and allocated again.
+    /// Return the number of the moved blocks; a block is left where it is when no other region has room for it.
+    pub fn migrate_region<F>(&mut self, region: RegionId, mut on_move: F) -> usize
+        where F: FnMut(usize, usize, usize)
+    {
+        if self.num_regions <= region.0 {
+            return 0;
+        }
+
+        // The cached blocks would stay allocated in the region otherwise.
+        self.unprotect_metadata();
+        self.flush_caches();
+
+        let mut count    = 0;
+        let mut tag_addr = Some(unsafe { self.tags[region.0].as_ref() }.addr());
+        while let Some(addr) = tag_addr {
+            let tag     = unsafe { BoundaryTag::<M>::new_from_addr(addr) };
+            let tag_ref = unsafe { tag.as_ref() };
+            tag_ref.verify_checksum();
+            if !tag_ref.is_alloc || !tag_ref.is_movable {
+                tag_addr = tag_ref.next_tag_addr();
+                continue;
+            }
+
+            let old_addr     = tag_ref.addr_free_area();
+            let size         = self.payload_size_of(tag_ref);
+            let request_size = self.request_size_of(size);
+            let align        = self.min_align;
+            let new_tag = (0..self.num_regions).filter(|&i| i != region.0).filter_map(|i| {
+                self.target_region = Some(self.region_bounds(i));
+                let new_tag = self.take_tag(request_size, align);
+                self.target_region = None;
+                new_tag
+            }).next();
+            let mut new_tag =
+                match new_tag {
+                    None => {
+                        tag_addr = tag_ref.next_tag_addr();
+                        continue;
+                    },
+                    Some(new_tag) => new_tag,
+                };
+
+            // The block keeps its owner, so it is accounted as moved rather than freed and allocated again.
+            {
+                let new_tag_mut = unsafe { new_tag.as_mut() };
+                new_tag_mut.is_movable = true;
+                new_tag_mut.user_data  = tag_ref.user_data;
+                #[cfg(feature = "zeroize")]
+                {
+                    new_tag_mut.is_secret = tag_ref.is_secret;
+                }
+                #[cfg(feature = "track-caller")]
+                {
+                    new_tag_mut.location = tag_ref.location;
+                }
+                new_tag_mut.update_checksum();
+            }
+            let new_ref  = unsafe { new_tag.as_ref() };
+            let new_addr = self.payload_ptr(new_ref);
+            unsafe { ptr::copy_nonoverlapping(old_addr as *const u8, new_addr as *mut u8, size) };
+
+            self.used_bytes = self.used_bytes - tag_ref.free_area_size() + new_ref.free_area_size();
+            self.account(&tag_ref.user_data, tag_ref.free_area_size(), false);
+            self.account(&new_ref.user_data, new_ref.free_area_size(), true);
+            if let Some(ref mut shadow) = self.shadow {
+                shadow.mark(old_addr, tag_ref.free_area_size(), false);
+                shadow.mark(new_ref.addr_free_area(), new_ref.free_area_size(), true);
+            }
+            on_move(old_addr, new_addr, size);
+            if let Some(ref mut observer) = self.alloc_observer {
+                observer.on_move(old_addr, new_addr);
+            }
+            #[cfg(feature = "std")]
+            {
+                if let Some(ref mut table) = self.backtraces {
+                    table.relocate(old_addr, new_addr);
+                }
+            }
+
+            // The old block is merged with a free block after it, so the walk goes on after that one.
+            tag_addr =
+                match BoundaryTag::<M>::next_tag_of(&tag) {
+                    Some(next_tag) if !unsafe { next_tag.as_ref() }.is_alloc => unsafe { next_tag.as_ref() }.next_tag_addr(),
+                    next_tag => next_tag.map(|next_tag| next_tag.as_ptr() as usize),
+                };
+            self.clear_payload_tags(tag_ref);
+            self.wipe_payload(tag);
+            if tag_ref.is_guarded {
+                self.remove_guard_page(tag);
+            }
+            self.release(tag);
+            count += 1;
+        }
+        self.update_level();
+        self.protect_metadata();
+
+        count
+    }
+
+    /// Stop managing `region` and give its memory back, such as a DRAM bank to be powered down.
+    /// The region has to hold no allocated block, after `migrate_region` if it holds movable ones; the cached blocks are released first.
+    /// The regions after it shift down by one index, so a `RegionId` of them or a preference set by `set_region_preference` has to be made again.
+    pub fn try_remove_region(&mut self, region: RegionId) -> Result<MemoryRegion, RemoveRegionError>
+    {
+        if self.num_regions <= region.0 {
+            return Err(RemoveRegionError::NoRegion);
+        }
+
+        self.unprotect_metadata();
+        self.flush_caches();
+
+        let head = self.tags[region.0];
+        let mut live     = 0;
+        let mut tag_addr = Some(unsafe { head.as_ref() }.addr());
+        while let Some(addr) = tag_addr {
+            let tag_ref = unsafe { &*(addr as *const BoundaryTag<M>) };
+            if tag_ref.is_alloc && !tag_ref.is_reserved {
+                live += 1;
+            }
+            tag_addr = tag_ref.next_tag_addr();
+        }
+        if live != 0 {
+            self.protect_metadata();
+            return Err(RemoveRegionError::InUse(live));
+        }
+
+        // Every block is free and coalesced, so only the holes split the region into several free blocks.
+        let (begin, end) = self.region_bounds(region.0);
+        let mut tag_addr = Some(begin);
+        while let Some(addr) = tag_addr {
+            let tag_ref = unsafe { &*(addr as *const BoundaryTag<M>) };
+            if !tag_ref.is_alloc {
+                self.remove_free_tag(tag_ref);
+            }
+            tag_addr = tag_ref.next_tag_addr();
+        }
+        for i in region.0..self.num_regions - 1 {
+            self.tags[i] = self.tags[i + 1];
+        }
+        self.num_regions -= 1;
+        self.update_level();
+        self.protect_metadata();
+
+        Ok(MemoryRegion::new(begin, end - begin))
+    }
+
+    /// Take over the regions and the free blocks of `other`, such as a temporary boot heap.
+    /// A region physically adjacent to one of this manager joins its tag chain and the free blocks at the seam are merged;
+    /// every other region takes a spare slot of the tags given to `with_capacity`.
+    /// `other` is given back untouched if there are not enough spare slots.
+    /// The huge blocks of `other` have to be freed before because its huge allocation source is not taken over.
+    // `other` is given back by value because there is no heap to box it in.
+    #[allow(clippy::result_large_err)]
+    pub fn absorb(&mut self, mut other: MemoryManager<'a, M, MIN_ALIGN>) -> Result<(), MemoryManager<'a, M, MIN_ALIGN>>
+    {
+        let num_separate = other.regions().iter().filter(|tag| self.adjacent_region_of(unsafe { tag.as_ref() }).is_none()).count();
+        if self.tags.len() - self.num_regions < num_separate {
+            return Err(other);
+        }
+
+        // The tags of `other` are written from here on, by `other` and then by this manager, so its protector leaves them writable.
+        other.unprotect_metadata();
+        self.unprotect_metadata();
+        // The cached blocks belong to the free lists of `other`, so they are released there first.
+        other.set_deferred_coalescing(false);
+        other.set_quick_lists(&[], 0);
+        self.used_bytes  += other.used_bytes;
+        self.used_blocks += other.used_blocks;
+
+        for i in 0..other.num_regions {
+            let head = other.tags[i];
+            let head_ref = unsafe { head.as_ref() };
+            BoundaryTag::<M>::rekey_chain(head, other.link_key, self.link_key);
+
+            let mut tag_addr = Some(head_ref.addr());
+            while let Some(addr) = tag_addr {
+                let tag_ref = unsafe { &*(addr as *const BoundaryTag<M>) };
+                if !tag_ref.is_alloc {
+                    self.insert_free_tag(tag_ref);
+                }
+                tag_addr = tag_ref.next_tag_addr();
+            }
+
+            match self.adjacent_region_of(head_ref) {
+                None => {
+                    self.tags[self.num_regions] = head;
+                    self.num_regions += 1;
+                },
+                Some((j, true)) => {
+                    let last = unsafe { BoundaryTag::<M>::new_from_addr(self.tags[j].as_ref().last_of_chain().addr()) };
+                    self.join(last, head);
+                },
+                Some((j, false)) => {
+                    let last = unsafe { BoundaryTag::<M>::new_from_addr(head_ref.last_of_chain().addr()) };
+                    let next_head = self.tags[j];
+                    self.join(last, next_head);
+                    self.tags[j] = head;
+                },
+            }
+        }
+
+        self.protect_metadata();
+
+        Ok(())
+    }
+
+    /// Carve a contiguous chunk of `size` bytes into an independent manager, so that a subsystem gets its own isolated heap.
+    /// The chunk, including the tag slice of the new manager, stays allocated in this manager.
+    /// The new manager keeps the minimum alignment and the size classes of this one.
+    pub fn split_off(&mut self, size: usize) -> Option<MemoryManager<'a, M, MIN_ALIGN>>
+    {
+        let size = size & !(mem::align_of::<BoundaryTag<M>>() - 1);
+        if size < mem::size_of::<BoundaryTag<M>>() + MIN_PAYLOAD_SIZE {
+            return None;
+        }
+
+        let tags_size = Self::tags_size_of(1);
+        let addr = self.alloc_aligned_bytes(tags_size + size, mem::align_of::<Unique<BoundaryTag<M>>>())?;
+
+        let tags    = unsafe { Self::tags_in_place(addr, tags_size + size, 1) };
+        let mut sub = MemoryManager::with_min_align(tags, 1);
+        sub.min_align    = self.min_align;
+        sub.size_classes = self.size_classes;
+        Some(sub)
+    }
+
+    /// Find the region physically adjacent to the one starting at `head`.
+    /// The flag tells whether `head` comes right after the found region.
+    fn adjacent_region_of(&self, head: &BoundaryTag<M>) -> Option<(usize, bool)>
+    {
+        let end = {
+            let last = head.last_of_chain();
+            last.addr_free_area() + last.free_area_size()
+        };
+
+        self.regions().iter().position(|tag| unsafe { tag.as_ref() }.addr() == end).map(|j| (j, false)).or_else(|| {
+            self.regions().iter().position(|tag| {
+                let last = unsafe { tag.as_ref() }.last_of_chain();
+                last.addr_free_area() + last.free_area_size() == head.addr()
+            }).map(|j| (j, true))
+        })
+    }
+
+    /// Link the last tag of a region to the head of the region right after it and merge them if both are free.
+    fn join(&mut self, mut last: Unique<BoundaryTag<M>>, mut head: Unique<BoundaryTag<M>>)
+    {
+        {
+            let last_mut = unsafe { last.as_mut() };
+            debug_check!(last_mut.addr_free_area() + last_mut.free_area_size() == head.as_ptr() as usize, "joining the regions which are not adjacent");
+            last_mut.set_last(false);
+            last_mut.update_checksum();
+
+            let head_mut = unsafe { head.as_mut() };
+            head_mut.set_prev_tag_addr(self.link_key, Some(last_mut.addr()));
+            head_mut.update_checksum();
+        }
+
+        if !unsafe { last.as_ref() }.is_alloc && !unsafe { head.as_ref() }.is_alloc {
+            self.remove_free_tag(unsafe { last.as_ref() });
+            self.remove_free_tag(unsafe { head.as_ref() });
+            let tag = BoundaryTag::<M>::merge(last, head, self.link_key);
+            self.insert_free_tag(unsafe { tag.as_ref() });
+        }
+    }
+
+    /// Serve the requests of `threshold` bytes or more with dedicated regions from `source`,
+    /// so that a giant allocation does not split the blocks used by the small ones.
+    /// The requests fall back to the managed regions when the source is exhausted.
+    pub fn set_huge_allocation_source(&mut self, threshold: usize, source: &'a mut dyn MemorySource)
+    {
+        self.huge_threshold = threshold;
+        self.huge_source    = Some(source);
+    }
+
+    fn alloc_huge(&mut self, request_size: usize, align: usize) -> Option<Unique<BoundaryTag<M>>>
+    {
+        let region = self.huge_source.as_mut()?.request(request_size + mem::size_of::<BoundaryTag<M>>())?;
+
+        // The tag has to stay at the head of the region to give it back, so a misaligned region cannot be used.
+        if !(region.addr() + mem::size_of::<BoundaryTag<M>>()).is_multiple_of(align) {
+            if let Some(ref mut source) = self.huge_source {
+                source.release(region);
+            }
+            return None;
+        }
+
+        let mut tag = BoundaryTag::<M>::with_user_data(region.addr(), region.size());
+        {
+            let tag_mut = unsafe { tag.as_mut() };
+            tag_mut.is_alloc = true;
+            tag_mut.is_huge  = true;
+            tag_mut.update_checksum();
+        }
+
+        Some(tag)
+    }
+
+    /// The share of the free bytes outside the largest free block in percent, 0 when the free memory is in one piece.
+    pub fn fragmentation(&self) -> usize
+    {
+        match (self.largest_free_block() * 100).checked_div(self.free_list_bytes) {
+            None => 0,
+            Some(percent) => 100 - percent,
+        }
+    }
+
+    /// Switch to the address-ordered free lists when the fragmentation reaches `high` percent and back to the segregated fit when it falls to `low`,
+    /// checking it every `interval` allocations and frees because finding the largest free block takes a scan.
+    pub fn set_adaptive_policy(&mut self, high: usize, low: usize, interval: usize)
+    {
+        debug_check!(low < high && interval != 0);
+        self.adaptive_policy = Some(AdaptivePolicy {
+            high,
+            low,
+            interval,
+            countdown: interval,
+        });
+    }
+
+    pub fn set_policy_observer(&mut self, observer: &'a mut dyn PolicyObserver)
+    {
+        self.policy_observer = Some(observer);
+    }
+
+    fn adapt_policy(&mut self)
+    {
+        match self.adaptive_policy {
+            None => return,
+            Some(ref mut policy) => {
+                policy.countdown -= 1;
+                if policy.countdown != 0 {
+                    return;
+                }
+                policy.countdown = policy.interval;
+            },
+        }
+
+        let fragmentation = self.fragmentation();
+        let (high, low) =
+            match self.adaptive_policy {
+                None => return,
+                Some(ref policy) => (policy.high, policy.low),
+            };
+        let mode =
+            if !self.address_ordered && high <= fragmentation {
+                FitMode::AddressOrdered
+            } else if self.address_ordered && fragmentation <= low {
+                FitMode::SegregatedFit
+            } else {
+                return;
+            };
+
+        #[cfg(feature = "log")]
+        log::trace!("fit policy changed to {:?} at {}% fragmentation", mode, fragmentation);
+
+        // Rebuilding the free lists writes the links in the free payloads.
+        self.unprotect_metadata();
+        self.set_address_ordered_free_lists(mode == FitMode::AddressOrdered);
+        self.protect_metadata();
+
+        if let Some(ref mut observer) = self.policy_observer {
+            observer.on_policy_change(mode, fragmentation);
+        }
+    }
+
+    /// Keep each free list sorted by address instead of pushing freed blocks at the head.
+    /// The searches then become address-ordered first fit, which keeps the allocations packed toward the low addresses.
+    pub fn set_address_ordered_free_lists(&mut self, enable: bool)
+    {
+        self.address_ordered = enable;
+
+        // Rebuild the lists from the tag chains so that they follow the new order.
+        self.unprotect_metadata();
+        self.free_lists       = [None; NUM_SIZE_CLASSES];
+        self.free_list_bitmap = 0;
+        self.free_list_bytes  = 0;
+        for i in 0..self.num_regions {
+            let mut tag_addr = Some(unsafe { self.tags[i].as_ref() }.addr());
+            while let Some(addr) = tag_addr {
+                let tag_ref = unsafe { &*(addr as *const BoundaryTag<M>) };
+                if !tag_ref.is_alloc {
+                    self.insert_free_tag(tag_ref);
+                }
+                tag_addr = tag_ref.next_tag_addr();
+            }
+        }
+        self.protect_metadata();
+    }
+
+    pub(crate) fn insert_free_tag(&mut self, tag: &BoundaryTag<M>)
+    {
+        let class         = size_class(tag.free_area_size());
+        let mut prev_addr = None;
+        let mut next_addr = self.free_lists[class];
+
+        if self.address_ordered {
+            while let Some(addr) = next_addr {
+                if tag.addr() < addr {
+                    break;
+                }
+                prev_addr = next_addr;
+                next_addr = unsafe { &*(addr as *const BoundaryTag<M>) }.payload_link();
+            }
+        }
+
+        tag.set_payload_link(next_addr);
+        tag.set_payload_back_link(prev_addr);
+
+        match prev_addr {
+            None       => self.free_lists[class] = Some(tag.addr()),
+            Some(addr) => unsafe { &*(addr as *const BoundaryTag<M>) }.set_payload_link(Some(tag.addr())),
+        }
+
+        if let Some(addr) = next_addr {
+            unsafe { &*(addr as *const BoundaryTag<M>) }.set_payload_back_link(Some(tag.addr()));
+        }
+
+        self.free_list_bitmap |= 1 << class;
+        self.free_list_bytes  += tag.free_area_size();
+    }
+
+    pub(crate) fn remove_free_tag(&mut self, tag: &BoundaryTag<M>)
+    {
+        let class     = size_class(tag.free_area_size());
+        let next_addr = tag.payload_link();
+        let prev_addr = tag.payload_back_link();
+
+        match prev_addr {
+            None       => self.free_lists[class] = next_addr,
+            Some(addr) => unsafe { BoundaryTag::<M>::new_from_addr(addr).as_ref() }.set_payload_link(next_addr),
+        }
+
+        if let Some(addr) = next_addr {
+            unsafe { BoundaryTag::<M>::new_from_addr(addr).as_ref() }.set_payload_back_link(prev_addr);
+        }
+
+        if self.free_lists[class].is_none() {
+            self.free_list_bitmap &= !(1 << class);
+        }
+        self.free_list_bytes -= tag.free_area_size();
+    }
+
+    /// Mark the tag as free, merge it with the free neighbors and put the result on its free list.
+    fn release(&mut self, mut tag: Unique<BoundaryTag<M>>)
+    {
+        {
+            let tag_mut = unsafe { tag.as_mut() };
+            tag_mut.is_alloc = false;
+            tag_mut.update_checksum();
+        }
+
+        let next_free_addr =
+            match BoundaryTag::<M>::next_tag_of(&tag) {
+                Some(ref next_tag) if !unsafe { next_tag.as_ref() }.is_alloc => Some(next_tag.as_ptr() as usize),
+                _ => None,
+            };
+        if let Some(addr) = next_free_addr {
+            let next_tag = unsafe { BoundaryTag::<M>::new_from_addr(addr) };
+            self.remove_free_tag(unsafe { next_tag.as_ref() });
+            tag = BoundaryTag::<M>::merge(tag, next_tag, self.link_key);
+        }
+
+        let prev_free_addr =
+            match BoundaryTag::<M>::prev_tag_of(&tag, self.link_key) {
+                Some(ref prev_tag) if !unsafe { prev_tag.as_ref() }.is_alloc => Some(prev_tag.as_ptr() as usize),
+                _ => None,
+            };
+        if let Some(addr) = prev_free_addr {
+            let prev_tag = unsafe { BoundaryTag::<M>::new_from_addr(addr) };
+            self.remove_free_tag(unsafe { prev_tag.as_ref() });
+            tag = BoundaryTag::<M>::merge(prev_tag, tag, self.link_key);
+        }
+
+        self.insert_free_tag(unsafe { tag.as_ref() });
+    }
+
+    /// Discard every allocation and turn each region back into a single free block, or a free block between each of its holes.
+    /// The huge blocks are not tracked, so they have to be freed before.
+    pub fn reset(&mut self)
+    {
+        self.unprotect_metadata();
+        for i in 0..MAX_QUICK_LISTS {
+            self.quick_lists[i] = QuickList::new(self.quick_lists[i].size, self.quick_lists[i].max_len);
+        }
+        self.pending_tag_addr.set(None);
+        self.free_lists       = [None; NUM_SIZE_CLASSES];
+        self.free_list_bitmap = 0;
+        self.free_list_bytes  = 0;
+        self.used_bytes       = 0;
+        self.used_blocks      = 0;
+        if let Some((ref mut budgets, _)) = self.budgets {
+            for budget in budgets.iter_mut() {
+                budget.used = 0;
+            }
+        }
+        // The reserved blocks are never in the shadow map, so no bit survives the reset.
+        if let Some(ref mut shadow) = self.shadow {
+            shadow.clear();
+        }
+
+        for i in 0..self.num_regions {
+            let mut free_tag: Option<Unique<BoundaryTag<M>>> = None;
+            let mut tag_addr = Some(unsafe { self.tags[i].as_ref() }.addr());
+            while let Some(addr) = tag_addr {
+                let mut tag = unsafe { BoundaryTag::<M>::new_from_addr(addr) };
+                tag_addr = unsafe { tag.as_ref() }.next_tag_addr();
+
+                if unsafe { tag.as_ref() }.is_reserved {
+                    if let Some(free_tag) = free_tag.take() {
+                        self.insert_free_tag(unsafe { free_tag.as_ref() });
+                    }
+                    continue;
+                }
+
+                // The guard page is mapped again and the secrets are wiped before the payload becomes part of a free block.
+                if unsafe { tag.as_ref() }.is_guarded {
+                    self.remove_guard_page(tag);
+                }
+                if unsafe { tag.as_ref() }.is_alloc {
+                    self.wipe_payload(tag);
+                }
+
+                {
+                    let tag_mut = unsafe { tag.as_mut() };
+                    tag_mut.is_alloc   = false;
+                    tag_mut.is_movable = false;
+                    #[cfg(feature = "zeroize")]
+                    {
+                        tag_mut.is_secret = false;
+                    }
+                    tag_mut.user_data = M::default();
+                    tag_mut.clear_location();
+                    tag_mut.update_checksum();
+                }
+                free_tag = Some(
+                    match free_tag {
+                        None => tag,
+                        Some(free_tag) => BoundaryTag::<M>::merge(free_tag, tag, self.link_key),
+                    });
+            }
+
+            if let Some(free_tag) = free_tag {
+                self.insert_free_tag(unsafe { free_tag.as_ref() });
+            }
+        }
+        self.protect_metadata();
+    }
+
+    /// Cache up to `max_len` freed blocks for each of the payload `sizes`.
+    /// The blocks cached for the previous sizes are released.
+    pub fn set_quick_lists(&mut self, sizes: &[usize], max_len: usize)
+    {
+        debug_check!(sizes.len() <= MAX_QUICK_LISTS);
+        debug_check!(sizes.iter().all(|&size| mem::size_of::<usize>() <= size));
+
+        self.unprotect_metadata();
+        for i in 0..MAX_QUICK_LISTS {
+            while let Some(tag) = self.quick_lists[i].pop() {
+                self.release(tag);
+            }
+
+            let size = if i < sizes.len() { sizes[i] } else { 0 };
+            self.quick_lists[i] = QuickList::new(size, max_len);
+        }
+        self.hot_size = None;
+        self.update_level();
+        self.protect_metadata();
+    }
+
+    /// Keep up to `count` blocks of `size` bytes split in advance by `maintain`, so that allocating that size is a pop from a ready list.
+    /// The hot size takes a quick list of its own; return `false` if all of them serve other sizes.
+    pub fn set_hot_size(&mut self, size: usize, count: usize) -> bool
+    {
+        let size = self.request_size_of(size);
+        let i =
+            match self.quick_lists.iter().position(|list| list.size == size || list.size == 0) {
+                None => return false,
+                Some(i) => i,
+            };
+
+        if self.quick_lists[i].size == 0 {
+            self.quick_lists[i] = QuickList::new(size, count);
+        } else {
+            self.quick_lists[i].max_len = count;
+        }
+        self.hot_size = Some(size);
+        true
+    }
+
+    /// Split at most `max_blocks` blocks of the hot size into its quick list and return how many were split.
+    pub fn presplit_hot_size(&mut self, max_blocks: usize) -> usize
+    {
+        let size =
+            match self.hot_size {
+                None => return 0,
+                Some(size) => size,
+            };
+        let i =
+            match self.quick_lists.iter().position(|list| list.size == size) {
+                None => return 0,
+                Some(i) => i,
+            };
+
+        self.unprotect_metadata();
+        let mut count = 0;
+        while count < max_blocks && self.quick_lists[i].len.get() < self.quick_lists[i].max_len {
+            let tag =
+                match self.find_free_tag(size + mem::size_of::<BoundaryTag<M>>() + self.split_threshold) {
+                    None => break,
+                    Some(tag) => tag,
+                };
+            unsafe {tag.as_ref()}.verify_checksum();
+            self.remove_free_tag(unsafe {tag.as_ref()});
+
+            let (tag, new_tag) = BoundaryTag::<M>::divide(tag, size, self.link_key);
+            self.insert_free_tag(unsafe {tag.as_ref()});
+            let mut new_tag =
+                match new_tag {
+                    None => break,
+                    Some(new_tag) => new_tag,
+                };
+
+            // The cached blocks stay marked as allocated.
+            let new_tag_mut = unsafe {new_tag.as_mut()};
+            new_tag_mut.is_alloc = true;
+            new_tag_mut.update_checksum();
+            self.quick_lists[i].push(new_tag_mut);
+            count += 1;
+        }
+
+        self.update_level();
+        self.protect_metadata();
+        count
+    }
+
+    /// Release cached blocks until each quick list but the one of the hot size is at most half full, releasing at most `max_blocks` blocks.
+    pub fn trim_quick_lists(&mut self, max_blocks: usize) -> usize
+    {
+        self.unprotect_metadata();
+        let mut count = 0;
+        for i in 0..MAX_QUICK_LISTS {
+            // The list of the hot size is kept full on purpose.
+            if Some(self.quick_lists[i].size) == self.hot_size {
+                continue;
+            }
+
+            while count < max_blocks && self.quick_lists[i].max_len / 2 < self.quick_lists[i].len.get() {
+                let tag =
+                    match self.quick_lists[i].pop() {
+                        None => break,
+                        Some(tag) => tag,
+                    };
+                self.release(tag);
+                count += 1;
+            }
+        }
+
+        self.update_level();
+        self.protect_metadata();
+        count
+    }
+
+    /// Make `free` only push the block onto a pending list instead of coalescing it.
+    /// The pending blocks stay marked as allocated until `coalesce` releases them.
+    /// Disabling the mode releases all the pending blocks.
+    pub fn set_deferred_coalescing(&mut self, enable: bool)
+    {
+        self.deferred_coalescing = enable;
+        if !enable {
+            self.coalesce(usize::MAX);
+        }
+    }
+
+    /// Release at most `max_blocks` pending blocks and return how many were released.
+    pub fn coalesce(&mut self, max_blocks: usize) -> usize
+    {
+        self.unprotect_metadata();
+        let mut count = 0;
+        while count < max_blocks {
+            let tag_addr =
+                match self.pending_tag_addr.get() {
+                    None => break,
+                    Some(addr) => addr,
+                };
+
+            let tag = unsafe { BoundaryTag::<M>::new_from_addr(tag_addr) };
+            self.pending_tag_addr.set(unsafe { tag.as_ref() }.payload_link());
+
+            self.release(tag);
+            count += 1;
+        }
+
+        self.update_level();
+        self.protect_metadata();
+        count
+    }
+
+    /// Walk every tag chain and free list and report the first inconsistency found.
+    pub fn check_integrity(&self) -> Result<(), IntegrityError>
+    {
+        let result = self.find_inconsistency();
+
+        #[cfg(feature = "log")]
+        {
+            if let Err(ref error) = result {
+                log::error!("heap is corrupted: {:?}", error);
+            }
+        }
+
+        result
+    }
+
+    /// Run a canned sequence of allocations, pattern writes, reallocations and frees on a heap built over `scratch`,
+    /// so that a kernel can catch a miscompiled or miswired heap during early boot.
+    /// The heap takes the alignment and the split threshold of this manager, which is not touched otherwise.
+    /// `scratch` must be valid for reads and writes and unused during the test; 4 KiB is enough.
+    pub fn self_test(&mut self, scratch: MemoryRegion) -> Result<(), SelfTestError>
+    {
+        const SIZES: [usize; 8]  = [1, 8, 24, 100, 16, 256, 40, 500];
+        const ALIGNS: [usize; 4] = [1, 16, 64, 128];
+
+        let mut mman =
+            match unsafe { MemoryManager::<M>::from_raw_parts(scratch.addr(), scratch.size()) } {
+                None => return Err(SelfTestError::ScratchTooSmall),
+                Some(mman) => mman,
+            };
+        mman.min_align       = self.min_align;
+        mman.split_threshold = self.split_threshold;
+        let initial          = mman.stats();
+
+        let pattern = |addr: usize| (addr >> 3) as u8 ^ 0xA5;
+        let verify  = |addr: usize, size: usize, byte: u8| {
+            if (0..size).all(|i| unsafe { *((addr + i) as *const u8) } == byte) { Ok(()) } else { Err(SelfTestError::Corrupted(addr)) }
+        };
+
+        let mut addrs = [0; 8];
+        for i in 0..SIZES.len() {
+            let align = cmp::max(ALIGNS[i % ALIGNS.len()], self.min_align);
+            let addr =
+                match mman.alloc_aligned_bytes(SIZES[i], align) {
+                    None => return Err(SelfTestError::AllocFailed(SIZES[i])),
+                    Some(addr) => addr,
+                };
+            if addr % align != 0 || addr < scratch.addr() || scratch.addr() + scratch.size() < addr + SIZES[i] {
+                return Err(SelfTestError::BadBlock(addr));
+            }
+            unsafe { (addr as *mut u8).write_bytes(pattern(addr), SIZES[i]) };
+            addrs[i] = addr;
+        }
+
+        // Free every other block to make holes between the live ones, then fill the holes again.
+        for i in (0..SIZES.len()).step_by(2) {
+            verify(addrs[i], SIZES[i], pattern(addrs[i]))?;
+            mman.free_bytes(addrs[i]);
+        }
+        mman.check_integrity().map_err(SelfTestError::Integrity)?;
+
+        for i in (0..SIZES.len()).step_by(2) {
+            let addr =
+                match mman.alloc_bytes(SIZES[i]) {
+                    None => return Err(SelfTestError::AllocFailed(SIZES[i])),
+                    Some(addr) => addr,
+                };
+            unsafe { (addr as *mut u8).write_bytes(pattern(addr), SIZES[i]) };
+            addrs[i] = addr;
+        }
+
+        // Growing a block keeps its contents wherever it goes.
+        let old_addr = addrs[0];
+        let new_size = 2 * SIZES[SIZES.len() - 1];
+        addrs[0] =
+            match mman.realloc_bytes(old_addr, new_size) {
+                None => return Err(SelfTestError::AllocFailed(new_size)),
+                Some(addr) => addr,
+            };
+        verify(addrs[0], SIZES[0], pattern(old_addr))?;
+
+        for i in 1..SIZES.len() {
+            verify(addrs[i], SIZES[i], pattern(addrs[i]))?;
+        }
+        for addr in addrs.iter() {
+            mman.free_bytes(*addr);
+        }
+        mman.check_integrity().map_err(SelfTestError::Integrity)?;
+
+        if mman.stats() != initial {
+            return Err(SelfTestError::NotRestored);
+        }
+
+        Ok(())
+    }
+
+    fn find_inconsistency(&self) -> Result<(), IntegrityError>
+    {
+        for tag in self.regions().iter() {
+            let mut tag_ref = unsafe { tag.as_ref() };
+            loop {
+                tag_ref.verify_checksum();
+
+                let next_tag_ref =
+                    match tag_ref.next_tag_addr() {
+                        None => break,
+                        Some(addr) => unsafe { &*(addr as *const BoundaryTag<M>) },
+                    };
+
+                if next_tag_ref.addr() != tag_ref.addr_free_area() + tag_ref.free_area_size() || next_tag_ref.prev_tag_addr(self.link_key) != Some(tag_ref.addr()) {
+                    return Err(IntegrityError::BrokenLink(tag_ref.addr()));
+                }
+
+                if !tag_ref.is_alloc && !next_tag_ref.is_alloc {
+                    return Err(IntegrityError::UncoalescedNeighbors(tag_ref.addr()));
+                }
+
+                tag_ref = next_tag_ref;
+            }
+        }
+
+        for class in 0..NUM_SIZE_CLASSES {
+            let mut prev_addr = None;
+            let mut tag_addr  = self.free_lists[class];
+            while let Some(addr) = tag_addr {
+                let tag_ref = unsafe { &*(addr as *const BoundaryTag<M>) };
+                if tag_ref.is_alloc {
+                    return Err(IntegrityError::AllocatedInFreeList(addr));
+                }
+
+                if size_class(tag_ref.free_area_size()) != class {
+                    return Err(IntegrityError::WrongSizeClass(addr));
+                }
+
+                if tag_ref.payload_back_link() != prev_addr {
+                    return Err(IntegrityError::BrokenLink(addr));
+                }
+
+                if self.address_ordered && prev_addr.is_some_and(|prev_addr| addr < prev_addr) {
+                    return Err(IntegrityError::UnorderedFreeList(addr));
+                }
+
+                prev_addr = tag_addr;
+                tag_addr  = tag_ref.payload_link();
+            }
+
+            if self.free_lists[class].is_some() != (self.free_list_bitmap & (1 << class) != 0) {
+                return Err(IntegrityError::BitmapMismatch(class));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether `addr` is inside one of the managed regions, including the tags.
+    pub fn contains(&self, addr: usize) -> bool
+    {
+        let addr = untag(addr);
+        self.regions().iter().any(|tag| {
+            let head = unsafe { tag.as_ref() };
+            let last = head.last_of_chain();
+            head.addr() <= addr && addr < last.addr_free_area() + last.free_area_size()
+        })
+    }
+
+    /// Find the block containing `addr`, which may point anywhere in its tag or payload.
+    /// The blocks cached in the quick lists or pending for coalescing are reported as allocated.
+    pub fn block_of(&self, addr: usize) -> Option<BlockInfo>
+    {
+        let addr = untag(addr);
+        for tag in self.regions().iter() {
+            let mut tag_addr = Some(unsafe { tag.as_ref() }.addr());
+            while let Some(a) = tag_addr {
+                let tag_ref = unsafe { &*(a as *const BoundaryTag<M>) };
+                if addr < tag_ref.addr() {
+                    break;
+                }
+
+                if addr < tag_ref.addr_free_area() + tag_ref.free_area_size() {
+                    return Some(BlockInfo {
+                        addr: tag_ref.addr_free_area(),
+                        size: tag_ref.free_area_size(),
+                        is_alloc: tag_ref.is_alloc,
+                    });
+                }
+                tag_addr = tag_ref.next_tag_addr();
+            }
+        }
+
+        None
+    }
+
+    /// Call `f` with every block that is still allocated and return how many were found.
+    /// The cached blocks are released first, so that only the blocks the program forgot to free are reported.
+    pub fn report_leaks<F>(&mut self, mut f: F) -> usize
+        where F: FnMut(Leak)
+    {
+        self.flush_caches();
+
+        let mut count = 0;
+        for tag in self.regions().iter() {
+            let mut tag_addr = Some(unsafe { tag.as_ref() }.addr());
+            while let Some(addr) = tag_addr {
+                let tag_ref = unsafe { &*(addr as *const BoundaryTag<M>) };
+                if tag_ref.is_alloc && !tag_ref.is_reserved {
+                    f(Leak {
+                        block: BlockInfo {
+                            addr: tag_ref.addr_free_area(),
+                            size: tag_ref.free_area_size(),
+                            is_alloc: true,
+                        },
+                        #[cfg(feature = "track-caller")]
+                        location: tag_ref.location,
+                    });
+                    count += 1;
+                }
+                tag_addr = tag_ref.next_tag_addr();
+            }
+        }
+
+        count
+    }
+
+    /// Release the blocks pending for coalescing and cached in the quick lists, so that every free block is in the free lists.
+    fn flush_caches(&mut self)
+    {
+        self.unprotect_metadata();
+        self.coalesce(usize::MAX);
+        for i in 0..MAX_QUICK_LISTS {
+            while let Some(tag) = self.quick_lists[i].pop() {
+                self.release(tag);
+            }
+        }
+        self.update_level();
+        self.protect_metadata();
+    }
+
+    /// Walk the blocks from the head of the first region.
+    /// The cached blocks are released first, so that the cursor sees every free block as free.
+    pub fn cursor<'m>(&'m mut self) -> BlockCursor<'m, 'a, M, MIN_ALIGN>
+    {
+        self.flush_caches();
+        BlockCursor::new(self)
+    }
+
+    /// Print every leaked block to the standard error with its backtrace, if recorded, and return how many were found.
+    #[cfg(feature = "std")]
+    pub fn print_leaks(&mut self) -> usize
+    {
+        let mut leaks = std::vec::Vec::new();
+        self.report_leaks(|leak| leaks.push(leak));
+
+        for leak in leaks.iter() {
+            eprintln!("leaked {} bytes at 0x{:x}", leak.block.size, leak.block.addr);
+            #[cfg(feature = "track-caller")]
+            {
+                if let Some(location) = leak.location {
+                    eprintln!("  allocated at {}", location);
+                }
+            }
+            if let Some(backtrace) = self.backtraces.as_ref().and_then(|table| table.get(leak.block.addr)) {
+                eprintln!("{}", backtrace);
+            }
+        }
+
+        leaks.len()
+    }
+
+    /// Perform at most `budget` housekeeping operations and return how many were performed.
+    /// Real-time systems can call this from their idle loop instead of paying the cost in `free`.
+    pub fn maintain(&mut self, budget: usize) -> usize
+    {
+        let count = self.coalesce(budget);
+        let count = count + self.trim_quick_lists(budget - count);
+        count + self.presplit_hot_size(budget - count)
+    }
+
+    /// Pick the block for each allocation at random among the `k` best fitting free blocks.
+    /// This makes the returned addresses harder to predict at the cost of a full scan of the free lists.
+    pub fn set_random_fit(&mut self, k: usize, entropy: &'a mut dyn EntropySource)
+    {
+        debug_check!(k != 0 && k <= MAX_RANDOM_FIT_CANDIDATES);
+        self.random_fit = Some((k, entropy));
+    }
+
+    fn find_free_tag(&mut self, request_size: usize) -> Option<Unique<BoundaryTag<M>>>
+    {
+        if let Some((begin, end)) = self.target_region {
+            return self.find_free_tag_in(request_size, begin, end);
+        }
+
+        let preference = self.region_preference;
+        for region in preference.iter().filter(|region| region.0 < self.num_regions) {
+            let (begin, end) = self.region_bounds(region.0);
+            if let Some(tag) = self.find_free_tag_in(request_size, begin, end) {
+                return Some(tag);
+            }
+        }
+
+        if self.random_fit.is_some() {
+            return self.find_random_fit_tag(request_size);
+        }
+
+        // Every block in a larger class fits, so the smallest populated one is taken without a scan.
+        let class = size_class(request_size);
+        let larger_classes =
+            if class + 1 < mem::size_of::<usize>() * 8 {
+                self.free_list_bitmap & (usize::MAX << (class + 1))
+            } else {
+                0
+            };
+        if larger_classes != 0 {
+            return self.free_lists[larger_classes.trailing_zeros() as usize].map(|addr| unsafe { BoundaryTag::<M>::new_from_addr(addr) });
+        }
+
+        // Fall back to the class of the request itself, where not every block fits.
+        let mut tag_addr = self.free_lists[class];
+        while let Some(addr) = tag_addr {
+            let tag_ref = unsafe { &*(addr as *const BoundaryTag<M>) };
+            if request_size <= tag_ref.free_area_size() {
+                return Some(unsafe { BoundaryTag::<M>::new_from_addr(addr) });
+            }
+            tag_addr = tag_ref.payload_link();
+        }
+
+        None
+    }
+
+    fn find_random_fit_tag(&mut self, request_size: usize) -> Option<Unique<BoundaryTag<M>>>
+    {
+        // Keep the k smallest fitting tags in ascending order of size.
+        let k =
+            match self.random_fit {
+                None => return None,
+                Some((k, _)) => k,
+            };
+        let mut candidates = [(0, 0); MAX_RANDOM_FIT_CANDIDATES];
+        let mut count      = 0;
+        for class in size_class(request_size)..NUM_SIZE_CLASSES {
+            let mut tag_addr = self.free_lists[class];
+            while let Some(addr) = tag_addr {
+                let tag_ref = unsafe { &*(addr as *const BoundaryTag<M>) };
+                tag_addr    = tag_ref.payload_link();
+
+                let size = tag_ref.free_area_size();
+                if size < request_size {
+                    continue;
+                }
+
+                let mut pos = count;
+                while 0 < pos && size < candidates[pos - 1].1 {
+                    pos -= 1;
+                }
+
+                if k <= pos {
+                    continue;
+                }
+
+                let end = if count < k { count } else { k - 1 };
+                let mut j = end;
+                while pos < j {
+                    candidates[j] = candidates[j - 1];
+                    j -= 1;
+                }
+                candidates[pos] = (addr, size);
+
+                if count < k {
+                    count += 1;
+                }
+            }
+        }
+
+        if count == 0 {
+            return None;
+        }
+
+        match self.random_fit {
+            None => None,
+            Some((_, ref mut entropy)) => {
+                let addr = candidates[entropy.next_usize() % count].0;
+                Some(unsafe { BoundaryTag::<M>::new_from_addr(addr) })
+            },
+        }
+    }
+
+    /// Allocate a block of `size` bytes and return the address of its payload.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn alloc_bytes(&mut self, size: usize) -> Option<usize>
+    {
+        let align = self.min_align;
+        self.alloc_aligned_bytes(size, align)
+    }
+
+    /// Allocate a block of `size` bytes whose payload is aligned to `align` at least.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn alloc_aligned_bytes(&mut self, size: usize, align: usize) -> Option<usize>
+    {
+        let align = cmp::max(align, self.min_align);
+        self.alloc_tag(size, align, false).ok().map(|tag| self.payload_ptr(unsafe { tag.as_ref() }))
+    }
+
+    /// Allocate a block of `size` bytes whose payload `ptr` satisfies `(ptr + offset) % align == 0`,
+    /// such as a buffer whose header comes before the aligned point.
+    /// Return `None` if no payload can satisfy it, because every payload is aligned to the tags and the offset breaks that alignment.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn alloc_aligned_offset(&mut self, size: usize, align: usize, offset: usize) -> Option<usize>
+    {
+        debug_check!(align.is_power_of_two());
+        let offset = offset & (align - 1);
+        if offset == 0 {
+            return self.alloc_aligned_bytes(size, align);
+        }
+        if !offset.is_multiple_of(mem::align_of::<BoundaryTag<M>>()) {
+            return None;
+        }
+
+        self.align_offset = offset;
+        let result = self.alloc_tag(size, align, false);
+        self.align_offset = 0;
+
+        result.ok().map(|tag| self.payload_ptr(unsafe { tag.as_ref() }))
+    }
+
+    /// Allocate a block like `alloc_aligned_bytes` and return the address of its payload together with its physical address.
+    /// The physical address is aligned only if the mapping keeps the alignment, as a mapping by pages does up to the page size.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn alloc_bytes_with_phys(&mut self, size: usize, align: usize) -> Option<(usize, usize)>
+    {
+        self.alloc_aligned_bytes(size, align).map(|addr| (addr, self.phys_addr_of(addr)))
+    }
+
+    /// Limit `alloc_dma` to `regions`, tried in this order; without them every region is taken as coherent.
+    pub fn set_dma_regions(&mut self, regions: &'a [DmaRegion])
+    {
+        self.dma_regions = regions;
+    }
+
+    /// Maintain the caches of the DMA buffers in the non-coherent regions by `maintenance`, whose cache lines are of `line_size` bytes.
+    pub fn set_cache_maintenance(&mut self, line_size: usize, maintenance: &'a mut dyn CacheMaintenance)
+    {
+        debug_check!(line_size.is_power_of_two());
+        self.cache_maintenance = Some((line_size, maintenance));
+    }
+
+    /// Allocate a buffer satisfying `layout` for a device, with its physical address.
+    /// In a non-coherent region the buffer is rounded out to whole cache lines, so that the cache operations on it never touch another block,
+    /// and it is cleaned and invalidated once so that no dirty line of a previous owner is written over the data of the device.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn alloc_dma(&mut self, layout: Layout) -> Result<DmaBuffer, AllocError>
+    {
+        if self.dma_regions.is_empty() {
+            let size = layout.size();
+            return self.try_alloc(layout).map(|addr| {
+                DmaBuffer {
+                    vaddr: addr,
+                    paddr: self.phys_addr_of(addr),
+                    len: size,
+                    coherent: true,
+                }
+            });
+        }
+
+        let line_size = self.cache_maintenance.as_ref().map_or(1, |&(line_size, _)| line_size);
+        let regions   = self.dma_regions;
+        let mut error = AllocError::NoRegion;
+        for dma_region in regions.iter() {
+            let (size, align) =
+                if dma_region.coherent {
+                    (layout.size(), layout.align())
+                } else {
+                    (round_up(layout.size(), line_size), cmp::max(layout.align(), line_size))
+                };
+            let dma_layout =
+                match Layout::from_size_align(size, align) {
+                    Err(_) => continue,
+                    Ok(dma_layout) => dma_layout,
+                };
+
+            match self.malloc_in_region(dma_region.region, dma_layout) {
+                Err(e) => error = e,
+                Ok(addr) => {
+                    let buffer = DmaBuffer {
+                        vaddr: addr,
+                        paddr: self.phys_addr_of(addr),
+                        len: size,
+                        coherent: dma_region.coherent,
+                    };
+                    self.sync_for_device(&buffer);
+                    self.sync_for_cpu(&buffer);
+                    return Ok(buffer);
+                },
+            }
+        }
+
+        Err(error)
+    }
+
+    /// Clean the caches of `buffer` before a device reads it.
+    pub fn sync_for_device(&mut self, buffer: &DmaBuffer)
+    {
+        if buffer.coherent {
+            return;
+        }
+        if let Some((_, ref mut maintenance)) = self.cache_maintenance {
+            maintenance.clean(buffer.vaddr, buffer.len);
+        }
+    }
+
+    /// Invalidate the caches of `buffer` after a device wrote it.
+    pub fn sync_for_cpu(&mut self, buffer: &DmaBuffer)
+    {
+        if buffer.coherent {
+            return;
+        }
+        if let Some((_, ref mut maintenance)) = self.cache_maintenance {
+            maintenance.invalidate(buffer.vaddr, buffer.len);
+        }
+    }
+
+    pub fn free_dma(&mut self, buffer: DmaBuffer)
+    {
+        self.free_bytes(buffer.vaddr);
+    }
+
+    /// Flag `regions` as executable, the only regions `alloc_exec` takes its blocks from.
+    pub fn set_exec_regions(&mut self, regions: &'a [RegionId])
+    {
+        self.exec_regions = regions;
+    }
+
+    /// Change the permissions of the executable blocks by `hook`, in pages of `page_size` bytes.
+    pub fn set_exec_hook(&mut self, page_size: usize, hook: &'a mut dyn ExecHook)
+    {
+        debug_check!(page_size.is_power_of_two());
+        self.exec_hook = Some((page_size, hook));
+    }
+
+    /// Allocate a writable block satisfying `layout` in an executable region, such as the code buffer of a JIT, to be sealed by `seal_exec`.
+    /// The payload takes whole pages, so that changing its permissions never touches a tag or another block.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn alloc_exec(&mut self, layout: Layout) -> Result<usize, AllocError>
+    {
+        let page_size = self.exec_hook.as_ref().map_or(1, |&(page_size, _)| page_size);
+        let exec_layout =
+            match Layout::from_size_align(round_up(cmp::max(layout.size(), 1), page_size), cmp::max(layout.align(), page_size)) {
+                Err(_) => return Err(AllocError::Exhausted { largest_free: self.largest_free_block(), total_free: self.free_list_bytes }),
+                Ok(exec_layout) => exec_layout,
+            };
+
+        let regions   = self.exec_regions;
+        let mut error = AllocError::NoRegion;
+        for &region in regions.iter() {
+            match self.malloc_in_region(region, exec_layout) {
+                Err(e) => error = e,
+                Ok(addr) => return Ok(addr),
+            }
+        }
+
+        Err(error)
+    }
+
+    /// The whole pages of the payload of the executable block at `addr`.
+    fn exec_pages_of(&self, addr: usize) -> usize
+    {
+        let page_size = self.exec_hook.as_ref().map_or(1, |&(page_size, _)| page_size);
+        self.usable_size(addr) & !(page_size - 1)
+    }
+
+    /// Make the block of `alloc_exec` at `addr` executable once its code is written; it cannot be written anymore.
+    pub fn seal_exec(&mut self, addr: usize)
+    {
+        let len = self.exec_pages_of(addr);
+        if let Some((_, ref mut hook)) = self.exec_hook {
+            hook.make_executable(addr, len);
+        }
+    }
+
+    /// Make the block of `alloc_exec` at `addr` writable again, such as to patch a trampoline.
+    pub fn unseal_exec(&mut self, addr: usize)
+    {
+        let len = self.exec_pages_of(addr);
+        if let Some((_, ref mut hook)) = self.exec_hook {
+            hook.make_writable(addr, len);
+        }
+    }
+
+    /// Free the block of `alloc_exec` at `addr`, making it writable first so that it can be reused for data.
+    pub fn free_exec(&mut self, addr: usize)
+    {
+        self.unseal_exec(addr);
+        self.free_bytes(addr);
+    }
+
+    /// Allocate a block that `compact` may move; the owner has to follow the moves by the callback of `compact`.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn alloc_movable_bytes(&mut self, size: usize) -> Option<usize>
+    {
+        let align = self.min_align;
+        self.alloc_tag(size, align, true).ok().map(|tag| self.payload_ptr(unsafe { tag.as_ref() }))
+    }
+
+    /// Allocate a block of `size` bytes carrying `data`, which `user_data` and `free_bytes_with_data` give back.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn alloc_bytes_with_data(&mut self, size: usize, data: M) -> Option<usize>
+    {
+        if !self.within_budget(&data, size) {
+            return None;
+        }
+
+        self.alloc_bytes(size).inspect(|&addr| self.set_user_data(addr, data))
+    }
+
+    /// The bytes the block whose payload is at `addr` can hold, including the slack and the rounding of the request,
+    /// so `realloc_bytes` up to this size never moves the block.
+    pub fn usable_size(&self, addr: usize) -> usize
+    {
+        let tag_ref = unsafe { &*((untag(addr) - mem::size_of::<BoundaryTag<M>>()) as *const BoundaryTag<M>) };
+        debug_check!(tag_ref.is_alloc);
+        self.payload_size_of(tag_ref)
+    }
+
+    /// The user data of the allocated block whose payload is at `addr`.
+    pub fn user_data(&self, addr: usize) -> M
+    {
+        let tag_ref = unsafe { &*((untag(addr) - mem::size_of::<BoundaryTag<M>>()) as *const BoundaryTag<M>) };
+        debug_check!(tag_ref.is_alloc);
+        tag_ref.user_data
+    }
+
+    pub fn set_user_data(&mut self, addr: usize, data: M)
+    {
+        let mut tag = unsafe { BoundaryTag::<M>::new_from_addr(untag(addr) - mem::size_of::<BoundaryTag<M>>()) };
+        debug_check!(unsafe { tag.as_ref() }.is_alloc);
+        // The block moves to the budget of its new tag, which may exceed the budget.
+        let (old_data, size) = {
+            let tag_ref = unsafe { tag.as_ref() };
+            (tag_ref.user_data, tag_ref.free_area_size())
+        };
+        self.account(&old_data, size, false);
+        self.account(&data, size, true);
+        self.unprotect_metadata();
+        unsafe { tag.as_mut() }.user_data = data;
+        self.protect_metadata();
+    }
+
+    /// Wipe the payload of every block when it is freed, not only of the blocks marked by `mark_secret`.
+    #[cfg(feature = "zeroize")]
+    pub fn set_zeroize_on_free(&mut self, enable: bool)
+    {
+        self.zeroize_all = enable;
+    }
+
+    /// Allocate a block of `size` bytes whose payload is wiped when it is freed, for keys and other secrets.
+    #[cfg(feature = "zeroize")]
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn alloc_secret_bytes(&mut self, size: usize) -> Option<usize>
+    {
+        self.alloc_bytes(size).inspect(|&addr| self.mark_secret(addr))
+    }
+
+    /// Wipe the payload of the allocated block at `addr` when it is freed, even if it is moved by `realloc_bytes` before.
+    #[cfg(feature = "zeroize")]
+    pub fn mark_secret(&mut self, addr: usize)
+    {
+        let mut tag = unsafe { BoundaryTag::<M>::new_from_addr(untag(addr) - mem::size_of::<BoundaryTag<M>>()) };
+        debug_check!(unsafe { tag.as_ref() }.is_alloc);
+        self.unprotect_metadata();
+        {
+            let tag_mut = unsafe { tag.as_mut() };
+            tag_mut.is_secret = true;
+            tag_mut.update_checksum();
+        }
+        self.protect_metadata();
+    }
+
+    /// Allocate a block satisfying `layout` and tell why it failed if it does.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn try_alloc(&mut self, layout: Layout) -> Result<usize, AllocError>
+    {
+        let align = cmp::max(layout.align(), self.min_align);
+        self.alloc_tag(layout.size(), align, false).map(|tag| self.payload_ptr(unsafe { tag.as_ref() }))
+    }
+
+    /// Allocate a block satisfying `layout` unless the free memory left would fall below the floor of `priority`.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn try_alloc_with_priority(&mut self, layout: Layout, priority: Priority) -> Result<usize, AllocError>
+    {
+        let align = cmp::max(layout.align(), self.min_align);
+        self.unprotect_metadata();
+        let result = self.alloc_tag_unprotected(layout.size(), align, priority).map(|tag| Self::set_movable(tag, false));
+        self.protect_metadata();
+
+        result.map(|tag| self.payload_ptr(unsafe { tag.as_ref() }))
+    }
+
+    /// Pin a free range satisfying `layout`, so that the following `commit` cannot fail.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn reserve(&mut self, layout: Layout) -> Result<Reservation, AllocError>
+    {
+        let align = cmp::max(layout.align(), self.min_align);
+        match self.alloc_tag(layout.size(), align, false) {
+            Err(e) => Err(e),
+            Ok(tag) => {
+                let tag_ref = unsafe { tag.as_ref() };
+                Ok(Reservation {
+                    addr: self.payload_ptr(tag_ref),
+                    capacity: self.payload_size_of(tag_ref),
+                })
+            },
+        }
+    }
+
+    /// Turn the reservation into an allocation and return the address of its payload.
+    pub fn commit(&mut self, reservation: Reservation) -> usize
+    {
+        reservation.addr
+    }
+
+    /// Turn the first `size` bytes of the reservation into an allocation and free the rest if it can be a block,
+    /// such as when a parser knows the length of what it built only at the end.
+    pub fn commit_with_size(&mut self, reservation: Reservation, size: usize) -> usize
+    {
+        debug_check!(size <= reservation.capacity);
+        self.shrink_in_place(reservation.addr, size);
+        reservation.addr
+    }
+
+    /// Give the reserved range back without using it.
+    /// The range is merged with its free neighbors at once, bypassing the quick lists and the deferred coalescing,
+    /// so that an aborted reservation leaves the heap as it was.
+    pub fn cancel(&mut self, reservation: Reservation)
+    {
+        self.unprotect_metadata();
+        self.free_tag_unprotected(reservation.addr, false);
+        self.protect_metadata();
+    }
+
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    fn alloc_tag(&mut self, size: usize, align: usize, is_movable: bool) -> Result<Unique<BoundaryTag<M>>, AllocError>
+    {
+        self.unprotect_metadata();
+        let result = self.alloc_tag_unprotected(size, align, Priority::Normal).map(|tag| Self::set_movable(tag, is_movable));
+        self.protect_metadata();
+        result
+    }
+
+    /// Mark the block just allocated as movable or not, while the tags are writable.
+    fn set_movable(mut tag: Unique<BoundaryTag<M>>, is_movable: bool) -> Unique<BoundaryTag<M>>
+    {
+        let tag_mut = unsafe { tag.as_mut() };
+        tag_mut.is_movable = is_movable;
+        tag_mut.update_checksum();
+        tag
+    }
+
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    fn alloc_tag_unprotected(&mut self, size: usize, align: usize, priority: Priority) -> Result<Unique<BoundaryTag<M>>, AllocError>
+    {
+        let request_size = self.request_size_of(size);
+        if self.max_blocks <= self.used_blocks || self.max_bytes < self.used_bytes.saturating_add(request_size) {
+            return Err(self.on_alloc_error(size, align, AllocError::QuotaExceeded));
+        }
+
+        let floor =
+            match priority {
+                Priority::Low => self.low_floor,
+                Priority::Normal => self.normal_floor,
+                Priority::High => 0,
+            };
+        if floor != 0 && self.free_list_bytes < floor.saturating_add(request_size) {
+            return Err(self.on_alloc_error(size, align, AllocError::BelowFloor));
+        }
+
+        // The slack is dropped rather than failing the allocation or going over the quota or the floor.
+        let slack_size = self.request_size_of(size.saturating_add(self.slack_of(size)));
+        let slack_size =
+            if self.max_bytes < self.used_bytes.saturating_add(slack_size) || (floor != 0 && self.free_list_bytes < floor.saturating_add(slack_size)) {
+                request_size
+            } else {
+                slack_size
+            };
+        let tag =
+            match self.take_tag(slack_size, align) {
+                None if slack_size != request_size => self.take_tag(request_size, align),
+                tag => tag,
+            };
+
+        match tag {
+            None => {
+                let error = self.shortage_error(request_size);
+                Err(self.on_alloc_error(size, align, error))
+            },
+            Some(mut tag) => {
+                // A reused block may still carry the data of its previous owner.
+                unsafe { tag.as_mut() }.user_data = M::default();
+                #[cfg(feature = "track-caller")]
+                {
+                    unsafe { tag.as_mut() }.location = Some(Location::caller());
+                }
+                self.charge(unsafe { tag.as_ref() });
+                self.update_level();
+                if let Some(ref mut observer) = self.alloc_observer {
+                    observer.on_alloc(unsafe { tag.as_ref() }.addr_free_area(), size, align);
+                }
+                Ok(tag)
+            },
+        }
+    }
+
+    /// Find a free block of `request_size` bytes at least between `begin` and `end`, scanning every populated class that may have one.
+    fn find_free_tag_in(&self, request_size: usize, begin: usize, end: usize) -> Option<Unique<BoundaryTag<M>>>
+    {
+        for class in size_class(request_size)..NUM_SIZE_CLASSES {
+            if self.free_list_bitmap & (1 << class) == 0 {
+                continue;
+            }
+
+            let mut tag_addr = self.free_lists[class];
+            while let Some(addr) = tag_addr {
+                let tag_ref = unsafe { &*(addr as *const BoundaryTag<M>) };
+                if begin <= addr && addr < end && request_size <= tag_ref.free_area_size() {
+                    return Some(unsafe { BoundaryTag::<M>::new_from_addr(addr) });
+                }
+                tag_addr = tag_ref.payload_link();
+            }
+        }
+
+        None
+    }
+
+    fn take_tag(&mut self, request_size: usize, align: usize) -> Option<Unique<BoundaryTag<M>>>
+    {
+        // The guarded and the huge blocks align their payloads only.
+        if let Some((threshold, page_size, _)) = self.guard_pages {
+            if threshold <= request_size && self.align_offset == 0 {
+                return self.take_guarded_tag(request_size, align, page_size);
+            }
+        }
+
+        // The huge and the cached blocks may be anywhere, so they cannot serve a request limited to a region.
+        if self.huge_threshold <= request_size && self.target_region.is_none() && self.align_offset == 0 {
+            if let Some(tag) = self.alloc_huge(request_size, align) {
+                return Some(tag);
+            }
+        }
+
+        // The cached blocks are aligned only to the heap alignment, without any offset.
+        if align <= self.min_align && self.target_region.is_none() && self.align_offset == 0 {
+            if let Some(list) = self.quick_lists.iter().find(|list| list.size == request_size) {
+                if let Some(tag) = list.pop() {
+                    return Some(tag);
+                }
+            }
+        }
+
+        let is_over_aligned = mem::align_of::<BoundaryTag<M>>() < align;
+        let search_size =
+            if is_over_aligned {
+                // Leave room to move the payload to an aligned address and still split the block.
+                request_size + align - 1 + mem::size_of::<BoundaryTag<M>>() + self.split_threshold
+            } else {
+                request_size
+            };
+
+        let tag = self.find_free_tag(search_size)?;
+        unsafe {tag.as_ref()}.verify_checksum();
+        self.remove_free_tag(unsafe {tag.as_ref()});
+
+        // The new block ends at the end of the free one, so its payload is aligned by extending it.
+        let alloc_size =
+            if is_over_aligned {
+                let end = unsafe {tag.as_ref()}.addr_free_area() + unsafe {tag.as_ref()}.free_area_size();
+                end - (((end - request_size + self.align_offset) & !(align - 1)) - self.align_offset)
+            } else {
+                request_size
+            };
+
+        // Take the whole block if the rest would be too small to be a free block.
+        let mut alloc_tag =
+            if alloc_size + mem::size_of::<BoundaryTag<M>>() + self.split_threshold <= unsafe {tag.as_ref()}.free_area_size() {
+                match BoundaryTag::<M>::divide(tag, alloc_size, self.link_key) {
+                    (tag, Some(new_tag)) => {
+                        self.insert_free_tag(unsafe {tag.as_ref()});
+                        new_tag
+                    },
+                    (tag, None) => tag,
+                }
+            } else {
+                tag
+            };
+
+        {
+            let t = unsafe {alloc_tag.as_mut()};
+            t.is_alloc = true;
+            t.update_checksum();
+        }
+
+        Some(alloc_tag)
+    }
+
+    /// Take a block whose payload ends at a page given to the `VmHook`, which stays in the block.
+    fn take_guarded_tag(&mut self, request_size: usize, align: usize, page_size: usize) -> Option<Unique<BoundaryTag<M>>>
+    {
+        let align       = cmp::max(align, self.min_align);
+        let search_size = request_size + align - 1 + 2 * page_size + mem::size_of::<BoundaryTag<M>>() + self.split_threshold;
+        let tag = self.find_free_tag(search_size)?;
+        unsafe {tag.as_ref()}.verify_checksum();
+        self.remove_free_tag(unsafe {tag.as_ref()});
+
+        let end        = unsafe {tag.as_ref()}.addr_free_area() + unsafe {tag.as_ref()}.free_area_size();
+        let guard_addr = (end - page_size) & !(page_size - 1);
+        let alloc_size = end - ((guard_addr - request_size) & !(align - 1));
+
+        let mut alloc_tag =
+            match BoundaryTag::<M>::divide(tag, alloc_size, self.link_key) {
+                (tag, Some(new_tag)) => {
+                    self.insert_free_tag(unsafe {tag.as_ref()});
+                    new_tag
+                },
+                (tag, None) => tag,
+            };
+
+        {
+            let t = unsafe {alloc_tag.as_mut()};
+            t.is_alloc   = true;
+            t.is_guarded = true;
+            t.update_checksum();
+        }
+
+        if let Some((_, _, ref mut hook)) = self.guard_pages {
+            hook.unmap(guard_addr);
+        }
+
+        Some(alloc_tag)
+    }
+
+    /// The address of the guard page in the payload of a guarded block, found as `take_guarded_tag` placed it.
+    fn guard_page_of(&self, tag: &BoundaryTag<M>) -> usize
+    {
+        let page_size = self.guard_pages.as_ref().map_or(1, |&(_, page_size, _)| page_size);
+        (tag.addr_free_area() + tag.free_area_size() - page_size) & !(page_size - 1)
+    }
+
+    /// Give the guard page of the block back to the heap.
+    fn remove_guard_page(&mut self, mut tag: Unique<BoundaryTag<M>>)
+    {
+        let guard_addr = self.guard_page_of(unsafe { tag.as_ref() });
+        if let Some((_, _, ref mut hook)) = self.guard_pages {
+            hook.remap(guard_addr);
+        }
+
+        let tag_mut = unsafe { tag.as_mut() };
+        tag_mut.is_guarded = false;
+        tag_mut.update_checksum();
+    }
+
+    /// The bytes of the payload the program may use, which end at the guard page of a guarded block.
+    fn payload_size_of(&self, tag: &BoundaryTag<M>) -> usize
+    {
+        if tag.is_guarded {
+            self.guard_page_of(tag) - tag.addr_free_area()
+        } else {
+            tag.free_area_size()
+        }
+    }
+
+    /// The address of the payload given to the program.
+    /// Under `mte` the payload gets a fresh random tag, which the address carries.
+    #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+    pub(crate) fn payload_ptr(&self, tag: &BoundaryTag<M>) -> usize
+    {
+        unsafe { mte::tag_granules(tag.addr_free_area(), self.payload_size_of(tag)) }
+    }
+
+    #[cfg(not(all(feature = "mte", target_arch = "aarch64")))]
+    pub(crate) fn payload_ptr(&self, tag: &BoundaryTag<M>) -> usize
+    {
+        tag.addr_free_area()
+    }
+
+    /// Give the payload of a freed block the tag of the allocator back, so that the pointers kept to it fault.
+    #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+    fn clear_payload_tags(&self, tag: &BoundaryTag<M>)
+    {
+        unsafe { mte::clear_granules(tag.addr_free_area(), self.payload_size_of(tag)) };
+    }
+
+    #[cfg(not(all(feature = "mte", target_arch = "aarch64")))]
+    fn clear_payload_tags(&self, _tag: &BoundaryTag<M>)
+    {
+    }
+
+    /// Zero the payload of a freed block holding secrets by volatile writes, which the compiler cannot drop as dead stores.
+    #[cfg(feature = "zeroize")]
+    fn wipe_payload(&self, mut tag: Unique<BoundaryTag<M>>)
+    {
+        let tag_ref = unsafe { tag.as_ref() };
+        if !self.zeroize_all && !tag_ref.is_secret {
+            return;
+        }
+
+        wipe_bytes(tag_ref.addr_free_area(), self.payload_size_of(tag_ref));
+
+        let tag_mut = unsafe { tag.as_mut() };
+        tag_mut.is_secret = false;
+        tag_mut.update_checksum();
+    }
+
+    #[cfg(not(feature = "zeroize"))]
+    fn wipe_payload(&self, _tag: Unique<BoundaryTag<M>>)
+    {
+    }
+
+    /// Prepare the bytes at `addr` cut off the payload of `tag` for the allocator:
+    /// give them the tag of the allocator back under `mte` and wipe them if the block holds secrets.
+    #[cfg_attr(not(any(feature = "zeroize", all(feature = "mte", target_arch = "aarch64"))), allow(unused_variables))]
+    fn scrub_bytes(&self, tag: &BoundaryTag<M>, addr: usize, size: usize)
+    {
+        #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+        unsafe { mte::clear_granules(addr, size) };
+
+        #[cfg(feature = "zeroize")]
+        {
+            if self.zeroize_all || tag.is_secret {
+                wipe_bytes(addr, size);
+            }
+        }
+    }
+
+    /// Cut the payload of the allocated block down to `new_size` bytes and free the rest as a block of its own, merged with the free block after it.
+    /// Nothing is cut if the rest could not be a free block by the split threshold, or from a guarded or a huge block.
+    fn release_tail(&mut self, tag: Unique<BoundaryTag<M>>, new_size: usize)
+    {
+        let tag_ref = unsafe { tag.as_ref() };
+        if tag_ref.is_guarded || tag_ref.is_huge {
+            return;
+        }
+
+        let keep_size = self.request_size_of(new_size);
+        let tail_size =
+            match tag_ref.free_area_size().checked_sub(keep_size + mem::size_of::<BoundaryTag<M>>()) {
+                None => return,
+                Some(tail_size) => tail_size,
+            };
+        if tail_size < self.split_threshold {
+            return;
+        }
+
+        let cut_addr = tag_ref.addr_free_area() + keep_size;
+        let cut_size = mem::size_of::<BoundaryTag<M>>() + tail_size;
+        self.scrub_bytes(tag_ref, cut_addr, cut_size);
+
+        self.unprotect_metadata();
+        self.used_bytes -= cut_size;
+        if let Some(ref mut shadow) = self.shadow {
+            shadow.mark(cut_addr, cut_size, false);
+        }
+        self.account(&tag_ref.user_data, cut_size, false);
+
+        if let (_, Some(tail_tag)) = BoundaryTag::<M>::divide(tag, tail_size, self.link_key) {
+            self.release(tail_tag);
+        }
+        self.update_level();
+        self.protect_metadata();
+    }
+
+    /// Slide the movable blocks toward the head of each region so that the free blocks between them merge.
+    /// The guarded blocks stay where they are, since their guard pages cannot be copied.
+    /// `on_move` receives the old and the new payload addresses and the payload size of every moved block.
+    /// Return the number of the moved blocks.
+    pub fn compact<F>(&mut self, mut on_move: F) -> usize
+        where F: FnMut(usize, usize, usize)
+    {
+        // The cached blocks are linked by their addresses, so they must not be moved.
+        self.unprotect_metadata();
+        self.flush_caches();
+
+        let mut count = 0;
+        for i in 0..self.num_regions {
+            let mut free_tag: Option<Unique<BoundaryTag<M>>> = None;
+            let mut tag_addr = Some(unsafe { self.tags[i].as_ref() }.addr());
+
+            while let Some(addr) = tag_addr {
+                let tag_ref = unsafe { &*(addr as *const BoundaryTag<M>) };
+                tag_ref.verify_checksum();
+
+                if !tag_ref.is_alloc {
+                    self.remove_free_tag(tag_ref);
+                    free_tag = Some(
+                        match free_tag {
+                            None => unsafe { BoundaryTag::<M>::new_from_addr(addr) },
+                            Some(free_tag) => BoundaryTag::<M>::merge(free_tag, unsafe { BoundaryTag::<M>::new_from_addr(addr) }, self.link_key),
+                        });
+                } else if let (true, Some(prev_free_tag)) = (tag_ref.is_movable && !tag_ref.is_guarded, free_tag) {
+                    let (old_addr, new_addr, size, moved_free_tag) = BoundaryTag::<M>::swap_with_prev_free(prev_free_tag, unsafe { BoundaryTag::<M>::new_from_addr(addr) }, self.link_key);
+                    // The old payload left behind the header of the free block may still hold secrets.
+                    let moved_tag = unsafe { &*((new_addr - mem::size_of::<BoundaryTag<M>>()) as *const BoundaryTag<M>) };
+                    let vacated   = unsafe { moved_free_tag.as_ref() }.addr_free_area();
+                    self.scrub_bytes(moved_tag, vacated, old_addr + size - vacated);
+                    on_move(old_addr, new_addr, size);
+                    if let Some(ref mut observer) = self.alloc_observer {
+                        observer.on_move(old_addr, new_addr);
+                    }
+                    #[cfg(feature = "std")]
+                    {
+                        if let Some(ref mut table) = self.backtraces {
+                            table.relocate(old_addr, new_addr);
+                        }
+                    }
+                    if let Some(ref mut shadow) = self.shadow {
+                        shadow.mark(old_addr, size, false);
+                        shadow.mark(new_addr, size, true);
+                    }
+                    free_tag = Some(moved_free_tag);
+                    count += 1;
+                } else if let Some(free_tag) = free_tag.take() {
+                    self.insert_free_tag(unsafe { free_tag.as_ref() });
+                }
+
+                tag_addr =
+                    match free_tag {
+                        Some(ref free_tag) => unsafe { free_tag.as_ref() }.next_tag_addr(),
+                        None => tag_ref.next_tag_addr(),
+                    };
+            }
+
+            if let Some(free_tag) = free_tag {
+                self.insert_free_tag(unsafe { free_tag.as_ref() });
+            }
+        }
+
+        self.protect_metadata();
+        count
+    }
+
+    /// Resize the block whose payload is at `addr` to `new_size` bytes and return the address of the payload.
+    /// The block is moved to a block aligned to the heap alignment if it is too small; it is left as it is when `None` is returned.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn realloc_bytes(&mut self, addr: usize, new_size: usize) -> Option<usize>
+    {
+        let tag_ref  = unsafe { &*((untag(addr) - mem::size_of::<BoundaryTag<M>>()) as *const BoundaryTag<M>) };
+        let old_size = self.payload_size_of(tag_ref);
+        if new_size <= old_size {
+            self.shrink_in_place(addr, new_size);
+            return Some(addr);
+        }
+        if self.grow_in_place(addr, new_size).is_ok() {
+            return Some(addr);
+        }
+
+        let new_addr = self.alloc_bytes(new_size)?;
+        unsafe { ptr::copy_nonoverlapping(addr as *const u8, new_addr as *mut u8, old_size) };
+        self.set_user_data(new_addr, tag_ref.user_data);
+        #[cfg(feature = "zeroize")]
+        {
+            if tag_ref.is_secret {
+                self.mark_secret(new_addr);
+            }
+        }
+        self.free_bytes(addr);
+
+        Some(new_addr)
+    }
+
+    /// Grow the block whose payload is at `addr` to `new_size` bytes at least by taking over the free block after it, and return the usable size.
+    /// The block is left as it is on `CannotGrow`, so that the caller can move it by itself.
+    pub fn grow_in_place(&mut self, addr: usize, new_size: usize) -> Result<usize, CannotGrow>
+    {
+        let tag     = unsafe { BoundaryTag::<M>::new_from_addr(untag(addr) - mem::size_of::<BoundaryTag<M>>()) };
+        let tag_ref = unsafe { tag.as_ref() };
+        tag_ref.verify_checksum();
+        debug_check!(tag_ref.is_alloc);
+
+        let old_size = self.payload_size_of(tag_ref);
+        if new_size <= old_size {
+            return Ok(old_size);
+        }
+        if tag_ref.is_guarded || tag_ref.is_huge {
+            return Err(CannotGrow);
+        }
+
+        let next_tag =
+            match BoundaryTag::<M>::next_tag_of(&tag) {
+                Some(next_tag) if !unsafe { next_tag.as_ref() }.is_alloc => next_tag,
+                _ => return Err(CannotGrow),
+            };
+        let next_ref     = unsafe { next_tag.as_ref() };
+        let added_size   = mem::size_of::<BoundaryTag<M>>() + next_ref.free_area_size();
+        let request_size = self.request_size_of(new_size);
+        if old_size + added_size < request_size || self.max_bytes < self.used_bytes.saturating_add(request_size - old_size) {
+            return Err(CannotGrow);
+        }
+
+        self.unprotect_metadata();
+        next_ref.verify_checksum();
+        self.remove_free_tag(next_ref);
+        let added_addr = next_ref.addr();
+        let tag        = BoundaryTag::<M>::merge(tag, next_tag, self.link_key);
+
+        let tag_ref = unsafe { tag.as_ref() };
+        self.used_bytes += added_size;
+        if let Some(ref mut shadow) = self.shadow {
+            shadow.mark(added_addr, added_size, true);
+        }
+        self.account(&tag_ref.user_data, added_size, true);
+        #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+        unsafe { mte::extend_tag(addr, added_addr, added_size) };
+
+        // The bytes beyond the request go back to the heap if they can be a block.
+        self.release_tail(tag, new_size);
+        self.update_level();
+        self.protect_metadata();
+
+        Ok(self.payload_size_of(tag_ref))
+    }
+
+    /// Shrink the block whose payload is at `addr` to `new_size` bytes, freeing the tail if it can be a block, and return the usable size.
+    pub fn shrink_in_place(&mut self, addr: usize, new_size: usize) -> usize
+    {
+        let tag = unsafe { BoundaryTag::<M>::new_from_addr(untag(addr) - mem::size_of::<BoundaryTag<M>>()) };
+        debug_check!(new_size <= self.payload_size_of(unsafe { tag.as_ref() }));
+
+        self.release_tail(tag, new_size);
+        self.payload_size_of(unsafe { tag.as_ref() })
+    }
+
+    /// Free the block whose payload is at `addr`.
+    /// The regions are not looked up: the tag is right before `addr` and the tags of both neighbors are linked from it.
+    /// Its cost is not bounded by a constant though: the quick lists are searched for the size of the block,
+    /// the address-ordered free lists of `set_address_ordered_free_lists` are walked to keep them sorted, and wiping or zeroizing touches the whole payload.
+    pub fn free_bytes(&mut self, addr: usize)
+    {
+        self.unprotect_metadata();
+        self.free_tag_unprotected(addr, true);
+        self.protect_metadata();
+    }
+
+    /// Free the block, caching it in a quick list or on the pending list if `may_cache` and the heap is configured so.
+    fn free_tag_unprotected(&mut self, addr: usize, may_cache: bool)
+    {
+        let tag  = unsafe { BoundaryTag::<M>::new_from_addr(untag(addr) - mem::size_of::<BoundaryTag<M>>()) };
+        let tag_ref = unsafe { tag.as_ref() };
+        tag_ref.verify_checksum();
+        debug_check!(!tag_ref.is_reserved);
+        self.uncharge(tag_ref);
+        self.clear_payload_tags(tag_ref);
+        self.wipe_payload(tag);
+
+        if tag_ref.is_guarded {
+            self.remove_guard_page(tag);
+        }
+
+        if tag_ref.is_huge {
+            let region = MemoryRegion::new(tag_ref.addr(), tag_ref.free_area_size() + mem::size_of::<BoundaryTag<M>>());
+            if let Some(ref mut source) = self.huge_source {
+                source.release(region);
+            }
+            return;
+        }
+
+        if may_cache && self.quick_lists.iter().any(|list| list.push(tag_ref)) {
+            return;
+        }
+
+        // A block too small to hold the pending link is released immediately.
+        if may_cache && self.deferred_coalescing && mem::size_of::<usize>() <= tag_ref.free_area_size() {
+            tag_ref.set_payload_link(self.pending_tag_addr.get());
+            self.pending_tag_addr.set(Some(tag_ref.addr()));
+            return;
+        }
+
+        self.release(tag);
+        self.update_level();
+    }
+
+    /// Free the block whose payload is at `addr` and return its user data, so that the owner can be updated.
+    pub fn free_bytes_with_data(&mut self, addr: usize) -> M
+    {
+        let data = self.user_data(addr);
+        self.free_bytes(addr);
+        data
+    }
+
+    /// Allocate up to `n` objects into `out` and return how many were allocated.
+    /// The objects are carved from the tail of each free block found, so a single search serves many objects.
+    pub fn malloc_many<T>(&mut self, n: usize, out: &mut [NonNull<T>]) -> usize
+    {
+        self.unprotect_metadata();
+        let count = self.malloc_many_unprotected(n, out);
+        self.protect_metadata();
+        count
+    }
+
+    fn malloc_many_unprotected<T>(&mut self, n: usize, out: &mut [NonNull<T>]) -> usize
+    {
+        let n            = cmp::min(n, out.len());
+        let request_size = self.request_size_of(mem::size_of::<T>());
+        let mut count    = 0;
+
+        // Carving assumes the natural alignment and the general heap, so the other cases go one by one,
+        // where the quota, the priority floors, the guard pages, the target region and the slack are applied per block as well.
+        let carvable = cmp::max(mem::align_of::<T>(), self.min_align) <= mem::align_of::<BoundaryTag<M>>() && request_size < self.huge_threshold
+            && self.max_blocks == usize::MAX && self.max_bytes == usize::MAX && self.low_floor == 0 && self.normal_floor == 0
+            && self.guard_pages.is_none() && self.target_region.is_none() && self.slack_percent == 0 && self.slack_bytes == 0;
+        if !carvable {
+            while count < n {
+                match self.alloc_aligned_bytes(mem::size_of::<T>(), mem::align_of::<T>()) {
+                    None => break,
+                    Some(addr) => out[count] = unsafe { NonNull::new_unchecked(addr as *mut T) },
+                }
+                count += 1;
+            }
+            return count;
+        }
+
+        while count < n {
+            let tag =
+                match self.find_free_tag(request_size) {
+                    None => break,
+                    Some(tag) => tag,
+                };
+            unsafe {tag.as_ref()}.verify_checksum();
+            self.remove_free_tag(unsafe {tag.as_ref()});
+
+            while count < n && request_size + mem::size_of::<BoundaryTag<M>>() + self.split_threshold <= unsafe {tag.as_ref()}.free_area_size() {
+                let mut new_tag =
+                    match BoundaryTag::<M>::divide(tag, request_size, self.link_key).1 {
+                        None => break,
+                        Some(new_tag) => new_tag,
+                    };
+                let new_tag_mut = unsafe {new_tag.as_mut()};
+                new_tag_mut.is_alloc = true;
+                new_tag_mut.update_checksum();
+                self.charge(new_tag_mut);
+                if let Some(ref mut observer) = self.alloc_observer {
+                    observer.on_alloc(new_tag_mut.addr_free_area(), mem::size_of::<T>(), mem::align_of::<T>());
+                }
+
+                out[count] = unsafe { NonNull::new_unchecked(self.payload_ptr(new_tag_mut) as *mut T) };
+                count += 1;
+            }
+
+            if count < n && request_size <= unsafe {tag.as_ref()}.free_area_size() {
+                // The rest cannot be split any more, so it is taken whole.
+                let mut tag = tag;
+                let tag_mut = unsafe {tag.as_mut()};
+                tag_mut.is_alloc   = true;
+                tag_mut.is_movable = false;
+                // A reused block may still carry the data of its previous owner.
+                tag_mut.user_data  = M::default();
+                tag_mut.clear_location();
+                tag_mut.update_checksum();
+                self.charge(tag_mut);
+                if let Some(ref mut observer) = self.alloc_observer {
+                    observer.on_alloc(tag_mut.addr_free_area(), mem::size_of::<T>(), mem::align_of::<T>());
+                }
+
+                out[count] = unsafe { NonNull::new_unchecked(self.payload_ptr(tag_mut) as *mut T) };
+                count += 1;
+            } else {
+                self.insert_free_tag(unsafe {tag.as_ref()});
+            }
+        }
+
+        self.update_level();
+        count
+    }
+
+    /// Allocate an array of `len` elements aligned for `T`.
+    /// Return `None` if the size overflows or the heap is exhausted.
+    #[cfg_attr(feature = "track-caller", track_caller)]
+    pub fn alloc_slice<T>(&mut self, len: usize) -> Option<NonNull<[T]>>
+    {
+        let size = len.checked_mul(mem::size_of::<T>())?;
+
+        // Nothing is allocated for an empty array, like the collections of the standard library.
+        let ptr =
+            if size == 0 {
+                NonNull::<T>::dangling().as_ptr()
+            } else {
+                self.alloc_aligned_bytes(size, mem::align_of::<T>())? as *mut T
+            };
+
+        Some(unsafe { NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(ptr, len)) })
+    }
+
+    /// Free an array allocated by `alloc_slice`.
+    pub fn free_slice<T>(&mut self, slice: NonNull<[T]>)
+    {
+        // The elements may never have been written, so no reference to them is made.
+        if slice.len() * mem::size_of::<T>() != 0 {
+            self.free_bytes(slice.as_ptr() as *mut T as usize);
+        }
+    }
+
+    /// Free all the blocks at once, which is cheaper than freeing them one by one because each free run is coalesced only once.
+    /// `ptrs` is sorted by address in place.
+    pub fn free_bulk(&mut self, ptrs: &mut [NonNull<u8>])
+    {
+        self.unprotect_metadata();
+
+        // The huge blocks go back to their source and are left out of the coalescing pass.
+        let mut len = ptrs.len();
+        let mut i   = 0;
+        while i < len {
+            // The pointers are sorted by address below, which a tag in the top bits would disturb.
+            ptrs[i] = unsafe { NonNull::new_unchecked(untag(ptrs[i].as_ptr() as usize) as *mut u8) };
+            let tag     = unsafe { BoundaryTag::<M>::new_from_addr(ptrs[i].as_ptr() as usize - mem::size_of::<BoundaryTag<M>>()) };
+            let tag_ref = unsafe { tag.as_ref() };
+            tag_ref.verify_checksum();
+            self.uncharge(tag_ref);
+            self.clear_payload_tags(tag_ref);
+            self.wipe_payload(tag);
+            if tag_ref.is_guarded {
+                self.remove_guard_page(tag);
+            }
+
+            if tag_ref.is_huge {
+                let region = MemoryRegion::new(tag_ref.addr(), tag_ref.free_area_size() + mem::size_of::<BoundaryTag<M>>());
+                if let Some(ref mut source) = self.huge_source {
+                    source.release(region);
+                }
+                len -= 1;
+                ptrs.swap(i, len);
+            } else {
+                i += 1;
+            }
+        }
+
+        let ptrs = &mut ptrs[..len];
+        ptrs.sort_unstable_by_key(|ptr| ptr.as_ptr() as usize);
+
+        for ptr in ptrs.iter() {
+            let mut tag = unsafe { BoundaryTag::<M>::new_from_addr(ptr.as_ptr() as usize - mem::size_of::<BoundaryTag<M>>()) };
+            let tag_mut = unsafe { tag.as_mut() };
+            tag_mut.is_alloc = false;
+            tag_mut.update_checksum();
+        }
+
+        // The freed blocks are not in the free lists yet, unlike the other free blocks.
+        let is_freed_now = |tag_addr: usize| {
+            ptrs.binary_search_by_key(&(tag_addr + mem::size_of::<BoundaryTag<M>>()), |ptr| ptr.as_ptr() as usize).is_ok()
+        };
+
+        // Each run is merged forward from its first freed block, so the blocks inside a run are skipped.
+        let mut run_end = 0;
+        for ptr in ptrs.iter() {
+            let tag_addr = ptr.as_ptr() as usize - mem::size_of::<BoundaryTag<M>>();
+            if tag_addr < run_end {
+                continue;
+            }
+
+            let mut tag = unsafe { BoundaryTag::<M>::new_from_addr(tag_addr) };
+            let prev_free_addr =
+                match BoundaryTag::<M>::prev_tag_of(&tag, self.link_key) {
+                    Some(ref prev_tag) if !unsafe { prev_tag.as_ref() }.is_alloc => Some(prev_tag.as_ptr() as usize),
+                    _ => None,
+                };
+            if let Some(addr) = prev_free_addr {
+                let prev_tag = unsafe { BoundaryTag::<M>::new_from_addr(addr) };
+                self.remove_free_tag(unsafe { prev_tag.as_ref() });
+                tag = BoundaryTag::<M>::merge(prev_tag, tag, self.link_key);
+            }
+
+            loop {
+                let next_free_addr =
+                    match BoundaryTag::<M>::next_tag_of(&tag) {
+                        Some(ref next_tag) if !unsafe { next_tag.as_ref() }.is_alloc => next_tag.as_ptr() as usize,
+                        _ => break,
+                    };
+
+                let next_tag = unsafe { BoundaryTag::<M>::new_from_addr(next_free_addr) };
+                if !is_freed_now(next_free_addr) {
+                    self.remove_free_tag(unsafe { next_tag.as_ref() });
+                }
+                tag = BoundaryTag::<M>::merge(tag, next_tag, self.link_key);
+            }
+
+            let tag_ref = unsafe { tag.as_ref() };
+            run_end = tag_ref.addr_free_area() + tag_ref.free_area_size();
+            self.insert_free_tag(tag_ref);
+        }
+
+        self.update_level();
+        self.protect_metadata();
+    }
+}
+
+impl<'a> MemoryManager<'a> {
+    /// Move `value` into a block that is guaranteed to stay at its address until it is dropped,
+    /// for the self-referential structures and the DMA descriptors.
+    pub fn alloc_pinned<'m, T>(&'m mut self, value: T) -> Option<Pin<Owned<'m, 'a, T>>>
+    {
+        // `Owned` allocates a block that is not movable, so `compact` never moves it.
+        Owned::new(self, value).map(|owned| unsafe { Pin::new_unchecked(owned) })
+    }
+}
+
+impl<'a, M: 'a + Copy + Default, const MIN_ALIGN: usize> Allocator for MemoryManager<'a, M, MIN_ALIGN> {
+    fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>>
+    {
+        self.alloc_aligned_bytes(layout.size(), layout.align()).map(|addr| unsafe { NonNull::new_unchecked(addr as *mut u8) })
+    }
+
+    fn deallocate(&mut self, ptr: NonNull<u8>, _: Layout)
+    {
+        self.free_bytes(ptr.as_ptr() as usize);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+    use core::mem;
+    use core::mem::MaybeUninit;
+    use core::ops::Range;
+    use core::ptr;
+    use core::ptr::NonNull;
+    use core::ptr::Unique;
+    use core::slice;
+    use std::string::String;
+
+    use super::MemoryManager;
+    use super::BlockInfo;
+    use super::HeapStats;
+    use super::MemoryLevel;
+    use super::WatermarkObserver;
+    use super::MetadataProtector;
+    use super::VmHook;
+    use super::{Budget, BudgetObserver, Priority, RegionId, CacheMaintenance, DmaRegion, ExecHook};
+    use policy::{FitMode, PolicyObserver};
+    use Allocator;
+    use BoundaryTag;
+    use EntropySource;
+    use MemorySource;
+    use error::{AllocError, CannotGrow, IntegrityError, RegionError, RemoveRegionError, SelfTestError};
+    use region::{LinearMapping, MemoryRegion};
+    #[cfg(feature = "std")]
+    use BacktraceTable;
+
+    use HostRegion;
+
+    use core::alloc::Layout;
+
+    fn allocate_memory() -> (usize, usize)
+    {
+        const SIZE: usize = 4096;
+        let addr = HostRegion::new(SIZE, 16).unwrap().leak().addr();
+
+        (addr, SIZE)
+    }
+
+    // #[test]
+    // fn test_all()
+    // {
+    //     let (addr, size) = allocate_memory();
+    //     let tag1 = BoundaryTag::from_memory(addr, size);
+
+    //     let mut tags = [tag1];
+    //     let mut mman = MemoryManager::new(&mut tags);
+
+    //     const SIZE: usize = 1024;
+    //     let slice_opt = mman.malloc::<[u8; SIZE]>();
+    //     assert_eq!(slice_opt.is_none(), false);
+    //     let slice = slice_opt.unwrap();
+
+    //     for i in &mut slice[..] {
+    //         *i = 0xAF;
+    //     }
+
+    //     for i in &slice[..] {
+    //         assert_eq!(*i, 0xAF);
+    //     }
+    // }
+
+    // #[test]
+    // #[should_panic]
+    // fn test_memory_manager_panic()
+    // {
+    //     let slice: &mut [&mut BoundaryTag] = &mut [];
+    //     let _ = MemoryManager::new(slice);
+    // }
+
+    struct FixedEntropy(usize);
+
+    impl EntropySource for FixedEntropy {
+        fn next_usize(&mut self) -> usize
+        {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_random_fit()
+    {
+        let (addr1, size1) = allocate_memory();
+        let (addr2, size2) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr1, size1), BoundaryTag::from_memory(addr2, size2 / 2)];
+
+        // The smaller region is the best fit, so the second candidate is the larger one.
+        let mut entropy = FixedEntropy(1);
+        let mut mman    = MemoryManager::new(&mut tags);
+        mman.set_random_fit(2, &mut entropy);
+
+        let obj  = mman.malloc::<[u8; 64]>().unwrap();
+        let addr = (obj as *const _) as usize;
+        assert!(addr1 <= addr && addr < addr1 + size1);
+    }
+
+    #[test]
+    fn test_free()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+
+        let obj = mman.malloc::<[u8; 128]>().unwrap();
+        mman.free(obj);
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+        assert!(BoundaryTag::next_tag_of(&mman.tags[0]).is_none());
+    }
+
+    #[test]
+    fn test_size_class()
+    {
+        assert_eq!(super::size_class(1), 0);
+        assert_eq!(super::size_class(2), 1);
+        assert_eq!(super::size_class(3), 1);
+        assert_eq!(super::size_class(4096), 12);
+        assert_eq!(super::size_class(usize::MAX), mem::size_of::<usize>() * 8 - 1);
+    }
+
+    #[test]
+    fn test_free_list_bitmap()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        let head_class = super::size_class(size - mem::size_of::<BoundaryTag>());
+        assert_eq!(mman.free_list_bitmap, 1 << head_class);
+
+        // Freeing the block next to the tail leaves it apart from the head block.
+        let obj1 = mman.malloc::<[u8; 128]>().unwrap();
+        let obj2 = mman.malloc::<[u8; 128]>().unwrap();
+        mman.free(obj1);
+        assert!(mman.free_list_bitmap & (1 << super::size_class(128)) != 0);
+
+        mman.free(obj2);
+        assert_eq!(mman.free_list_bitmap, 1 << head_class);
+    }
+
+    #[test]
+    fn test_address_ordered_free_lists()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        mman.set_address_ordered_free_lists(true);
+
+        let obj1 = mman.malloc::<[u8; 128]>().unwrap();
+        let _    = mman.malloc::<[u8; 128]>().unwrap();
+        let obj3 = mman.malloc::<[u8; 128]>().unwrap();
+        let _    = mman.malloc::<[u8; 128]>().unwrap();
+
+        // The block freed last has the lower address, so it must be placed first.
+        mman.free(obj1);
+        mman.free(obj3);
+        assert_eq!(mman.check_integrity(), Ok(()));
+
+        let head_addr = mman.free_lists[super::size_class(128)].unwrap();
+        assert_eq!(head_addr + mem::size_of::<BoundaryTag>(), (obj3 as *const _) as usize);
+    }
+
+    #[test]
+    fn test_block_of()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+
+        let obj = mman.alloc_bytes(128).unwrap();
+        assert!(mman.contains(obj + 64));
+        assert!(mman.contains(addr));
+        assert!(!mman.contains(addr + size));
+
+        let info = mman.block_of(obj + 64).unwrap();
+        assert_eq!(info, BlockInfo { addr: obj, size: 128, is_alloc: true });
+        assert_eq!(mman.block_of(obj - 1).map(|info| info.addr), Some(obj));
+        assert!(!mman.block_of(addr).unwrap().is_alloc);
+        assert!(mman.block_of(addr + size).is_none());
+    }
+
+    #[test]
+    fn test_absorb()
+    {
+        let (addr, size)   = allocate_memory();
+        let (addr2, size2) = allocate_memory();
+        let half = size / 2;
+        let mut tags        = [BoundaryTag::from_memory(addr + half, half), Unique::dangling()];
+        let mut other_tags1 = [BoundaryTag::from_memory(addr, half)];
+        let mut other_tags2 = [BoundaryTag::from_memory(addr2, size2)];
+        let mut mman        = MemoryManager::with_capacity(&mut tags, 1);
+        let mut other       = MemoryManager::new(&mut other_tags1);
+
+        // The region of the other manager is right before the one of this manager.
+        let obj = other.alloc_bytes(128).unwrap();
+        assert!(mman.absorb(other).is_ok());
+        assert_eq!(mman.num_regions, 1);
+        assert_eq!(mman.check_integrity(), Ok(()));
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.addr(), addr);
+
+        mman.free_bytes(obj);
+        assert_eq!(mman.check_integrity(), Ok(()));
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+
+        // A separate region takes the spare slot.
+        assert!(mman.absorb(MemoryManager::new(&mut other_tags2)).is_ok());
+        assert_eq!(mman.num_regions, 2);
+        assert_eq!(mman.check_integrity(), Ok(()));
+        assert!(mman.contains(addr2));
+    }
+
+    #[test]
+    fn test_split_off()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+
+        assert!(mman.split_off(size).is_none());
+        let mut sub = mman.split_off(1024).unwrap();
+        assert_eq!(unsafe { sub.tags[0].as_ref() }.free_area_size(), 1024 - mem::size_of::<BoundaryTag>());
+
+        // The sub-heap serves its allocations from its own chunk only.
+        let obj = sub.alloc_bytes(256).unwrap();
+        assert!(mman.block_of(obj).unwrap().is_alloc);
+        assert!(sub.alloc_bytes(1024).is_none());
+        assert!(mman.alloc_bytes(1024).is_some());
+
+        sub.free_bytes(obj);
+        assert_eq!(sub.check_integrity(), Ok(()));
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_empty_and_init()
+    {
+        let (addr, size) = allocate_memory();
+        let mut mman: MemoryManager = MemoryManager::empty();
+        assert!(mman.alloc_bytes(8).is_none());
+
+        assert!(!unsafe { mman.init(addr + 1, mem::size_of::<BoundaryTag>()) });
+        assert!(unsafe { mman.init(addr + 1, size - 1) });
+        assert_eq!(mman.check_integrity(), Ok(()));
+
+        let obj = mman.alloc_bytes(128).unwrap();
+        assert!(mman.contains(obj));
+        mman.free_bytes(obj);
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_add_region()
+    {
+        let addr = HostRegion::new(2 * 4096, 16).unwrap().leak().addr();
+        let mut mman: MemoryManager = MemoryManager::empty();
+        assert!(unsafe { mman.init_with_slots(addr, 4096, 2) });
+
+        // The adjacent memory joins the first region and merges with its free block.
+        assert_eq!(unsafe { mman.add_region(addr + 4096, 4096) }, Some(RegionId(0)));
+        assert_eq!(mman.region_range(RegionId(0)).unwrap().end, addr + 2 * 4096);
+        let obj = mman.alloc_bytes(6000).unwrap();
+        mman.free_bytes(obj);
+
+        let (other, size) = allocate_memory();
+        assert_eq!(unsafe { mman.add_region(other, size) }, Some(RegionId(1)));
+        let (another, size) = allocate_memory();
+        assert_eq!(unsafe { mman.add_region(another, size) }, None);
+        assert_eq!(unsafe { mman.add_region(other, 8) }, None);
+
+        let obj = mman.malloc_in_region(RegionId(1), Layout::from_size_align(128, 8).unwrap()).unwrap();
+        assert_eq!(mman.region_of(obj), Some(RegionId(1)));
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_from_raw_parts()
+    {
+        let (addr, size) = allocate_memory();
+        assert!(unsafe { MemoryManager::<()>::from_raw_parts(addr, 8) }.is_none());
+
+        let mut mman: MemoryManager = unsafe { MemoryManager::from_raw_parts(addr, size) }.unwrap();
+        let obj      = mman.alloc_bytes(1024).unwrap();
+        assert!(addr < obj && obj + 1024 <= addr + size);
+        mman.free_bytes(obj);
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_from_buffer()
+    {
+        let mut buffer: [MaybeUninit<u8>; 1024] = unsafe { MaybeUninit::uninit().assume_init() };
+        let mut mman: MemoryManager = MemoryManager::from_buffer(&mut buffer).unwrap();
+
+        let obj = mman.alloc_bytes(256).unwrap();
+        assert!(mman.alloc_bytes(1024).is_none());
+        mman.free_bytes(obj);
+        assert_eq!(mman.check_integrity(), Ok(()));
+
+        let mut tiny: [MaybeUninit<u8>; 8] = unsafe { MaybeUninit::uninit().assume_init() };
+        assert!(MemoryManager::<()>::from_buffer(&mut tiny).is_none());
+    }
+
+    #[test]
+    fn test_from_linker_symbols()
+    {
+        let (addr, size) = allocate_memory();
+        let start = addr as *mut u8;
+        let end   = (addr + size) as *mut u8;
+        assert!(unsafe { MemoryManager::<()>::from_linker_symbols(end, start) }.is_none());
+
+        let mut mman: MemoryManager = unsafe { MemoryManager::from_linker_symbols(start, end) }.unwrap();
+        assert!(mman.alloc_bytes(2048).is_some());
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_user_data()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::<u32>::with_user_data(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+
+        let obj1 = mman.alloc_bytes_with_data(64, 0xAF).unwrap();
+        let obj2 = mman.alloc_bytes(64).unwrap();
+        assert_eq!(mman.user_data(obj1), 0xAF);
+        assert_eq!(mman.user_data(obj2), 0);
+
+        mman.set_user_data(obj2, 7);
+        assert_eq!(mman.free_bytes_with_data(obj2), 7);
+        assert_eq!(mman.free_bytes_with_data(obj1), 0xAF);
+
+        // A new block starts with the default data.
+        let obj3 = mman.alloc_bytes(64).unwrap();
+        assert_eq!(mman.user_data(obj3), 0);
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "track-caller"), allow(unused_variables))]
+    fn test_report_leaks()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        mman.set_quick_lists(&[64], 4);
+
+        let (leaked, line) = (mman.alloc_bytes(128).unwrap(), line!());
+        let obj = mman.alloc_bytes(64).unwrap();
+        mman.free_bytes(obj);
+
+        let mut found = None;
+        assert_eq!(mman.report_leaks(|leak| found = Some(leak)), 1);
+
+        let leak = found.unwrap();
+        assert_eq!(leak.block.addr, leaked);
+        #[cfg(feature = "track-caller")]
+        {
+            let location = leak.location.unwrap();
+            assert_eq!(location.file(), file!());
+            assert_eq!(location.line(), line);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_backtrace_table()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags  = [BoundaryTag::from_memory(addr, size)];
+        let mut table = BacktraceTable::new();
+        let leaked;
+        {
+            let mut mman = MemoryManager::new(&mut tags);
+            mman.set_backtrace_table(&mut table);
+
+            leaked = mman.alloc_bytes(128).unwrap();
+            let obj = mman.alloc_bytes(64).unwrap();
+            mman.free_bytes(obj);
+            assert_eq!(mman.print_leaks(), 1);
+        }
+
+        assert_eq!(table.len(), 1);
+        assert!(table.get(leaked).is_some());
+    }
+
+    #[test]
+    fn test_check_integrity()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+
+        let obj = mman.malloc::<[u8; 128]>().unwrap();
+        assert_eq!(mman.check_integrity(), Ok(()));
+
+        // Break the link from the allocated block back to the head.
+        let obj_tag_addr = (obj as *const _) as usize - mem::size_of::<BoundaryTag>();
+        let obj_tag      = unsafe { &mut *(obj_tag_addr as *mut BoundaryTag) };
+        obj_tag.set_prev_tag_addr(mman.link_key, None);
+        obj_tag.update_checksum();
+        assert_eq!(mman.check_integrity(), Err(IntegrityError::BrokenLink(addr)));
+    }
+
+    #[test]
+    #[cfg(feature = "harden")]
+    fn test_link_secret_per_heap()
+    {
+        let (addr1, size1) = allocate_memory();
+        let (addr2, size2) = allocate_memory();
+        let mut tags1 = [BoundaryTag::from_memory(addr1, size1)];
+        let mut tags2 = [BoundaryTag::from_memory(addr2, size2)];
+        let mut mman1 = MemoryManager::with_link_secret(&mut tags1, 0x1234_5678);
+        let initial   = mman1.stats();
+        let obj1      = mman1.alloc_bytes(128).unwrap();
+
+        // Keying the second heap leaves the links of the first one as they are.
+        let mut mman2 = MemoryManager::with_link_secret(&mut tags2, 0x8765_4321);
+        let obj2      = mman2.alloc_bytes(128).unwrap();
+        assert_eq!(mman1.check_integrity(), Ok(()));
+        assert_eq!(mman2.check_integrity(), Ok(()));
+
+        let tag1 = unsafe { &*((obj1 - mem::size_of::<BoundaryTag>()) as *const BoundaryTag) };
+        assert_eq!(tag1.prev_tag_addr(mman1.link_key), Some(addr1));
+        assert!(tag1.prev_tag_addr(mman2.link_key) != Some(addr1));
+
+        // A new secret re-encodes the links already stored.
+        mman1.set_link_secret(0x0BAD_F00D);
+        assert_eq!(mman1.check_integrity(), Ok(()));
+        mman1.free_bytes(obj1);
+        mman2.free_bytes(obj2);
+        assert_eq!(mman1.stats(), initial);
+        assert_eq!(mman1.check_integrity(), Ok(()));
+        assert_eq!(mman2.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_self_test()
+    {
+        let (addr, size) = allocate_memory();
+        let mut mman: MemoryManager = MemoryManager::empty();
+        assert_eq!(mman.self_test(MemoryRegion::new(addr, size)), Ok(()));
+        assert_eq!(mman.self_test(MemoryRegion::new(addr, 32)), Err(SelfTestError::ScratchTooSmall));
+        match mman.self_test(MemoryRegion::new(addr, 512)) {
+            Err(SelfTestError::AllocFailed(_)) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    struct ProtectRecorder {
+        is_protected: bool,
+        range: Range<usize>,
+        count: usize,
+    }
+
+    impl MetadataProtector for ProtectRecorder {
+        fn protect(&mut self, range: Range<usize>)
+        {
+            assert!(!self.is_protected);
+            self.is_protected = true;
+            self.range        = range;
+            self.count       += 1;
+        }
+
+        fn unprotect(&mut self, range: Range<usize>)
+        {
+            assert!(self.is_protected);
+            assert_eq!(self.range, range);
+            self.is_protected = false;
+        }
+    }
+
+    #[test]
+    fn test_metadata_protector()
+    {
+        let (addr, size) = allocate_memory();
+        let mut protector = ProtectRecorder { is_protected: false, range: 0..0, count: 0 };
+        {
+            let mut tags = [BoundaryTag::from_memory(addr, size)];
+            let mut mman = MemoryManager::new(&mut tags);
+            mman.set_deferred_coalescing(true);
+            mman.set_metadata_protector(&mut protector);
+
+            // Nested operations, such as compact calling coalesce, protect the tags once at the end.
+            let obj = mman.alloc_bytes(64).unwrap();
+            mman.free_bytes(obj);
+            mman.compact(|_, _, _| ());
+        }
+
+        assert!(protector.is_protected);
+        assert_eq!(protector.range, addr..addr + size);
+        assert_eq!(protector.count, 4);
+    }
+
+    /// Protector failing when the bytes of `span` changed while they were protected.
+    struct WriteChecker<'c> {
+        span: Range<usize>,
+        sum: &'c Cell<Option<u64>>,
+    }
+
+    fn sum_of(span: &Range<usize>) -> u64
+    {
+        span.clone().fold(0xCBF2_9CE4_8422_2325, |sum, addr| (sum ^ unsafe { *(addr as *const u8) } as u64).wrapping_mul(0x100_0000_01B3))
+    }
+
+    impl<'c> MetadataProtector for WriteChecker<'c> {
+        fn protect(&mut self, _: Range<usize>)
+        {
+            self.sum.set(Some(sum_of(&self.span)));
+        }
+
+        fn unprotect(&mut self, _: Range<usize>)
+        {
+            assert_eq!(self.sum.get(), Some(sum_of(&self.span)), "the heap was written while protected");
+            self.sum.set(None);
+        }
+    }
+
+    #[test]
+    fn test_metadata_protector_covers_writes()
+    {
+        let host = HostRegion::new(8192, 16).unwrap();
+        let addr = host.addr();
+        let sum  = Cell::new(None);
+        let mut checker    = WriteChecker { span: addr..addr + 8192, sum: &sum };
+        let mut tags       = [BoundaryTag::from_memory(addr, 3072), BoundaryTag::from_memory(addr + 4096, 2048), Unique::dangling()];
+        let other_sum      = Cell::new(None);
+        let mut other_checker = WriteChecker { span: addr + 6144..addr + 8192, sum: &other_sum };
+        let mut other_tags = [BoundaryTag::from_memory(addr + 6144, 2048)];
+        let mut other      = MemoryManager::new(&mut other_tags);
+        let mut mman       = MemoryManager::with_capacity(&mut tags, 2);
+
+        // A block cached by `other` is released by `absorb`.
+        other.set_quick_lists(&[64], 4);
+        let cached = other.alloc_bytes(64).unwrap();
+        other.free_bytes(cached);
+        other.set_metadata_protector(&mut other_checker);
+        mman.set_metadata_protector(&mut checker);
+        let unchanged = || sum.get() == Some(sum_of(&(addr..addr + 8192)));
+
+        mman.set_quick_lists(&[64], 4);
+        let obj = mman.alloc_bytes(64).unwrap();
+        mman.free_bytes(obj);
+        mman.set_quick_lists(&[], 0);
+        assert!(unchanged());
+        mman.set_address_ordered_free_lists(true);
+        assert!(unchanged());
+
+        let obj    = mman.alloc_movable_bytes(128).unwrap();
+        let region = mman.region_of(obj).unwrap();
+        assert_eq!(mman.migrate_region(region, |_, _, _| ()), 1);
+        assert!(unchanged());
+        assert!(mman.try_remove_region(region).is_ok());
+        assert!(unchanged());
+        assert!(mman.absorb(other).is_ok());
+        assert!(unchanged());
+
+        mman.alloc_bytes(64).unwrap();
+        mman.reset();
+        assert!(unchanged());
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+
+    struct GuardRecorder {
+        unmapped: Option<usize>,
+    }
+
+    impl VmHook for GuardRecorder {
+        fn unmap(&mut self, addr: usize)
+        {
+            assert_eq!(self.unmapped, None);
+            self.unmapped = Some(addr);
+        }
+
+        fn remap(&mut self, addr: usize)
+        {
+            assert_eq!(self.unmapped, Some(addr));
+            self.unmapped = None;
+        }
+    }
+
+    #[test]
+    fn test_guard_pages()
+    {
+        const PAGE_SIZE: usize = 256;
+
+        let (addr, size) = allocate_memory();
+        let mut hook = GuardRecorder { unmapped: None };
+        {
+            let mut tags = [BoundaryTag::from_memory(addr, size)];
+            let mut mman = MemoryManager::new(&mut tags);
+            let initial  = mman.stats();
+            mman.set_guard_pages(512, PAGE_SIZE, &mut hook);
+
+            let small = mman.alloc_bytes(64).unwrap();
+            let large = mman.alloc_bytes(600).unwrap();
+            let guard = large + 600;
+            assert_eq!(guard % PAGE_SIZE, 0);
+            assert!(guard + PAGE_SIZE <= mman.block_of(large).map(|block| block.addr + block.size).unwrap());
+            assert_eq!(mman.check_integrity(), Ok(()));
+
+            mman.free_bytes(large);
+            mman.free_bytes(small);
+            assert_eq!(mman.check_integrity(), Ok(()));
+            assert_eq!(mman.stats(), initial);
+
+            // Compaction leaves a guarded block where it is, and a reset maps its guard page again.
+            let movable = mman.alloc_movable_bytes(600).unwrap();
+            let small   = mman.alloc_bytes(64).unwrap();
+            mman.free_bytes(small);
+            assert_eq!(mman.compact(|_, _, _| ()), 0);
+            assert_eq!(mman.block_of(movable).map(|block| block.addr), Some(movable));
+            mman.reset();
+            assert_eq!(mman.check_integrity(), Ok(()));
+            assert_eq!(mman.stats(), initial);
+        }
+        assert_eq!(hook.unmapped, None);
+    }
+
+    #[test]
+    fn test_shadow_map()
+    {
+        let (addr, size) = allocate_memory();
+        let mut bits = [0; 8];
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        let before   = mman.alloc_bytes(64).unwrap();
+        assert!(mman.is_allocated(before + 8));
+        assert!(!mman.is_allocated(before - 8));
+
+        assert!(mman.shadow_map_words() <= bits.len());
+        assert!(mman.set_shadow_map(&mut bits));
+        let obj1 = mman.alloc_bytes(100).unwrap();
+        let obj2 = mman.alloc_bytes(100).unwrap();
+
+        assert!(mman.is_allocated(before));
+        assert!(mman.is_allocated(obj1 + 99));
+        assert!(mman.is_valid_range(obj1, 100));
+        assert!(mman.is_valid_range(obj2 + 10, 50));
+        assert!(!mman.is_valid_range(obj1, 1000));
+        assert!(!mman.is_valid_range(addr, 16));
+        assert!(!mman.is_allocated(addr + size));
+
+        mman.free_bytes(obj1);
+        assert!(!mman.is_allocated(obj1));
+        assert!(!mman.is_valid_range(obj1, 100));
+        assert!(mman.is_valid_range(obj2, 100));
+
+        mman.free_bytes(obj2);
+        mman.free_bytes(before);
+        assert!(!mman.is_allocated(before));
+        assert_eq!(mman.check_integrity(), Ok(()));
+
+        // The blocks discarded by a reset are not allocated anymore.
+        let obj = mman.alloc_bytes(100).unwrap();
+        assert!(mman.is_allocated(obj));
+        mman.reset();
+        assert!(!mman.is_allocated(obj));
+        assert!(!mman.is_valid_range(obj, 100));
+
+        let (addr, size) = allocate_memory();
+        let mut short = [0; 1];
+        let mut tags  = [BoundaryTag::from_memory(addr, size)];
+        let mut mman  = MemoryManager::new(&mut tags);
+        assert!(!mman.set_shadow_map(&mut short));
+    }
+
+    struct BudgetRecorder {
+        exceeded: Option<usize>,
+    }
+
+    impl BudgetObserver for BudgetRecorder {
+        fn on_budget_exceeded(&mut self, tag: usize)
+        {
+            self.exceeded = Some(tag);
+        }
+    }
+
+    #[test]
+    fn test_budgets()
+    {
+        let (addr, size) = allocate_memory();
+        let mut budgets  = [Budget::new(usize::MAX), Budget::new(256)];
+        let mut recorder = BudgetRecorder { exceeded: None };
+        {
+            let mut tags = [BoundaryTag::with_user_data(addr, size)];
+            let mut mman = MemoryManager::<usize>::new(&mut tags);
+            let initial  = mman.stats();
+            let untagged = mman.alloc_bytes(64).unwrap();
+
+            mman.set_budgets(&mut budgets, |&tag| tag);
+            mman.set_budget_observer(&mut recorder);
+            assert_eq!(mman.budget(0).unwrap().used, 64);
+
+            let obj1 = mman.alloc_bytes_with_data(128, 1).unwrap();
+            let obj2 = mman.alloc_bytes_with_data(128, 1).unwrap();
+            assert_eq!(mman.budget(1).unwrap().used, 256);
+            assert_eq!(mman.alloc_bytes_with_data(16, 1), None);
+            assert_eq!(mman.budget(1).unwrap().used, 256);
+
+            // The tags out of the budgets are not capped.
+            let other = mman.alloc_bytes_with_data(512, 2).unwrap();
+
+            mman.free_bytes(obj1);
+            let obj1 = mman.alloc_bytes_with_data(16, 1).unwrap();
+            mman.set_user_data(obj1, 0);
+            assert_eq!(mman.budget(0).unwrap().used, 64 + 16);
+            assert_eq!(mman.budget(1).unwrap().used, 128);
+
+            mman.free_bytes(obj1);
+            mman.free_bytes(obj2);
+            mman.free_bytes(other);
+            mman.free_bytes(untagged);
+            assert_eq!(mman.budget(0).unwrap().used, 0);
+            assert_eq!(mman.budget(1).unwrap().used, 0);
+            assert_eq!(mman.stats(), initial);
+
+            // A moved block keeps its tag.
+            let old = mman.alloc_bytes_with_data(16, 1).unwrap();
+            let _   = mman.alloc_bytes(16).unwrap();
+            let obj = mman.realloc_bytes(old, 200).unwrap();
+            assert_ne!(obj, old);
+            assert_eq!(mman.user_data(obj), 1);
+            assert!(200 <= mman.budget(1).unwrap().used);
+
+            mman.reset();
+            assert_eq!(mman.budget(1).unwrap().used, 0);
+            assert_eq!(mman.stats(), initial);
+        }
+        assert_eq!(recorder.exceeded, Some(1));
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn test_zeroize()
+    {
+        // The head of a freed payload holds the links of the free lists.
+        const SKIPPED: usize = 32;
+        let bytes_of = |addr: usize| unsafe { core::slice::from_raw_parts((addr + SKIPPED) as *const u8, 128 - SKIPPED) };
+
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        let initial  = mman.stats();
+
+        let plain  = mman.alloc_bytes(128).unwrap();
+        let secret = mman.alloc_secret_bytes(64).unwrap();
+        unsafe { (plain as *mut u8).write_bytes(0xAA, 128) };
+        unsafe { (secret as *mut u8).write_bytes(0xAA, 64) };
+
+        // The secret survives the move to a larger block.
+        let secret = mman.realloc_bytes(secret, 128).unwrap();
+        unsafe { (secret as *mut u8).write_bytes(0xAA, 128) };
+        mman.free_bytes(secret);
+        mman.free_bytes(plain);
+        assert!(bytes_of(secret).iter().all(|&b| b == 0));
+        assert!(bytes_of(plain).iter().all(|&b| b == 0xAA));
+
+        mman.set_zeroize_on_free(true);
+        let plain = mman.alloc_bytes(128).unwrap();
+        unsafe { (plain as *mut u8).write_bytes(0xAA, 128) };
+        mman.free_bytes(plain);
+        assert!(bytes_of(plain).iter().all(|&b| b == 0));
+
+        assert_eq!(mman.check_integrity(), Ok(()));
+        assert_eq!(mman.stats(), initial);
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn test_zeroize_compact()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        let initial  = mman.stats();
+
+        let secret = mman.alloc_movable_bytes(128).unwrap();
+        let plain  = mman.alloc_bytes(128).unwrap();
+        mman.mark_secret(secret);
+        unsafe { (secret as *mut u8).write_bytes(0xAA, 128) };
+        mman.free_bytes(plain);
+
+        // The payload moves to the head of the region, wiping the bytes it leaves.
+        let mut moved = 0;
+        assert_eq!(mman.compact(|_, new_addr, _| moved = new_addr), 1);
+        assert!((0..128).all(|i| unsafe { *((secret + i) as *const u8) } == 0));
+        assert!((0..128).all(|i| unsafe { *((moved + i) as *const u8) } == 0xAA));
+
+        // The moved block is still a secret one.
+        mman.free_bytes(moved);
+        assert!((2 * mem::size_of::<usize>()..128).all(|i| unsafe { *((moved + i) as *const u8) } == 0));
+        assert_eq!(mman.check_integrity(), Ok(()));
+        assert_eq!(mman.stats(), initial);
+    }
+
+    struct TestSource {
+        requested: usize,
+        released: usize,
+    }
+
+    impl MemorySource for TestSource {
+        fn request(&mut self, size: usize) -> Option<MemoryRegion>
+        {
+            let (addr, region_size) = allocate_memory();
+            if region_size < size {
+                return None;
+            }
+
+            self.requested += 1;
+            Some(MemoryRegion::new(addr, region_size))
+        }
+
+        fn release(&mut self, _: MemoryRegion)
+        {
+            self.released += 1;
+        }
+    }
+
+    #[test]
+    fn test_huge_allocation()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags   = [BoundaryTag::from_memory(addr, size)];
+        let mut source = TestSource { requested: 0, released: 0 };
+        {
+            let mut mman = MemoryManager::new(&mut tags);
+            mman.set_huge_allocation_source(1024, &mut source);
+
+            let small = mman.malloc::<[u8; 128]>().unwrap();
+            let huge  = mman.malloc::<[u8; 2048]>().unwrap();
+            let huge_addr = (huge as *const _) as usize;
+            assert!(huge_addr < addr || addr + size <= huge_addr);
+            assert!(unsafe { &*((huge_addr - mem::size_of::<BoundaryTag>()) as *const BoundaryTag) }.is_huge);
+
+            mman.free(huge);
+            mman.free(small);
+            assert_eq!(mman.check_integrity(), Ok(()));
+        }
+
+        assert_eq!(source.requested, 1);
+        assert_eq!(source.released, 1);
+    }
+
+    #[test]
+    fn test_free_without_regions()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags   = [BoundaryTag::from_memory(addr, size)];
+        let mut source = TestSource { requested: 0, released: 0 };
+        {
+            let mut mman = MemoryManager::new(&mut tags);
+            mman.set_huge_allocation_source(1024, &mut source);
+            let initial  = mman.stats();
+
+            let obj1 = mman.alloc_bytes(128).unwrap();
+            let obj2 = mman.alloc_bytes(128).unwrap();
+            let huge = mman.alloc_bytes(2048).unwrap();
+
+            // Hide the regions, so that a free looking them up would miss the blocks.
+            let num_regions  = mman.num_regions;
+            mman.num_regions = 0;
+            mman.free_bytes(huge);
+            mman.free_bytes(obj1);
+            mman.free_bytes(obj2);
+            mman.num_regions = num_regions;
+
+            assert_eq!(mman.stats(), initial);
+            assert_eq!(mman.check_integrity(), Ok(()));
+        }
+
+        assert_eq!(source.released, 1);
+    }
+
+    #[test]
+    fn test_size_classes()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let classes  = [64, 256, 1024];
+        let mut mman = MemoryManager::new(&mut tags);
+        mman.set_size_classes(&classes);
+
+        assert_eq!(mman.request_size_of(1), 64);
+        assert_eq!(mman.request_size_of(64), 64);
+        assert_eq!(mman.request_size_of(65), 256);
+        assert_eq!(mman.request_size_of(1025), ::round_up(1025, mem::align_of::<BoundaryTag>()));
+
+        let obj     = mman.malloc::<[u8; 100]>().unwrap();
+        let tag_ref = unsafe { &*(((obj as *const _) as usize - mem::size_of::<BoundaryTag>()) as *const BoundaryTag) };
+        assert_eq!(tag_ref.free_area_size(), 256);
+    }
+
+    #[test]
+    fn test_min_align()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        mman.set_min_align(64);
+
+        let obj1 = mman.malloc::<[u8; 24]>().unwrap();
+        let obj2 = mman.malloc::<[u8; 100]>().unwrap();
+        assert_eq!((obj1 as *const _) as usize % 64, 0);
+        assert_eq!((obj2 as *const _) as usize % 64, 0);
+
+        mman.free(obj1);
+        mman.free(obj2);
+        assert_eq!(mman.check_integrity(), Ok(()));
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+    }
+
+    #[test]
+    fn test_const_min_align()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::<(), 64>::with_min_align(&mut tags, 1);
+
+        let obj1 = mman.alloc_bytes(24).unwrap();
+        let obj2 = mman.alloc_bytes(100).unwrap();
+        assert_eq!(obj1 % 64, 0);
+        assert_eq!(obj2 % 64, 0);
+
+        // A smaller alignment set at run time cannot go below `MIN_ALIGN`.
+        mman.set_min_align(8);
+        let obj3 = mman.alloc_bytes(8).unwrap();
+        assert_eq!(obj3 % 64, 0);
+
+        mman.free_bytes(obj1);
+        mman.free_bytes(obj2);
+        mman.free_bytes(obj3);
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_compact()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+
+        // Blocks are carved from the tail, so obj1 is the last one.
+        let obj1 = mman.alloc_movable_bytes(128).unwrap();
+        let obj2 = mman.alloc_bytes(128).unwrap();
+        let obj3 = mman.alloc_movable_bytes(128).unwrap();
+        let obj4 = mman.alloc_movable_bytes(128).unwrap();
+        unsafe { *(obj3 as *mut u64) = 0xAF };
+        mman.free_bytes(obj2);
+        mman.free_bytes(obj4);
+
+        let mut moves = [(0, 0, 0); 4];
+        let mut count = 0;
+        let moved = mman.compact(|old_addr, new_addr, len| {
+            moves[count] = (old_addr, new_addr, len);
+            count += 1;
+        });
+
+        assert_eq!(moved, 2);
+        assert_eq!(mman.check_integrity(), Ok(()));
+
+        // Both blocks moved toward the head and obj3 kept its contents.
+        assert_eq!(moves[0].0, obj3);
+        assert_eq!(unsafe { *(moves[0].1 as *const u64) }, 0xAF);
+        assert_eq!(moves[1].0, obj1);
+        assert!(moves[0].1 < obj3 && moves[1].1 < obj1);
+
+        // The free space is a single block.
+        let head = unsafe { mman.tags[0].as_ref() };
+        assert!(head.is_alloc);
+        mman.free_bytes(moves[0].1);
+        mman.free_bytes(moves[1].1);
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+    }
+
+    #[test]
+    fn test_reserve()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+
+        let reservation = mman.reserve(Layout::from_size_align(256, 128).unwrap()).unwrap();
+        assert!(mman.reserve(Layout::from_size_align(size, 1).unwrap()).is_err());
+
+        let obj = mman.commit(reservation);
+        assert_eq!(obj % 128, 0);
+        mman.free_bytes(obj);
+
+        let reservation = mman.reserve(Layout::from_size_align(256, 8).unwrap()).unwrap();
+        mman.cancel(reservation);
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+    }
+
+    #[test]
+    fn test_reserve_commit_with_size()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        let initial  = mman.stats();
+        mman.set_quick_lists(&[256], 4);
+        mman.set_deferred_coalescing(true);
+
+        // A canceled reservation is neither cached nor pending.
+        let reservation = mman.reserve(Layout::from_size_align(256, 8).unwrap()).unwrap();
+        assert_eq!(reservation.capacity(), 256);
+        mman.cancel(reservation);
+        assert_eq!(mman.stats(), initial);
+
+        let reservation = mman.reserve(Layout::from_size_align(1024, 8).unwrap()).unwrap();
+        unsafe { (reservation.addr() as *mut u8).write_bytes(0xAA, 100) };
+        let obj = mman.commit_with_size(reservation, 100);
+        assert_eq!(mman.usable_size(obj), mman.request_size_of(100));
+        assert_eq!(mman.stats().used_bytes, mman.request_size_of(100));
+        assert!((0..100).all(|i| unsafe { *((obj + i) as *const u8) } == 0xAA));
+
+        mman.free_bytes(obj);
+        mman.set_deferred_coalescing(false);
+        assert_eq!(mman.stats(), initial);
+    }
+
+    #[test]
+    fn test_quota()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        mman.set_quota(512, 2);
+
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        let obj1   = mman.try_alloc(layout).unwrap();
+        assert_eq!(mman.try_alloc(Layout::from_size_align(512, 8).unwrap()), Err(AllocError::QuotaExceeded));
+
+        let obj2 = mman.try_alloc(layout).unwrap();
+        assert_eq!(mman.try_alloc(Layout::from_size_align(8, 8).unwrap()), Err(AllocError::QuotaExceeded));
+
+        mman.free_bytes(obj1);
+        mman.free_bytes(obj2);
+        assert_eq!((mman.used_bytes, mman.used_blocks), (0, 0));
+
+        mman.set_quota(usize::MAX, usize::MAX);
+        let free = size - mem::size_of::<BoundaryTag>();
+        assert_eq!(mman.try_alloc(Layout::from_size_align(size, 8).unwrap()), Err(AllocError::Exhausted { largest_free: free, total_free: free }));
+    }
+
+    #[test]
+    fn test_priority_floors()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        let free     = size - mem::size_of::<BoundaryTag>();
+        mman.set_priority_floors(2048, 1024);
+
+        let layout = Layout::from_size_align(free - 2048, 8).unwrap();
+        let obj1   = mman.try_alloc_with_priority(layout, Priority::Low).unwrap();
+        assert_eq!(mman.try_alloc_with_priority(Layout::from_size_align(8, 8).unwrap(), Priority::Low), Err(AllocError::BelowFloor));
+
+        // The normal allocations go on down to their own floor.
+        let obj2 = mman.try_alloc(Layout::from_size_align(512, 8).unwrap()).unwrap();
+        assert_eq!(mman.try_alloc(Layout::from_size_align(512, 8).unwrap()), Err(AllocError::BelowFloor));
+        assert!(mman.alloc_bytes(512).is_none());
+
+        let obj3 = mman.try_alloc_with_priority(Layout::from_size_align(512, 8).unwrap(), Priority::High).unwrap();
+
+        mman.free_bytes(obj3);
+        mman.free_bytes(obj2);
+        mman.free_bytes(obj1);
+        assert_eq!(mman.stats().free_bytes, free);
+    }
+
+    #[test]
+    fn test_malloc_in_region()
+    {
+        let (addr1, size1) = allocate_memory();
+        let (addr2, size2) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr1, size1), BoundaryTag::from_memory(addr2, size2)];
+        let mut mman = MemoryManager::new(&mut tags);
+
+        let region1 = mman.region_of(addr1).unwrap();
+        let region2 = mman.region_of(addr2 + 100).unwrap();
+        assert_eq!(region2.index(), 1);
+        assert_eq!(mman.region_range(region2), Some(addr2..addr2 + size2));
+        assert_eq!(mman.region_range(RegionId(2)), None);
+
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        for &region in [region1, region2, region2, region1].iter() {
+            let obj = mman.malloc_in_region(region, layout).unwrap();
+            assert_eq!(mman.region_of(obj), Some(region));
+        }
+        assert_eq!(mman.malloc_in_region(RegionId(2), layout), Err(AllocError::NoRegion));
+        assert!(mman.malloc_in_region(region1, Layout::from_size_align(size1, 8).unwrap()).is_err());
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_region_preference()
+    {
+        let (addr1, size1) = allocate_memory();
+        let (addr2, size2) = allocate_memory();
+        let preference = [RegionId(1)];
+        let mut tags = [BoundaryTag::from_memory(addr1, size1), BoundaryTag::from_memory(addr2, size2)];
+        let mut mman = MemoryManager::new(&mut tags);
+        mman.set_region_preference(&preference);
+
+        // The preferred region is filled up before the other one is touched.
+        let obj1 = mman.alloc_bytes(2048).unwrap();
+        let obj2 = mman.alloc_bytes(1024).unwrap();
+        let obj3 = mman.alloc_bytes(2048).unwrap();
+        assert_eq!(mman.region_of(obj1), Some(RegionId(1)));
+        assert_eq!(mman.region_of(obj2), Some(RegionId(1)));
+        assert_eq!(mman.region_of(obj3), Some(RegionId(0)));
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_region_stats()
+    {
+        let (addr1, size1) = allocate_memory();
+        let (addr2, size2) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr1, size1), BoundaryTag::from_memory(addr2, size2)];
+        let mut mman = MemoryManager::new(&mut tags);
+        let layout   = Layout::from_size_align(1024, 8).unwrap();
+        let obj1     = mman.malloc_in_region(RegionId(1), layout).unwrap();
+        mman.malloc_in_region(RegionId(1), layout).unwrap();
+        mman.free_bytes(obj1);
+
+        let free = size1 - mem::size_of::<BoundaryTag>();
+        assert_eq!(mman.region_stats(RegionId(0)), Some(HeapStats { used_bytes: 0, used_blocks: 0, free_bytes: free, largest_free_block: free }));
+
+        let stats = mman.region_stats(RegionId(1)).unwrap();
+        assert_eq!((stats.used_bytes, stats.used_blocks), (1024, 1));
+        assert_eq!(stats.free_bytes, size2 - 3 * mem::size_of::<BoundaryTag>() - 1024);
+        assert_eq!(stats.largest_free_block, stats.free_bytes - 1024);
+        assert_eq!(mman.region_stats(RegionId(2)), None);
+    }
+
+    #[test]
+    fn test_remove_region()
+    {
+        let (addr1, size1) = allocate_memory();
+        let (addr2, size2) = allocate_memory();
+        let preference = [RegionId(1)];
+        let mut tags = [BoundaryTag::from_memory(addr1, size1), BoundaryTag::from_memory(addr2, size2)];
+        let mut mman = MemoryManager::new(&mut tags);
+        mman.set_region_preference(&preference);
+
+        let fixed   = mman.alloc_bytes(64).unwrap();
+        let movable = mman.alloc_movable_bytes(128).unwrap();
+        unsafe { ptr::write_bytes(movable as *mut u8, 0xA5, 128) };
+        assert_eq!(mman.try_remove_region(RegionId(1)).err(), Some(RemoveRegionError::InUse(2)));
+        assert_eq!(mman.try_remove_region(RegionId(2)).err(), Some(RemoveRegionError::NoRegion));
+
+        // The fixed block stays where it is, and the movable one goes to the other region.
+        let mut moved = (0, 0, 0);
+        assert_eq!(mman.migrate_region(RegionId(1), |old, new, size| moved = (old, new, size)), 1);
+        assert_eq!((moved.0, moved.2), (movable, 128));
+        assert_eq!(mman.region_of(moved.1), Some(RegionId(0)));
+        assert!(unsafe { slice::from_raw_parts(moved.1 as *const u8, 128) }.iter().all(|&b| b == 0xA5));
+        assert_eq!(mman.try_remove_region(RegionId(1)).err(), Some(RemoveRegionError::InUse(1)));
+
+        mman.free_bytes(fixed);
+        let region = mman.try_remove_region(RegionId(1)).unwrap();
+        assert_eq!((region.addr(), region.size()), (addr2, size2));
+        assert_eq!(mman.region_of(addr2 + 64), None);
+        assert_eq!(mman.stats().free_bytes, size1 - 2 * mem::size_of::<BoundaryTag>() - 128);
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_alloc_aligned_offset()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        let initial  = mman.stats();
+
+        let obj1 = mman.alloc_aligned_offset(100, 256, 16).unwrap();
+        assert_eq!((obj1 + 16) % 256, 0);
+        let obj2 = mman.alloc_aligned_offset(100, 256, 256 + 32).unwrap();
+        assert_eq!((obj2 + 32) % 256, 0);
+        assert_eq!(mman.alloc_aligned_offset(100, 256, 4), None);
+        let obj3 = mman.alloc_aligned_offset(100, 64, 0).unwrap();
+        assert_eq!(obj3 % 64, 0);
+
+        mman.free_bytes(obj1);
+        mman.free_bytes(obj2);
+        mman.free_bytes(obj3);
+        assert_eq!(mman.stats(), initial);
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_alloc_aligned_offset_skips_quick_lists()
+    {
+        let host     = HostRegion::new(4096, 256).unwrap();
+        let mut tags = [BoundaryTag::from_memory(host.addr(), 4096 - 256 + 104)];
+        let mut mman = MemoryManager::new(&mut tags);
+        mman.set_min_align(256);
+        mman.set_quick_lists(&[104], 4);
+
+        // The region ends 104 bytes past an aligned address, so the block carved at its end has the size of the quick list.
+        // The cached block is aligned to 256 itself, which is not what the offset asks for.
+        let obj = mman.alloc_bytes(100).unwrap();
+        assert_eq!(mman.usable_size(obj), 104);
+        mman.free_bytes(obj);
+        let obj = mman.alloc_aligned_offset(100, 256, 16).unwrap();
+        assert_eq!((obj + 16) % 256, 0);
+
+        mman.free_bytes(obj);
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_phys_to_virt()
+    {
+        let (addr, size) = allocate_memory();
+        let mapping  = LinearMapping::new(0x1000_0000);
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        let (vaddr, paddr) = mman.alloc_bytes_with_phys(64, 64).unwrap();
+        assert_eq!(paddr, vaddr);
+
+        mman.set_phys_to_virt(&mapping);
+        let (vaddr, paddr) = mman.alloc_bytes_with_phys(64, 64).unwrap();
+        assert_eq!(vaddr % 64, 0);
+        assert_eq!(paddr, vaddr - 0x1000_0000);
+        assert_eq!(mman.phys_addr_of(vaddr + 8), paddr + 8);
+        assert_eq!(mman.virt_addr_of(paddr), vaddr);
+        mman.free_bytes(vaddr);
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+
+    struct CacheRecorder {
+        cleaned: Option<(usize, usize)>,
+        invalidated: Option<(usize, usize)>,
+    }
+
+    impl CacheMaintenance for CacheRecorder {
+        fn clean(&mut self, addr: usize, len: usize)
+        {
+            self.cleaned = Some((addr, len));
+        }
+
+        fn invalidate(&mut self, addr: usize, len: usize)
+        {
+            self.invalidated = Some((addr, len));
+        }
+    }
+
+    #[test]
+    fn test_alloc_dma()
+    {
+        let (addr1, size1) = allocate_memory();
+        let (addr2, size2) = allocate_memory();
+        let dma_regions = [DmaRegion { region: RegionId(1), coherent: false }];
+        let mut caches  = CacheRecorder { cleaned: None, invalidated: None };
+        {
+            let mut tags = [BoundaryTag::from_memory(addr1, size1), BoundaryTag::from_memory(addr2, size2)];
+            let mut mman = MemoryManager::new(&mut tags);
+            mman.set_dma_regions(&dma_regions);
+            mman.set_cache_maintenance(64, &mut caches);
+
+            // The buffer is rounded out to the cache lines in the non-coherent region.
+            let buffer = mman.alloc_dma(Layout::from_size_align(100, 8).unwrap()).unwrap();
+            assert!(!buffer.is_coherent());
+            assert_eq!((buffer.vaddr % 64, buffer.len), (0, 128));
+            assert_eq!(buffer.paddr, buffer.vaddr);
+            assert_eq!(mman.region_of(buffer.vaddr), Some(RegionId(1)));
+            assert!(mman.alloc_dma(Layout::from_size_align(8192, 8).unwrap()).is_err());
+            mman.sync_for_device(&buffer);
+            mman.free_dma(buffer);
+        }
+        assert!(caches.cleaned.is_some());
+        assert_eq!(caches.cleaned, caches.invalidated);
+    }
+
+    struct ExecRecorder {
+        executable: Option<(usize, usize)>,
+        writable: Option<(usize, usize)>,
+    }
+
+    impl ExecHook for ExecRecorder {
+        fn make_executable(&mut self, addr: usize, len: usize)
+        {
+            self.executable = Some((addr, len));
+        }
+
+        fn make_writable(&mut self, addr: usize, len: usize)
+        {
+            self.writable = Some((addr, len));
+        }
+    }
+
+    #[test]
+    fn test_alloc_exec()
+    {
+        let (addr1, size1) = allocate_memory();
+        let region2  = HostRegion::new(4 * 4096, 16).unwrap().leak();
+        let exec     = [RegionId(1)];
+        let mut hook = ExecRecorder { executable: None, writable: None };
+        let code;
+        {
+            let mut tags = [BoundaryTag::from_memory(addr1, size1), BoundaryTag::from_memory(region2.addr(), region2.size())];
+            let mut mman = MemoryManager::new(&mut tags);
+            assert_eq!(mman.alloc_exec(Layout::from_size_align(64, 8).unwrap()), Err(AllocError::NoRegion));
+
+            mman.set_exec_regions(&exec);
+            mman.set_exec_hook(1024, &mut hook);
+            code = mman.alloc_exec(Layout::from_size_align(100, 8).unwrap()).unwrap();
+            assert_eq!(code % 1024, 0);
+            assert_eq!(mman.region_of(code), Some(RegionId(1)));
+
+            mman.seal_exec(code);
+            mman.free_exec(code);
+            assert_eq!(mman.check_integrity(), Ok(()));
+        }
+        assert_eq!(hook.executable, Some((code, 1024)));
+        assert_eq!(hook.writable, hook.executable);
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn test_region_holes()
+    {
+        let (addr, size) = allocate_memory();
+        let tag_size     = mem::size_of::<BoundaryTag>();
+        assert_eq!(BoundaryTag::from_memory_with_holes(addr, size, &[addr + 2048..addr + 2100, addr + 1024..addr + 1100]).err(), Some(RegionError::BadHole(1)));
+        assert_eq!(BoundaryTag::from_memory_with_holes(addr, size, &[addr + 1024..addr + size + 8]).err(), Some(RegionError::BadHole(0)));
+
+        // The gap after the second hole is too small to be a block, so the hole reaches the end of the region.
+        let holes    = [addr + 1024..addr + 1280, addr + size - 128..addr + size - 8];
+        let mut tags = [BoundaryTag::from_memory_with_holes(addr, size, &holes).unwrap()];
+        let mut mman = MemoryManager::new(&mut tags);
+        let initial  = mman.stats();
+        assert_eq!(initial.used_bytes, 0);
+        assert_eq!(initial.free_bytes, size - 384 - 4 * tag_size);
+        assert_eq!(mman.region_stats(RegionId(0)), Some(initial));
+
+        let obj1 = mman.alloc_bytes(initial.largest_free_block).unwrap();
+        let obj2 = mman.alloc_bytes(1024 - 2 * tag_size).unwrap();
+        assert!(obj1 + initial.largest_free_block <= holes[1].start && holes[0].end <= obj1);
+        assert!(obj2 + 1024 - 2 * tag_size <= holes[0].start);
+        assert_eq!(mman.alloc_bytes(8), None);
+        assert_eq!(mman.report_leaks(|_| {}), 2);
+
+        mman.free_bytes(obj1);
+        mman.reset();
+        assert_eq!(mman.stats(), initial);
+        assert_eq!(mman.report_leaks(|_| {}), 0);
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_fragmented()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+
+        // Leave two free blocks of 1 KiB apart.
+        let obj1    = mman.alloc_bytes(1024).unwrap();
+        mman.alloc_bytes(8).unwrap();
+        let obj2    = mman.alloc_bytes(1024).unwrap();
+        mman.alloc_bytes(8).unwrap();
+        let largest = mman.stats().largest_free_block;
+        let rest    = mman.alloc_bytes(largest).unwrap();
+        mman.free_bytes(obj1);
+        mman.free_bytes(obj2);
+
+        let stats = mman.stats();
+        assert_eq!(stats.free_bytes, 2048);
+        assert_eq!(mman.try_alloc(Layout::from_size_align(1536, 8).unwrap()), Err(AllocError::Fragmented { largest_free: 1024, total_free: 2048 }));
+        assert_eq!(mman.try_alloc(Layout::from_size_align(4096, 8).unwrap()), Err(AllocError::Exhausted { largest_free: 1024, total_free: 2048 }));
+
+        mman.free_bytes(rest);
+        assert!(mman.try_alloc(Layout::from_size_align(1536, 8).unwrap()).is_ok());
+    }
+
+    struct PolicyRecorder {
+        changes: [Option<(FitMode, usize)>; 2],
+        len: usize,
+    }
+
+    impl PolicyObserver for PolicyRecorder {
+        fn on_policy_change(&mut self, mode: FitMode, fragmentation: usize)
+        {
+            self.changes[self.len] = Some((mode, fragmentation));
+            self.len += 1;
+        }
+    }
+
+    #[test]
+    fn test_adaptive_policy()
+    {
+        let (addr, size) = allocate_memory();
+        let mut recorder = PolicyRecorder { changes: [None; 2], len: 0 };
+        {
+            let mut tags = [BoundaryTag::from_memory(addr, size)];
+            let mut mman = MemoryManager::new(&mut tags);
+            mman.set_adaptive_policy(40, 10, 1);
+            mman.set_policy_observer(&mut recorder);
+
+            let obj1    = mman.alloc_bytes(1024).unwrap();
+            let sep1    = mman.alloc_bytes(8).unwrap();
+            let obj2    = mman.alloc_bytes(1024).unwrap();
+            let sep2    = mman.alloc_bytes(8).unwrap();
+            let largest = mman.stats().largest_free_block;
+            let rest    = mman.alloc_bytes(largest).unwrap();
+            mman.free_bytes(obj1);
+            assert_eq!(mman.fragmentation(), 0);
+
+            // Two free blocks of the same size make the half of the free bytes unusable for a larger request.
+            mman.free_bytes(obj2);
+            assert_eq!(mman.fragmentation(), 50);
+            assert!(mman.address_ordered);
+
+            mman.free_bytes(rest);
+            mman.free_bytes(sep2);
+            mman.free_bytes(sep1);
+            assert_eq!(mman.fragmentation(), 0);
+            assert!(!mman.address_ordered);
+            assert_eq!(mman.check_integrity(), Ok(()));
+        }
+        assert_eq!(recorder.changes, [Some((FitMode::AddressOrdered, 50)), Some((FitMode::SegregatedFit, 0))]);
+    }
+
+    struct LevelRecorder {
+        levels: [Option<MemoryLevel>; 4],
+        count: usize,
+    }
+
+    impl WatermarkObserver for LevelRecorder {
+        fn on_level_change(&mut self, level: MemoryLevel)
+        {
+            self.levels[self.count] = Some(level);
+            self.count += 1;
+        }
+    }
+
+    #[test]
+    fn test_watermarks()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags     = [BoundaryTag::from_memory(addr, size)];
+        let mut recorder = LevelRecorder { levels: [None; 4], count: 0 };
+        {
+            let mut mman = MemoryManager::new(&mut tags);
+            mman.set_watermarks(3072, 1024);
+            mman.set_watermark_observer(&mut recorder);
+            assert_eq!(mman.memory_level(), MemoryLevel::Normal);
+
+            let obj1 = mman.alloc_bytes(1536).unwrap();
+            assert_eq!(mman.memory_level(), MemoryLevel::Low);
+            let obj2 = mman.alloc_bytes(1536).unwrap();
+            assert_eq!(mman.memory_level(), MemoryLevel::Critical);
+
+            mman.free_bytes(obj2);
+            mman.free_bytes(obj1);
+            assert_eq!(mman.memory_level(), MemoryLevel::Normal);
+        }
+
+        assert_eq!(recorder.count, 4);
+        assert_eq!(recorder.levels, [Some(MemoryLevel::Low), Some(MemoryLevel::Critical), Some(MemoryLevel::Low), Some(MemoryLevel::Normal)]);
+    }
+
+    #[test]
+    fn test_realloc_bytes()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+
+        let obj = mman.alloc_bytes(64).unwrap();
+        unsafe { *(obj as *mut u64) = 0xAF };
+        assert_eq!(mman.realloc_bytes(obj, 32), Some(obj));
+
+        let obj = mman.realloc_bytes(obj, 1024).unwrap();
+        assert_eq!(unsafe { *(obj as *const u64) }, 0xAF);
+        assert!(mman.realloc_bytes(obj, size).is_none());
+        assert_eq!(unsafe { *(obj as *const u64) }, 0xAF);
+
+        mman.free_bytes(obj);
+        assert_eq!(mman.check_integrity(), Ok(()));
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+    }
+
+    #[test]
+    fn test_stats()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        let initial  = mman.stats();
+        assert_eq!(initial.used_blocks, 0);
+        assert_eq!(initial.free_bytes, size - mem::size_of::<BoundaryTag>());
+        assert_eq!(initial.largest_free_block, initial.free_bytes);
+
+        let obj   = mman.alloc_bytes(128).unwrap();
+        let stats = mman.stats();
+        assert_eq!(stats.used_blocks, 1);
+        assert_eq!(stats.used_bytes, 128);
+        assert_eq!(stats.free_bytes, initial.free_bytes - 128 - mem::size_of::<BoundaryTag>());
+
+        mman.free_bytes(obj);
+        assert_eq!(mman.stats(), initial);
+    }
+
+    #[test]
+    fn test_stats_text()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        mman.alloc_bytes(128).unwrap();
+
+        let mut text = String::new();
+        mman.stats_text(&mut text).unwrap();
+        assert!(text.starts_with("# HELP heap_used_bytes Bytes in the allocated blocks.\n# TYPE heap_used_bytes gauge\nheap_used_bytes 128\n"));
+        assert!(text.contains("\nheap_used_blocks 1\n"));
+        assert!(text.ends_with("\nheap_memory_level 0\n"));
+    }
+
+    #[test]
+    fn test_free_bulk()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+
+        let mut ptrs = [NonNull::dangling(); 6];
+        for ptr in ptrs.iter_mut() {
+            *ptr = NonNull::new(mman.alloc_bytes(128).unwrap() as *mut u8).unwrap();
+        }
+        let kept = mman.alloc_bytes(128).unwrap();
+
+        // Leave a free block in the free list between the freed ones.
+        let listed = ptrs[3];
+        mman.free_bytes(listed.as_ptr() as usize);
+        ptrs.swap(3, 5);
+        ptrs.swap(0, 2);
+
+        mman.free_bulk(&mut ptrs[..5]);
+        assert_eq!(mman.check_integrity(), Ok(()));
+
+        mman.free_bytes(kept);
+        assert_eq!(mman.check_integrity(), Ok(()));
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+    }
+
+    #[test]
+    fn test_malloc_many()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+
+        let mut objs = [NonNull::<[u8; 64]>::dangling(); 8];
+        assert_eq!(mman.malloc_many(8, &mut objs), 8);
+        assert_eq!(mman.check_integrity(), Ok(()));
+
+        for (i, obj) in objs.iter().enumerate() {
+            unsafe { (*obj.as_ptr())[0] = i as u8 };
+        }
+        for (i, obj) in objs.iter().enumerate() {
+            assert_eq!(unsafe { (*obj.as_ptr())[0] }, i as u8);
+            mman.free(unsafe { &mut *obj.as_ptr() });
+        }
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+
+        // The heap runs out before the array is filled.
+        let mut objs = [NonNull::<[u8; 1024]>::dangling(); 8];
+        let count = mman.malloc_many(8, &mut objs);
+        assert!(0 < count && count < 4);
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_malloc_many_reused_data()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::with_user_data(addr, size)];
+        let mut mman = MemoryManager::<usize>::new(&mut tags);
+
+        // The freed block is too small to split, so the batch takes it whole.
+        let old = mman.alloc_bytes_with_data(64, 7).unwrap();
+        let _   = mman.alloc_bytes(64).unwrap();
+        mman.free_bytes(old);
+
+        let mut objs = [NonNull::<[u8; 64]>::dangling(); 64];
+        let count    = mman.malloc_many(64, &mut objs);
+        let objs     = &objs[..count];
+        assert!(objs.iter().any(|obj| obj.as_ptr() as usize == old));
+        assert!(objs.iter().all(|obj| mman.user_data(obj.as_ptr() as usize) == 0));
+    }
+
+    #[test]
+    fn test_malloc_many_floors()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        mman.set_priority_floors(2048, 1024);
+
+        // The batch stops at the floor of the normal allocations like the single ones.
+        let mut objs = [NonNull::<[u8; 64]>::dangling(); 64];
+        let count    = mman.malloc_many(64, &mut objs);
+        assert!(0 < count && count < 64);
+        assert!(1024 <= mman.stats().free_bytes);
+        assert!(mman.try_alloc_with_priority(Layout::from_size_align(512, 8).unwrap(), Priority::High).is_ok());
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_malloc_many_over_aligned()
+    {
+        #[repr(align(64))]
+        struct Line([u8; 64]);
+
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        let initial  = mman.stats();
+
+        let mut objs = [NonNull::<Line>::dangling(); 8];
+        assert_eq!(mman.malloc_many(8, &mut objs), 8);
+        assert!(objs.iter().all(|obj| (obj.as_ptr() as usize).is_multiple_of(64)));
+        assert_eq!(mman.check_integrity(), Ok(()));
+
+        for obj in objs.iter() {
+            unsafe { (*obj.as_ptr()).0[63] = 0xAF };
+            mman.free(unsafe { &mut *obj.as_ptr() });
+        }
+        assert_eq!(mman.stats(), initial);
+    }
+
+    #[test]
+    fn test_alloc_slice()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+
+        let array = mman.alloc_slice::<u64>(100).unwrap();
+        {
+            let array = unsafe { &mut *array.as_ptr() };
+            assert_eq!(array.len(), 100);
+            assert_eq!(array.as_ptr() as usize % mem::align_of::<u64>(), 0);
+            for (i, x) in array.iter_mut().enumerate() {
+                *x = i as u64;
+            }
+            assert_eq!(array[99], 99);
+        }
+
+        let empty = mman.alloc_slice::<u64>(0).unwrap();
+        assert_eq!(unsafe { &*empty.as_ptr() }.len(), 0);
+        assert!(mman.alloc_slice::<u64>(usize::MAX).is_none());
+
+        mman.free_slice(array);
+        mman.free_slice(empty);
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+    }
+
+    #[test]
+    fn test_allocator_object()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        {
+            let heap: &mut dyn Allocator = &mut mman;
+            let layout = Layout::from_size_align(100, 32).unwrap();
+            let ptr    = heap.allocate(layout).unwrap();
+            assert_eq!(ptr.as_ptr() as usize % 32, 0);
+            heap.deallocate(ptr, layout);
+        }
+
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+    }
+
+    #[test]
+    fn test_malloc_uninit()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+
+        let obj = mman.malloc_uninit::<u128>().unwrap();
+        assert_eq!((obj as *const _) as usize % mem::align_of::<u128>(), 0);
+
+        let value = unsafe {
+            obj.as_mut_ptr().write(0xAF);
+            &mut *obj.as_mut_ptr()
+        };
+        assert_eq!(*value, 0xAF);
+
+        mman.free(value);
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+    }
+
+    #[test]
+    fn test_deferred_coalescing()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        mman.set_deferred_coalescing(true);
+
+        let obj1 = mman.malloc::<[u8; 128]>().unwrap();
+        let obj2 = mman.malloc::<[u8; 128]>().unwrap();
+        mman.free(obj1);
+        mman.free(obj2);
+        assert!(BoundaryTag::next_tag_of(&mman.tags[0]).is_some());
+
+        assert_eq!(mman.coalesce(1), 1);
+        assert_eq!(mman.coalesce(8), 1);
+        assert_eq!(mman.coalesce(8), 0);
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+    }
+
+    #[test]
+    fn test_maintain()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        mman.set_deferred_coalescing(true);
+
+        for _ in 0..3 {
+            let obj = mman.malloc::<[u8; 64]>().unwrap();
+            mman.free(obj);
+        }
+
+        assert_eq!(mman.maintain(2), 2);
+        assert_eq!(mman.maintain(2), 1);
+        assert_eq!(mman.maintain(2), 0);
+    }
+
+    #[test]
+    fn test_reset()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        mman.set_quick_lists(&[64], 4);
+        mman.set_deferred_coalescing(true);
+
+        let obj1 = mman.malloc::<[u8; 64]>().unwrap();
+        let obj2 = mman.malloc::<[u8; 128]>().unwrap();
+        let _    = mman.malloc::<[u8; 256]>().unwrap();
+        mman.free(obj1);
+        mman.free(obj2);
+
+        mman.reset();
+        assert_eq!(mman.check_integrity(), Ok(()));
+        assert_eq!(mman.quick_lists[0].len.get(), 0);
+        assert_eq!(mman.coalesce(8), 0);
+        assert!(BoundaryTag::next_tag_of(&mman.tags[0]).is_none());
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+        assert!(mman.malloc::<[u8; 2048]>().is_some());
+    }
+
+    #[test]
+    fn test_quick_lists()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        mman.set_quick_lists(&[64], 4);
+
+        let obj1  = mman.malloc::<[u8; 64]>().unwrap();
+        let addr1 = (obj1 as *const _) as usize;
+        mman.free(obj1);
+        assert_eq!(mman.quick_lists[0].len.get(), 1);
+
+        let obj2 = mman.malloc::<[u8; 64]>().unwrap();
+        assert_eq!((obj2 as *const _) as usize, addr1);
+        assert_eq!(mman.quick_lists[0].len.get(), 0);
+
+        mman.free(obj2);
+        mman.set_quick_lists(&[], 0);
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+    }
+
+    #[test]
+    fn test_hot_size()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        assert!(mman.set_hot_size(48, 3));
+        assert_eq!(mman.maintain(8), 3);
+        assert_eq!(mman.quick_lists[0].len.get(), 3);
+        assert_eq!(mman.maintain(8), 0);
+        assert_eq!(mman.stats().used_blocks, 0);
+
+        let obj = mman.alloc_bytes(48).unwrap();
+        assert_eq!(mman.quick_lists[0].len.get(), 2);
+        assert_eq!(mman.stats().used_blocks, 1);
+        mman.free_bytes(obj);
+        assert_eq!(mman.quick_lists[0].len.get(), 3);
+        assert_eq!(mman.check_integrity(), Ok(()));
+
+        mman.set_quick_lists(&[], 0);
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+    }
+
+    #[test]
+    fn test_slack()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        let initial  = mman.stats();
+        mman.set_slack(50, 16);
+
+        let obj = mman.alloc_bytes(200).unwrap();
+        assert_eq!(mman.usable_size(obj), mman.request_size_of(200 + 100 + 16));
+        assert_eq!(mman.realloc_bytes(obj, 300), Some(obj));
+        assert_eq!(mman.realloc_bytes(obj, 316), Some(obj));
+
+        // The slack does not fit, but the request itself does.
+        let rest = mman.stats().largest_free_block;
+        let big  = mman.alloc_bytes(rest - 8).unwrap();
+        assert_eq!(mman.usable_size(big), rest);
+
+        mman.free_bytes(big);
+        mman.free_bytes(obj);
+        assert_eq!(mman.stats(), initial);
+
+        // A percent of a size below 100 bytes is not rounded away.
+        mman.set_slack(50, 0);
+        let small = mman.alloc_bytes(40).unwrap();
+        assert_eq!(mman.usable_size(small), mman.request_size_of(60));
+        mman.free_bytes(small);
+
+        // The slack is dropped when it would go over the quota.
+        mman.set_quota(mman.request_size_of(200), usize::MAX);
+        let obj = mman.alloc_bytes(200).unwrap();
+        assert_eq!(mman.usable_size(obj), mman.request_size_of(200));
+        assert_eq!(mman.stats().used_bytes, mman.request_size_of(200));
+        mman.free_bytes(obj);
+        assert_eq!(mman.stats(), initial);
+    }
+
+    #[test]
+    fn test_realloc_shrink()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        let initial  = mman.stats();
+
+        let obj = mman.alloc_bytes(1024).unwrap();
+        unsafe { (obj as *mut u8).write_bytes(0xAA, 1024) };
+        let free_bytes = mman.stats().free_bytes;
+
+        // The tail goes back to the free list as soon as it can be a block; the block is the last one, so the tail is not merged.
+        assert_eq!(mman.realloc_bytes(obj, 100), Some(obj));
+        assert_eq!(mman.usable_size(obj), mman.request_size_of(100));
+        assert_eq!(mman.stats().free_bytes, free_bytes + 1024 - mman.request_size_of(100) - mem::size_of::<BoundaryTag>());
+        assert_eq!(mman.stats().used_bytes, mman.request_size_of(100));
+        assert!((0..100).all(|i| unsafe { *((obj + i) as *const u8) } == 0xAA));
+
+        // A tail too small for a block stays in the payload.
+        assert_eq!(mman.realloc_bytes(obj, 96), Some(obj));
+        assert_eq!(mman.usable_size(obj), mman.request_size_of(100));
+        assert_eq!(mman.check_integrity(), Ok(()));
+
+        mman.free_bytes(obj);
+        assert_eq!(mman.stats(), initial);
+    }
+
+    #[test]
+    fn test_grow_and_shrink_in_place()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        let initial  = mman.stats();
+
+        // The blocks are carved from the tail of the free block, so the first one is after the second one.
+        let obj1 = mman.alloc_bytes(64).unwrap();
+        let obj2 = mman.alloc_bytes(64).unwrap();
+        assert_eq!(mman.grow_in_place(obj2, 100), Err(CannotGrow));
+        assert_eq!(mman.grow_in_place(obj2, 64), Ok(64));
+
+        mman.free_bytes(obj1);
+        assert_eq!(mman.grow_in_place(obj2, 100), Ok(mman.request_size_of(100)));
+        assert_eq!(mman.stats().used_bytes, mman.request_size_of(100));
+        assert_eq!(mman.grow_in_place(obj2, 1000), Err(CannotGrow));
+        assert_eq!(mman.check_integrity(), Ok(()));
+
+        assert_eq!(mman.shrink_in_place(obj2, 16), 16);
+        assert_eq!(mman.stats().used_bytes, 16);
+        assert_eq!(mman.realloc_bytes(obj2, 120), Some(obj2));
+        assert_eq!(mman.check_integrity(), Ok(()));
+
+        mman.free_bytes(obj2);
+        assert_eq!(mman.stats(), initial);
+    }
+}
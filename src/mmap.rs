@@ -0,0 +1,102 @@
+use core::ptr;
+
+use libc;
+
+use region::{MemoryRegion, MemorySource};
+
+
+/// `MemorySource` mapping anonymous pages from the host kernel, like a kernel heap gets pages from its page allocator.
+/// With guard pages, every region is surrounded by inaccessible pages, so an overrun faults at once.
+pub struct MmapSource {
+    page_size: usize,
+    has_guard_pages: bool,
+}
+
+
+impl MmapSource {
+    pub fn new(has_guard_pages: bool) -> MmapSource
+    {
+        MmapSource {
+            page_size: unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize,
+            has_guard_pages,
+        }
+    }
+
+    pub fn page_size(&self) -> usize
+    {
+        self.page_size
+    }
+
+    fn guard_size(&self) -> usize
+    {
+        if self.has_guard_pages { self.page_size } else { 0 }
+    }
+}
+
+
+impl MemorySource for MmapSource {
+    fn request(&mut self, size: usize) -> Option<MemoryRegion>
+    {
+        let size       = (size + self.page_size - 1) & !(self.page_size - 1);
+        let guard_size = self.guard_size();
+        let total_size = size + 2 * guard_size;
+
+        let addr = unsafe { libc::mmap(ptr::null_mut(), total_size, libc::PROT_NONE, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0) };
+        if addr == libc::MAP_FAILED {
+            return None;
+        }
+
+        // Only the pages between the guards become accessible.
+        let addr = addr as usize + guard_size;
+        if unsafe { libc::mprotect(addr as *mut libc::c_void, size, libc::PROT_READ | libc::PROT_WRITE) } != 0 {
+            unsafe { libc::munmap((addr - guard_size) as *mut libc::c_void, total_size) };
+            return None;
+        }
+
+        Some(MemoryRegion::new(addr, size))
+    }
+
+    fn release(&mut self, region: MemoryRegion)
+    {
+        let guard_size = self.guard_size();
+        unsafe { libc::munmap((region.addr() - guard_size) as *mut libc::c_void, region.size() + 2 * guard_size) };
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use BoundaryTag;
+    use HostRegion;
+    use MemoryManager;
+
+    #[test]
+    fn test_request_and_release()
+    {
+        let mut source = MmapSource::new(true);
+        let region     = source.request(100).unwrap();
+        assert_eq!(region.addr() % source.page_size(), 0);
+        assert_eq!(region.size(), source.page_size());
+
+        unsafe { *((region.addr() + region.size() - 1) as *mut u8) = 0xAF };
+        source.release(region);
+    }
+
+    #[test]
+    fn test_huge_allocation()
+    {
+        let host       = HostRegion::new(4096, 16).unwrap();
+        let mut tags   = [BoundaryTag::from_memory(host.addr(), host.size())];
+        let mut source = MmapSource::new(true);
+        let mut mman   = MemoryManager::new(&mut tags);
+        mman.set_huge_allocation_source(1024, &mut source);
+
+        let huge = mman.alloc_bytes(64 * 1024).unwrap();
+        assert!(!mman.contains(huge));
+        unsafe { *((huge + 64 * 1024 - 1) as *mut u8) = 0xAF };
+
+        mman.free_bytes(huge);
+        assert_eq!(mman.check_integrity(), Ok(()));
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::vec::Vec;
+
+use BoundaryTag;
+use HostRegion;
+use MemoryManager;
+
+
+/// Reference model of a heap: the live ranges and nothing else.
+struct ModelHeap {
+    ranges: BTreeMap<usize, usize>,
+}
+
+
+impl ModelHeap {
+    fn new() -> ModelHeap
+    {
+        ModelHeap {
+            ranges: BTreeMap::new(),
+        }
+    }
+
+    /// Add the range, asserting that it overlaps no live range.
+    fn insert(&mut self, addr: usize, size: usize)
+    {
+        if let Some((&prev_addr, &prev_size)) = self.ranges.range(..addr + size).next_back() {
+            assert!(prev_addr + prev_size <= addr, "0x{:x} of {} bytes overlaps 0x{:x} of {} bytes", addr, size, prev_addr, prev_size);
+        }
+        self.ranges.insert(addr, size);
+    }
+
+    fn remove(&mut self, addr: usize) -> usize
+    {
+        self.ranges.remove(&addr).unwrap()
+    }
+
+    fn total_size(&self) -> usize
+    {
+        self.ranges.values().sum()
+    }
+}
+
+
+/// xorshift64, so that a failing seed reproduces the same sequence.
+struct Rng(u64);
+
+
+impl Rng {
+    fn next(&mut self) -> usize
+    {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+}
+
+
+fn fill_byte(addr: usize) -> u8
+{
+    (addr >> 3) as u8
+}
+
+
+/// Run random allocations and frees against both the heap and the model, checking the heap after every operation.
+fn run(seed: u64, num_ops: usize)
+{
+    const SIZE: usize = 64 * 1024;
+    const ALIGNS: [usize; 4] = [1, 8, 64, 256];
+
+    let host      = HostRegion::new(SIZE, 16).unwrap();
+    let mut tags  = [BoundaryTag::from_memory(host.addr(), host.size())];
+    let mut mman  = MemoryManager::new(&mut tags);
+    let initial   = mman.stats();
+    let mut model = ModelHeap::new();
+    let mut live  = Vec::new();
+    let mut rng   = Rng(seed);
+
+    for _ in 0..num_ops {
+        if live.is_empty() || !rng.next().is_multiple_of(3) {
+            let size  = 1 + rng.next() % 1024;
+            let align = ALIGNS[rng.next() % ALIGNS.len()];
+            if let Some(addr) = mman.alloc_aligned_bytes(size, align) {
+                assert_eq!(addr % align, 0);
+                assert!(host.addr() <= addr && addr + size <= host.addr() + SIZE);
+                assert!(size <= mman.block_of(addr).unwrap().size);
+
+                model.insert(addr, size);
+                unsafe { (addr as *mut u8).write_bytes(fill_byte(addr), size) };
+                live.push(addr);
+            }
+        } else {
+            let addr = live.swap_remove(rng.next() % live.len());
+            let size = model.remove(addr);
+            assert!((0..size).all(|i| unsafe { *((addr + i) as *const u8) } == fill_byte(addr)), "0x{:x} was overwritten", addr);
+            mman.free_bytes(addr);
+        }
+
+        assert_eq!(mman.check_integrity(), Ok(()));
+        let stats = mman.stats();
+        assert_eq!(stats.used_blocks, live.len());
+        assert!(model.total_size() <= stats.used_bytes);
+    }
+
+    for addr in live {
+        model.remove(addr);
+        mman.free_bytes(addr);
+    }
+    assert_eq!(mman.stats(), initial);
+}
+
+
+#[test]
+fn test_differential()
+{
+    for seed in 1..=16 {
+        run(seed, 2000);
+    }
+}
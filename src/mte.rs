@@ -0,0 +1,81 @@
+use core::arch::asm;
+
+
+/// Bytes covered by a single allocation tag.
+pub const MTE_GRANULE_SIZE: usize = 16;
+
+
+/// The allocation tag lives in the bits 56 to 59 of a pointer, ignored by the address translation.
+const TAG_MASK: usize = 0xF << 56;
+
+
+/// The address without its allocation tag, as the allocator reaches its own memory.
+pub fn untag(addr: usize) -> usize
+{
+    addr & !TAG_MASK
+}
+
+
+/// Give the granules of the `size` bytes at `addr` a random tag and return `addr` carrying the tag.
+/// The tag is never zero, which stays with the untagged pointers of the allocator, so a tagged pointer cannot reach the tags.
+/// `addr` and `size` must be multiples of `MTE_GRANULE_SIZE`.
+pub unsafe fn tag_granules(addr: usize, size: usize) -> usize
+{
+    let tagged: usize;
+    asm!(
+        ".arch_extension memtag",
+        "irg {tagged}, {addr}, {exclude}",
+        tagged = out(reg) tagged,
+        addr = in(reg) addr,
+        exclude = in(reg) 1usize,
+        options(nomem, nostack),
+    );
+
+    store_tags(tagged, size);
+    tagged
+}
+
+
+/// Give the granules of a freed block the zero tag back, so that every pointer kept to the block faults.
+pub unsafe fn clear_granules(addr: usize, size: usize)
+{
+    store_tags(untag(addr), size);
+}
+
+
+/// Give the granules of the `size` bytes at `addr` the tag `tagged` carries, such as the bytes a block takes over from its neighbor.
+pub unsafe fn extend_tag(tagged: usize, addr: usize, size: usize)
+{
+    store_tags(addr | (tagged & TAG_MASK), size);
+}
+
+
+unsafe fn store_tags(tagged: usize, size: usize)
+{
+    let mut granule = tagged;
+    while granule < tagged + size {
+        asm!(
+            ".arch_extension memtag",
+            "stg {granule}, [{granule}]",
+            granule = in(reg) granule,
+            options(nostack),
+        );
+        granule += MTE_GRANULE_SIZE;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_untag()
+    {
+        let addr = 0x7F12_3450;
+        assert_eq!(untag(addr), addr);
+        assert_eq!(untag(0xA << 56 | addr), addr);
+        // The bits above the tag are left to the other users of the ignored top byte.
+        assert_eq!(untag(0xFA << 56 | addr), 0xF0 << 56 | addr);
+    }
+}
@@ -0,0 +1,182 @@
+use core::mem;
+
+use super::Allocator;
+use super::MemoryManager;
+
+
+/// Slot of an object cache.
+/// The link lives beside the object so that a freed object keeps its constructed state.
+#[repr(C)]
+struct Slot<T> {
+    object: T,
+    next_free_addr: Option<usize>,
+}
+
+
+/// Cache of objects of a single type in the style of the kmem_cache of Linux.
+/// `ctor` runs only when a slot is taken from the heap for the first time and `dtor` only when the slot goes back to the heap,
+/// so the freed objects are reused in their constructed state.
+pub struct ObjectCache<'m, 'a: 'm, T> {
+    mman: &'m mut MemoryManager<'a>,
+    ctor: Option<fn(&mut T)>,
+    dtor: Option<fn(&mut T)>,
+    free_slot_addr: Option<usize>,
+    num_free: usize,
+}
+
+
+impl<'m, 'a: 'm, T> ObjectCache<'m, 'a, T> {
+    pub fn new(mman: &'m mut MemoryManager<'a>, ctor: Option<fn(&mut T)>, dtor: Option<fn(&mut T)>) -> ObjectCache<'m, 'a, T>
+    {
+        ObjectCache {
+            mman,
+            ctor,
+            dtor,
+            free_slot_addr: None,
+            num_free: 0,
+        }
+    }
+
+    /// The number of the constructed objects waiting for reuse.
+    pub fn num_free(&self) -> usize
+    {
+        self.num_free
+    }
+
+    pub fn alloc<'b>(&mut self) -> Option<&'b mut T>
+    {
+        if let Some(addr) = self.free_slot_addr {
+            let slot = unsafe { &mut *(addr as *mut Slot<T>) };
+            self.free_slot_addr = slot.next_free_addr;
+            self.num_free      -= 1;
+            return Some(&mut slot.object);
+        }
+
+        let slot = self.mman.malloc::<Slot<T>>()?;
+
+        if let Some(ctor) = self.ctor {
+            ctor(&mut slot.object);
+        }
+
+        Some(&mut slot.object)
+    }
+
+    pub fn free(&mut self, obj: &mut T)
+    {
+        // The object is at the head of its slot.
+        let slot = unsafe { &mut *((obj as *mut T) as *mut Slot<T>) };
+        slot.next_free_addr = self.free_slot_addr;
+        self.free_slot_addr = Some((slot as *mut Slot<T>) as usize);
+        self.num_free      += 1;
+    }
+
+    /// Destruct the cached objects and give their slots back to the heap.
+    pub fn shrink(&mut self) -> usize
+    {
+        let mut count = 0;
+        while let Some(addr) = self.free_slot_addr {
+            let slot = unsafe { &mut *(addr as *mut Slot<T>) };
+            self.free_slot_addr = slot.next_free_addr;
+
+            if let Some(dtor) = self.dtor {
+                dtor(&mut slot.object);
+            }
+            self.mman.free(slot);
+            count += 1;
+        }
+
+        self.num_free = 0;
+        count
+    }
+
+    /// The size of the memory taken by each object, including the link to the next free slot.
+    pub fn slot_size() -> usize
+    {
+        mem::size_of::<Slot<T>>()
+    }
+}
+
+
+impl<'m, 'a: 'm, T> Drop for ObjectCache<'m, 'a, T> {
+    fn drop(&mut self)
+    {
+        self.shrink();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use core::mem;
+
+    use super::*;
+    use BoundaryTag;
+
+    use HostRegion;
+
+    fn allocate_memory() -> (usize, usize)
+    {
+        const SIZE: usize = 4096;
+        let addr = HostRegion::new(SIZE, 16).unwrap().leak().addr();
+
+        (addr, SIZE)
+    }
+
+    struct Connection {
+        buffer: [u8; 64],
+        generation: usize,
+    }
+
+    fn construct(c: &mut Connection)
+    {
+        c.buffer     = [0xAF; 64];
+        c.generation = 0;
+    }
+
+    fn destruct(c: &mut Connection)
+    {
+        c.generation = usize::MAX;
+    }
+
+    #[test]
+    fn test_reuse_constructed_object()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        let mut cache = ObjectCache::new(&mut mman, Some(construct), Some(destruct));
+
+        let c1 = cache.alloc().unwrap();
+        assert_eq!(c1.buffer[0], 0xAF);
+        assert_eq!(c1.generation, 0);
+
+        // The constructor does not run again for the reused object.
+        c1.generation = 1;
+        let c1_addr = (c1 as *const Connection) as usize;
+        cache.free(c1);
+        assert_eq!(cache.num_free(), 1);
+
+        let c2 = cache.alloc().unwrap();
+        assert_eq!((c2 as *const Connection) as usize, c1_addr);
+        assert_eq!(c2.generation, 1);
+
+        cache.free(c2);
+        assert_eq!(cache.shrink(), 1);
+        assert_eq!(cache.num_free(), 0);
+    }
+
+    #[test]
+    fn test_drop_returns_slots()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        {
+            let mut cache = ObjectCache::<Connection>::new(&mut mman, None, None);
+            let c = cache.alloc().unwrap();
+            cache.free(c);
+        }
+
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+    }
+}
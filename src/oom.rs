@@ -0,0 +1,77 @@
+use core::alloc::Layout;
+use core::fmt;
+use core::fmt::Write;
+#[cfg(not(test))]
+use core::hint;
+
+use super::LockedHeap;
+
+
+static mut OOM_REPORTER: Option<(&'static LockedHeap, &'static mut dyn Write)> = None;
+
+
+/// Make the allocation error handler write the failing layout and the stats of `heap` to `writer`, such as a serial port, before halting.
+///
+/// # Safety
+/// This has to be called before any other thread can allocate.
+pub unsafe fn set_oom_reporter(heap: &'static LockedHeap, writer: &'static mut dyn Write)
+{
+    OOM_REPORTER = Some((heap, writer));
+}
+
+
+fn report_alloc_error(heap: &LockedHeap, writer: &mut dyn Write, layout: Layout) -> fmt::Result
+{
+    writeln!(writer, "memory allocation of {} bytes (align {}) failed", layout.size(), layout.align())?;
+
+    // The failure may have happened with the heap locked, such as in an interrupt handler.
+    match heap.try_lock() {
+        None => writeln!(writer, "heap is locked"),
+        Some(mman) => {
+            let stats = mman.stats();
+            writeln!(writer, "used: {} bytes in {} blocks, free: {} bytes, largest free block: {} bytes, level: {:?}",
+                     stats.used_bytes, stats.used_blocks, stats.free_bytes, stats.largest_free_block, mman.memory_level())
+        },
+    }
+}
+
+
+// The test harness has its own handler from std.
+#[cfg(not(test))]
+#[alloc_error_handler]
+fn on_alloc_error(layout: Layout) -> !
+{
+    unsafe {
+        if let Some((heap, ref mut writer)) = OOM_REPORTER {
+            let _ = report_alloc_error(heap, *writer, layout);
+        }
+    }
+
+    loop {
+        hint::spin_loop();
+    }
+}
+
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::string::String;
+
+    use super::*;
+
+    static_heap!(OOM_HEAP, 4096);
+
+    #[test]
+    fn test_report_alloc_error()
+    {
+        let mut report = String::new();
+        report_alloc_error(&OOM_HEAP, &mut report, Layout::from_size_align(8192, 8).unwrap()).unwrap();
+        assert!(report.starts_with("memory allocation of 8192 bytes (align 8) failed\nused: 0 bytes in 0 blocks"));
+
+        let guard = OOM_HEAP.lock();
+        let mut report = String::new();
+        report_alloc_error(&OOM_HEAP, &mut report, Layout::from_size_align(8192, 8).unwrap()).unwrap();
+        assert!(report.ends_with("heap is locked\n"));
+        drop(guard);
+    }
+}
@@ -0,0 +1,154 @@
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::ptr::NonNull;
+
+use super::MemoryManager;
+
+
+/// Value placed in a block of a `MemoryManager` that frees the block when it is dropped, like `Box`.
+/// The block is never movable, so `compact` leaves it in place.
+pub struct Owned<'m, 'a: 'm, T> {
+    mman: &'m mut MemoryManager<'a>,
+    ptr: NonNull<T>,
+}
+
+
+impl<'m, 'a: 'm, T> Owned<'m, 'a, T> {
+    /// Move `value` into a new block, or return `None` if the block cannot be allocated.
+    pub fn new(mman: &'m mut MemoryManager<'a>, value: T) -> Option<Owned<'m, 'a, T>>
+    {
+        let addr = mman.alloc_aligned_bytes(mem::size_of::<T>(), mem::align_of::<T>())?;
+
+        let ptr = unsafe { NonNull::new_unchecked(addr as *mut T) };
+        unsafe { ptr::write(ptr.as_ptr(), value) };
+
+        Some(Owned {
+            mman,
+            ptr,
+        })
+    }
+
+    /// Give up the ownership without freeing the block, so that the pointer can cross FFI or be stored in an intrusive structure.
+    pub fn into_raw(owned: Owned<'m, 'a, T>) -> NonNull<T>
+    {
+        let ptr = owned.ptr;
+        mem::forget(owned);
+        ptr
+    }
+
+    /// Take the ownership of a value again.
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by `into_raw` for an `Owned` of the same `mman` and must not be used afterwards.
+    pub unsafe fn from_raw(mman: &'m mut MemoryManager<'a>, ptr: NonNull<T>) -> Owned<'m, 'a, T>
+    {
+        Owned {
+            mman,
+            ptr,
+        }
+    }
+}
+
+
+impl<'m, 'a: 'm, T> Deref for Owned<'m, 'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T
+    {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+
+impl<'m, 'a: 'm, T> DerefMut for Owned<'m, 'a, T> {
+    fn deref_mut(&mut self) -> &mut T
+    {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+
+impl<'m, 'a: 'm, T> Drop for Owned<'m, 'a, T> {
+    fn drop(&mut self)
+    {
+        unsafe { ptr::drop_in_place(self.ptr.as_ptr()) };
+        self.mman.free_bytes(self.ptr.as_ptr() as usize);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use core::mem;
+    use core::cell::Cell;
+
+    use super::*;
+    use BoundaryTag;
+
+    use HostRegion;
+
+    fn allocate_memory() -> (usize, usize)
+    {
+        const SIZE: usize = 4096;
+        let addr = HostRegion::new(SIZE, 16).unwrap().leak().addr();
+
+        (addr, SIZE)
+    }
+
+    struct DropCounter<'c>(&'c Cell<usize>);
+
+    impl<'c> Drop for DropCounter<'c> {
+        fn drop(&mut self)
+        {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn test_drop_frees_block()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        let drops    = Cell::new(0);
+        {
+            let mut obj = Owned::new(&mut mman, (0u64, DropCounter(&drops))).unwrap();
+            obj.0 = 0xAF;
+            assert_eq!(obj.0, 0xAF);
+        }
+
+        assert_eq!(drops.get(), 1);
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+    }
+
+    #[test]
+    fn test_raw_round_trip()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+
+        let ptr = Owned::into_raw(Owned::new(&mut mman, 0xAFu64).unwrap());
+        assert!(unsafe { mman.tags[0].as_ref() }.free_area_size() < size - mem::size_of::<BoundaryTag>());
+
+        {
+            let obj = unsafe { Owned::from_raw(&mut mman, ptr) };
+            assert_eq!(*obj, 0xAF);
+        }
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+    }
+
+    #[test]
+    fn test_pinned()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+
+        let obj = mman.alloc_pinned(0xAFu64).unwrap();
+        let tag = unsafe { &*((&*obj as *const u64 as usize - mem::size_of::<BoundaryTag>()) as *const BoundaryTag) };
+        assert_eq!(*obj, 0xAF);
+        assert!(!tag.is_movable);
+    }
+}
@@ -0,0 +1,187 @@
+use core::mem;
+use core::ptr::NonNull;
+use core::slice;
+use core::alloc::Layout;
+
+use super::Allocator;
+use super::round_up;
+use region::MemoryRegion;
+
+
+/// Allocator handing out runs of whole pages.
+/// The boundary tags live in a table at the head of the region instead of in the pages,
+/// so every run is naturally aligned to the page size.
+/// The first and the last entries of a run hold its page count and whether it is allocated.
+pub struct PageAllocator {
+    base_addr: usize,
+    page_size: usize,
+    tags: &'static mut [usize],
+}
+
+
+impl PageAllocator {
+    /// Manage the region in pages of `page_size` bytes, which must be a power of two.
+    /// Return `None` if the region cannot hold a single page besides the tag table.
+    pub fn new(region: &MemoryRegion, page_size: usize) -> Option<PageAllocator>
+    {
+        debug_check!(page_size.is_power_of_two());
+
+        let region_end    = region.addr() + region.size();
+        let mut num_pages = region.size() / (page_size + mem::size_of::<usize>());
+        let base_addr = loop {
+            if num_pages == 0 {
+                return None;
+            }
+
+            let base_addr = round_up(region.addr() + num_pages * mem::size_of::<usize>(), page_size);
+            if base_addr + num_pages * page_size <= region_end {
+                break base_addr;
+            }
+            num_pages -= 1;
+        };
+
+        let tags = unsafe { slice::from_raw_parts_mut(region.addr() as *mut usize, num_pages) };
+        let mut page_allocator = PageAllocator {
+            base_addr,
+            page_size,
+            tags,
+        };
+        page_allocator.set_run(0, num_pages, false);
+
+        Some(page_allocator)
+    }
+
+    pub fn num_pages(&self) -> usize
+    {
+        self.tags.len()
+    }
+
+    /// Allocate a run of `count` pages with the first fit and return its address.
+    pub fn alloc_pages(&mut self, count: usize) -> Option<usize>
+    {
+        if count == 0 {
+            return None;
+        }
+
+        let mut i = 0;
+        while i < self.tags.len() {
+            let (run_count, is_alloc) = PageAllocator::decode(self.tags[i]);
+            if !is_alloc && count <= run_count {
+                self.set_run(i, count, true);
+                if count < run_count {
+                    self.set_run(i + count, run_count - count, false);
+                }
+                return Some(self.base_addr + i * self.page_size);
+            }
+            i += run_count;
+        }
+
+        None
+    }
+
+    /// Free the run starting at `addr` and merge it with the free neighbor runs.
+    pub fn free_pages(&mut self, addr: usize)
+    {
+        debug_check!(addr.is_multiple_of(self.page_size));
+
+        let mut first = (addr - self.base_addr) / self.page_size;
+        let (mut count, is_alloc) = PageAllocator::decode(self.tags[first]);
+        debug_check!(is_alloc);
+
+        let next = first + count;
+        if next < self.tags.len() {
+            let (next_count, next_is_alloc) = PageAllocator::decode(self.tags[next]);
+            if !next_is_alloc {
+                count += next_count;
+            }
+        }
+
+        if 0 < first {
+            // The last entry of the previous run tells where it begins.
+            let (prev_count, prev_is_alloc) = PageAllocator::decode(self.tags[first - 1]);
+            if !prev_is_alloc {
+                first -= prev_count;
+                count += prev_count;
+            }
+        }
+
+        self.set_run(first, count, false);
+    }
+
+    fn encode(count: usize, is_alloc: bool) -> usize
+    {
+        (count << 1) | (is_alloc as usize)
+    }
+
+    fn decode(tag: usize) -> (usize, bool)
+    {
+        (tag >> 1, tag & 1 != 0)
+    }
+
+    fn set_run(&mut self, first: usize, count: usize, is_alloc: bool)
+    {
+        let tag = PageAllocator::encode(count, is_alloc);
+        self.tags[first]             = tag;
+        self.tags[first + count - 1] = tag;
+    }
+}
+
+
+impl Allocator for PageAllocator {
+    fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>>
+    {
+        let count = layout.size().div_ceil(self.page_size);
+        self.alloc_pages(count).map(|addr| unsafe { NonNull::new_unchecked(addr as *mut u8) })
+    }
+
+    fn deallocate(&mut self, ptr: NonNull<u8>, _: Layout)
+    {
+        self.free_pages(ptr.as_ptr() as usize);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use HostRegion;
+
+    fn allocate_region(size: usize) -> MemoryRegion
+    {
+        HostRegion::new(size, 16).unwrap().leak()
+    }
+
+    #[test]
+    fn test_new()
+    {
+        let region = allocate_region(64 * 1024);
+        let pages  = PageAllocator::new(&region, 4096).unwrap();
+        assert_eq!(pages.base_addr % 4096, 0);
+        assert!(region.addr() + pages.num_pages() * mem::size_of::<usize>() <= pages.base_addr);
+        assert!(pages.base_addr + pages.num_pages() * 4096 <= region.addr() + region.size());
+        assert_eq!(PageAllocator::decode(pages.tags[0]), (pages.num_pages(), false));
+    }
+
+    #[test]
+    fn test_alloc_and_free()
+    {
+        let region    = allocate_region(64 * 1024);
+        let mut pages = PageAllocator::new(&region, 4096).unwrap();
+        let num_pages = pages.num_pages();
+
+        let addr1 = pages.alloc_pages(2).unwrap();
+        let addr2 = pages.alloc_pages(3).unwrap();
+        assert_eq!(addr1 % 4096, 0);
+        assert_eq!(addr2, addr1 + 2 * 4096);
+        assert!(pages.alloc_pages(num_pages).is_none());
+
+        pages.free_pages(addr1);
+        assert_eq!(pages.alloc_pages(1), Some(addr1));
+        pages.free_pages(addr1);
+
+        pages.free_pages(addr2);
+        assert_eq!(PageAllocator::decode(pages.tags[0]), (num_pages, false));
+        assert_eq!(pages.alloc_pages(num_pages), Some(addr1));
+    }
+}
@@ -0,0 +1,29 @@
+/// Source of random numbers used for randomized free block selection.
+pub trait EntropySource {
+    fn next_usize(&mut self) -> usize;
+}
+
+
+/// Policy chosen by the adaptive switching of `MemoryManager::set_adaptive_policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitMode {
+    SegregatedFit,
+    AddressOrdered,
+}
+
+
+/// Receiver of the switches made by `MemoryManager::set_adaptive_policy`, with the fragmentation in percent that caused each.
+pub trait PolicyObserver {
+    fn on_policy_change(&mut self, mode: FitMode, fragmentation: usize);
+}
+
+
+/// How a free block is chosen for each allocation.
+pub enum FitPolicy<'a> {
+    /// Take a block from the smallest populated size class that surely fits.
+    SegregatedFit,
+    /// Keep the free lists sorted by address to pack the allocations toward the low addresses.
+    AddressOrdered,
+    /// Pick at random among the given number of the best fitting blocks.
+    RandomFit(usize, &'a mut dyn EntropySource),
+}
@@ -0,0 +1,122 @@
+use core::mem;
+
+use tag::{BoundaryTag, LinkKey};
+use MemoryManager;
+
+
+const SIZE: usize = 512;
+
+
+#[repr(C, align(16))]
+struct Buffer([u8; SIZE]);
+
+
+fn any_size() -> usize
+{
+    let size: usize = kani::any();
+    kani::assume(0 < size && size <= SIZE / 4);
+    size
+}
+
+
+#[kani::proof]
+fn divide_preserves_bytes_and_alignment()
+{
+    let mut buffer = Buffer([0; SIZE]);
+    let addr       = buffer.0.as_mut_ptr() as usize;
+    let tag        = BoundaryTag::from_memory(addr, SIZE);
+
+    let request_size = any_size() * mem::align_of::<BoundaryTag>();
+    let (tag, new_tag_opt) = BoundaryTag::divide(tag, request_size, LinkKey::NONE);
+
+    let tag_ref = unsafe { tag.as_ref() };
+    match new_tag_opt {
+        None => assert_eq!(tag_ref.free_area_size(), SIZE - mem::size_of::<BoundaryTag>()),
+        Some(new_tag) => {
+            let new_tag_ref = unsafe { new_tag.as_ref() };
+            assert_eq!(new_tag_ref.free_area_size(), request_size);
+            assert_eq!(tag_ref.free_area_size() + new_tag_ref.free_area_size() + 2 * mem::size_of::<BoundaryTag>(), SIZE);
+            assert_eq!(new_tag_ref.addr() % mem::align_of::<BoundaryTag>(), 0);
+            assert_eq!(new_tag_ref.addr_free_area() + new_tag_ref.free_area_size(), addr + SIZE);
+            assert_eq!(tag_ref.next_tag_addr(), Some(new_tag_ref.addr()));
+            assert_eq!(new_tag_ref.prev_tag_addr(LinkKey::NONE), Some(addr));
+        },
+    }
+}
+
+
+#[kani::proof]
+fn merge_of_adjacent_blocks_preserves_chain()
+{
+    let mut buffer = Buffer([0; SIZE]);
+    let addr       = buffer.0.as_mut_ptr() as usize;
+    let tag        = BoundaryTag::from_memory(addr, SIZE);
+
+    let (tag, tag2) = BoundaryTag::divide(tag, any_size() * mem::align_of::<BoundaryTag>(), LinkKey::NONE);
+    let (tag, tag1) = BoundaryTag::divide(tag, any_size() * mem::align_of::<BoundaryTag>(), LinkKey::NONE);
+    let tag1 = tag1.unwrap();
+    let tag2 = tag2.unwrap();
+
+    // Merge either pair of neighbors, given in either order.
+    let merged =
+        if kani::any() {
+            if kani::any() { BoundaryTag::merge(tag, tag1, LinkKey::NONE) } else { BoundaryTag::merge(tag1, tag, LinkKey::NONE) }
+        } else {
+            if kani::any() { BoundaryTag::merge(tag1, tag2, LinkKey::NONE) } else { BoundaryTag::merge(tag2, tag1, LinkKey::NONE) }
+        };
+
+    let first = unsafe { BoundaryTag::<()>::new_from_addr(addr) };
+    let first_ref = unsafe { first.as_ref() };
+    assert_eq!(first_ref.prev_tag_addr(LinkKey::NONE), None);
+
+    let merged_ref = unsafe { merged.as_ref() };
+    match merged_ref.next_tag_addr() {
+        None => assert_eq!(merged_ref.addr_free_area() + merged_ref.free_area_size(), addr + SIZE),
+        Some(next_addr) => {
+            assert_eq!(next_addr, merged_ref.addr_free_area() + merged_ref.free_area_size());
+            let next = unsafe { BoundaryTag::<()>::new_from_addr(next_addr) };
+            assert_eq!(unsafe { next.as_ref() }.prev_tag_addr(LinkKey::NONE), Some(merged_ref.addr()));
+        },
+    }
+}
+
+
+#[kani::proof]
+#[kani::unwind(8)]
+fn free_never_overlaps_blocks()
+{
+    let mut buffer = Buffer([0; SIZE]);
+    let mut tags   = [BoundaryTag::from_memory(buffer.0.as_mut_ptr() as usize, SIZE)];
+    let mut mman   = MemoryManager::new(&mut tags);
+
+    let size1 = any_size();
+    let size2 = any_size();
+    let size3 = any_size();
+    let addr1 = mman.alloc_bytes(size1);
+    let addr2 = mman.alloc_bytes(size2);
+    let addr3 = mman.alloc_bytes(size3);
+
+    // Free the middle block either first or last, covering the merges with either neighbor and with both.
+    let middle_first: bool = kani::any();
+    if let (true, Some(addr)) = (middle_first, addr2) {
+        mman.free_bytes(addr);
+    }
+    if let Some(addr) = addr1 {
+        mman.free_bytes(addr);
+    }
+    assert_eq!(mman.check_integrity(), Ok(()));
+    if let (false, Some(addr)) = (middle_first, addr2) {
+        mman.free_bytes(addr);
+    }
+    assert_eq!(mman.check_integrity(), Ok(()));
+
+    // The remaining block is still inside a single block of the chain.
+    if let Some(addr) = addr3 {
+        let block = mman.block_of(addr).unwrap();
+        assert!(block.is_alloc);
+        assert!(addr + size3 <= block.addr + block.size);
+        mman.free_bytes(addr);
+    }
+    assert_eq!(mman.check_integrity(), Ok(()));
+    assert_eq!(mman.stats().used_blocks, 0);
+}
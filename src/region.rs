@@ -0,0 +1,101 @@
+#[repr(C)]
+pub struct MemoryRegion {
+    addr: usize,
+    size: usize,
+}
+
+
+impl MemoryRegion {
+    pub fn new(addr: usize, size: usize) -> MemoryRegion
+    {
+        MemoryRegion {
+            addr,
+            size,
+        }
+    }
+
+
+    pub fn addr(&self) -> usize
+    {
+        self.addr
+    }
+
+
+    pub fn size(&self) -> usize
+    {
+        self.size
+    }
+}
+
+
+/// Provider of memory from outside of the managed regions, such as pages mapped by the kernel.
+pub trait MemorySource {
+    /// Return a new region of at least `size` bytes, or `None` if the source is exhausted.
+    fn request(&mut self, size: usize) -> Option<MemoryRegion>;
+
+    /// Give back a region obtained by `request`.
+    fn release(&mut self, region: MemoryRegion);
+}
+
+
+/// Translation between the virtual addresses the manager works on and the physical addresses seen by the devices,
+/// such as a higher-half kernel handing DMA buffers to a device.
+pub trait PhysToVirt {
+    fn virt_to_phys(&self, vaddr: usize) -> usize;
+
+    fn phys_to_virt(&self, paddr: usize) -> usize;
+}
+
+
+/// `PhysToVirt` of a memory mapped at a fixed offset from its physical addresses, like the direct map of a higher-half kernel.
+pub struct LinearMapping {
+    offset: usize,
+}
+
+
+impl LinearMapping {
+    pub const fn new(offset: usize) -> LinearMapping
+    {
+        LinearMapping {
+            offset,
+        }
+    }
+}
+
+
+impl PhysToVirt for LinearMapping {
+    fn virt_to_phys(&self, vaddr: usize) -> usize
+    {
+        vaddr.wrapping_sub(self.offset)
+    }
+
+    fn phys_to_virt(&self, paddr: usize) -> usize
+    {
+        paddr.wrapping_add(self.offset)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all() {
+        const SIZE: usize = 4096;
+        let x = [0usize; SIZE];
+        let addr = x.as_ptr() as usize;
+
+        let r = MemoryRegion::new(addr, SIZE);
+        assert_eq!(addr, r.addr());
+        assert_eq!(SIZE, r.size());
+    }
+
+    #[test]
+    fn test_linear_mapping()
+    {
+        let mapping = LinearMapping::new(0xFFFF_8000_0000_0000);
+        assert_eq!(mapping.virt_to_phys(0xFFFF_8000_0010_0000), 0x10_0000);
+        assert_eq!(mapping.phys_to_virt(0x10_0000), 0xFFFF_8000_0010_0000);
+    }
+}
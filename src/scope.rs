@@ -0,0 +1,107 @@
+use core::cmp;
+use core::mem;
+use core::ptr;
+
+use super::MemoryManager;
+use super::round_up;
+
+
+/// Allocations that are freed together when the scope is dropped.
+/// Each allocation is prefixed with a link to the previous one, so no other bookkeeping is needed.
+/// The references handed out must not outlive the scope.
+pub struct Scope<'heap, 'a: 'heap> {
+    mman: &'heap mut MemoryManager<'a>,
+    last_addr: Option<usize>,
+    count: usize,
+}
+
+
+impl<'heap, 'a: 'heap> Scope<'heap, 'a> {
+    pub fn new(mman: &'heap mut MemoryManager<'a>) -> Scope<'heap, 'a>
+    {
+        Scope {
+            mman,
+            last_addr: None,
+            count: 0,
+        }
+    }
+
+    /// The number of the live allocations made through the scope.
+    pub fn len(&self) -> usize
+    {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.count == 0
+    }
+
+    pub fn alloc<'b, T>(&mut self) -> Option<&'b mut T>
+    {
+        let offset = Scope::object_offset::<T>();
+        let addr = self.mman.alloc_bytes(offset + mem::size_of::<T>())?;
+
+        unsafe { ptr::write(addr as *mut usize, self.last_addr.unwrap_or(0)) };
+        self.last_addr = Some(addr);
+        self.count += 1;
+
+        Some(unsafe { &mut *((addr + offset) as *mut T) })
+    }
+
+    fn object_offset<T>() -> usize
+    {
+        round_up(mem::size_of::<usize>(), cmp::max(mem::align_of::<T>(), mem::align_of::<usize>()))
+    }
+}
+
+
+impl<'heap, 'a: 'heap> Drop for Scope<'heap, 'a> {
+    fn drop(&mut self)
+    {
+        while let Some(addr) = self.last_addr {
+            let prev_addr = unsafe { ptr::read(addr as *const usize) };
+            self.last_addr = if prev_addr == 0 { None } else { Some(prev_addr) };
+            self.mman.free_bytes(addr);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use core::mem;
+
+    use super::*;
+    use BoundaryTag;
+
+    use HostRegion;
+
+    fn allocate_memory() -> (usize, usize)
+    {
+        const SIZE: usize = 4096;
+        let addr = HostRegion::new(SIZE, 16).unwrap().leak().addr();
+
+        (addr, SIZE)
+    }
+
+    #[test]
+    fn test_free_on_drop()
+    {
+        let (addr, size) = allocate_memory();
+        let mut tags = [BoundaryTag::from_memory(addr, size)];
+        let mut mman = MemoryManager::new(&mut tags);
+        {
+            let mut scope = Scope::new(&mut mman);
+            let buffer    = scope.alloc::<[u8; 256]>().unwrap();
+            let value     = scope.alloc::<u64>().unwrap();
+            buffer[255]   = 0xAF;
+            *value        = 0xFFFF;
+            assert_eq!(buffer[255], 0xAF);
+            assert_eq!(scope.len(), 2);
+        }
+
+        assert_eq!(mman.check_integrity(), Ok(()));
+        assert_eq!(unsafe { mman.tags[0].as_ref() }.free_area_size(), size - mem::size_of::<BoundaryTag>());
+    }
+}
@@ -0,0 +1,144 @@
+use core::cmp;
+use core::mem;
+
+
+const BITS_PER_WORD: usize = mem::size_of::<usize>() * 8;
+
+
+/// A bit per granule of a heap range, set for the granules of the allocated payloads.
+/// The tags between the payloads are never set, so the set bits of two neighboring blocks never touch.
+pub(crate) struct ShadowMap<'a> {
+    base: usize,
+    granule: usize,
+    bits: &'a mut [usize],
+}
+
+
+impl<'a> ShadowMap<'a> {
+    /// The number of words covering `size` bytes in granules of `granule` bytes.
+    pub(crate) fn words_for(size: usize, granule: usize) -> usize
+    {
+        size.div_ceil(granule).div_ceil(BITS_PER_WORD)
+    }
+
+    pub(crate) fn new(base: usize, granule: usize, bits: &'a mut [usize]) -> ShadowMap<'a>
+    {
+        debug_check!(granule.is_power_of_two() && base.is_multiple_of(granule));
+
+        let mut map = ShadowMap {
+            base,
+            granule,
+            bits,
+        };
+        map.clear();
+        map
+    }
+
+    /// Clear every bit, as when no block is allocated.
+    pub(crate) fn clear(&mut self)
+    {
+        for word in self.bits.iter_mut() {
+            *word = 0;
+        }
+    }
+
+    fn index_of(&self, addr: usize) -> Option<usize>
+    {
+        if addr < self.base {
+            return None;
+        }
+
+        let index = (addr - self.base) / self.granule;
+        if index < self.bits.len() * BITS_PER_WORD { Some(index) } else { None }
+    }
+
+    /// Set or clear the bits of the granules of `size` bytes at `addr`; the granules out of the map are ignored.
+    pub(crate) fn mark(&mut self, addr: usize, size: usize, is_alloc: bool)
+    {
+        let mut granule = addr;
+        while granule < addr + size {
+            if let Some(index) = self.index_of(granule) {
+                let mask = 1 << (index % BITS_PER_WORD);
+                if is_alloc {
+                    self.bits[index / BITS_PER_WORD] |= mask;
+                } else {
+                    self.bits[index / BITS_PER_WORD] &= !mask;
+                }
+            }
+            granule += self.granule;
+        }
+    }
+
+    /// Whether the map covers `addr` at all.
+    pub(crate) fn covers(&self, addr: usize) -> bool
+    {
+        self.index_of(addr).is_some()
+    }
+
+    pub(crate) fn is_set(&self, addr: usize) -> bool
+    {
+        match self.index_of(addr) {
+            None => false,
+            Some(index) => self.bits[index / BITS_PER_WORD] & (1 << (index % BITS_PER_WORD)) != 0,
+        }
+    }
+
+    /// Whether every granule of the `len` bytes at `addr` is set, a word at a time.
+    pub(crate) fn is_range_set(&self, addr: usize, len: usize) -> bool
+    {
+        let first = match self.index_of(addr) {
+            None => return false,
+            Some(index) => index,
+        };
+        let last = match self.index_of(addr + cmp::max(len, 1) - 1) {
+            None => return false,
+            Some(index) => index,
+        };
+
+        let mut index = first;
+        while index <= last {
+            let bit   = index % BITS_PER_WORD;
+            let count = cmp::min(BITS_PER_WORD - bit, last - index + 1);
+            let mask  = if count == BITS_PER_WORD { !0 } else { ((1 << count) - 1) << bit };
+            if self.bits[index / BITS_PER_WORD] & mask != mask {
+                return false;
+            }
+            index += count;
+        }
+
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shadow_map()
+    {
+        let mut bits = [!0; 2];
+        let base     = 0x1000;
+        let mut map  = ShadowMap::new(base, 16, &mut bits);
+        assert!(!map.is_set(base));
+        assert!(!map.covers(base - 16));
+        assert!(!map.covers(base + 128 * 16));
+
+        map.mark(base + 16, 100 * 16, true);
+        assert!(!map.is_set(base));
+        assert!(map.is_set(base + 16));
+        assert!(map.is_set(base + 100 * 16 + 15));
+        assert!(!map.is_set(base + 101 * 16));
+
+        // The range crosses the boundary of the words.
+        assert!(map.is_range_set(base + 16, 100 * 16));
+        assert!(map.is_range_set(base + 60 * 16 + 3, 10 * 16));
+        assert!(!map.is_range_set(base, 32));
+        assert!(!map.is_range_set(base + 16, 101 * 16));
+
+        map.mark(base + 64 * 16, 16, false);
+        assert!(!map.is_range_set(base + 16, 100 * 16));
+        assert!(map.is_range_set(base + 16, 63 * 16));
+    }
+}
@@ -0,0 +1,246 @@
+//! Synthetic workloads run against a heap, reporting how its fragmentation evolves,
+//! so that the split threshold and the size classes can be tuned on a host before they ship.
+
+use core::cmp;
+
+use manager::MemoryManager;
+
+
+/// Shape of the allocations of a workload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    /// Blocks of `small` or `large` bytes, `large_percent` of them large, freed at random.
+    Bimodal { small: usize, large: usize, large_percent: usize },
+    /// Blocks growing from `min` to `max` bytes over the run, each replacing the oldest one, such as a buffer resized again and again.
+    Ramp { min: usize, max: usize },
+    /// Blocks of `min` to `max` bytes allocated and freed at random.
+    Churn { min: usize, max: usize },
+    /// Blocks of `size` bytes freed in the order of their allocations, as a queue of up to `depth` messages between two tasks.
+    ProducerConsumer { size: usize, depth: usize },
+}
+
+
+impl Pattern {
+    fn is_valid(&self) -> bool
+    {
+        match *self {
+            Pattern::Bimodal { .. } => true,
+            Pattern::Ramp { min, max } | Pattern::Churn { min, max } => min <= max,
+            Pattern::ProducerConsumer { depth, .. } => depth != 0,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Workload {
+    pub pattern: Pattern,
+    pub steps: usize,
+    /// Seed of the generator, so that a workload always makes the same operations.
+    pub seed: u64,
+}
+
+
+/// State of the heap after a step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Sample {
+    pub step: usize,
+    pub used_bytes: usize,
+    pub fragmentation: usize,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SimReport {
+    pub allocs: usize,
+    pub frees: usize,
+    /// The allocations the heap could not serve, which are skipped.
+    pub failed: usize,
+    pub peak_used_bytes: usize,
+    pub peak_fragmentation: usize,
+    /// How many samples were written.
+    pub num_samples: usize,
+}
+
+
+/// xorshift64, so that a seed reproduces the same workload.
+struct Rng(u64);
+
+
+impl Rng {
+    fn next(&mut self) -> usize
+    {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+
+    /// A number in `min..=max`, which must not be empty.
+    fn between(&mut self, min: usize, max: usize) -> usize
+    {
+        match (max - min).checked_add(1) {
+            // The range covers every number.
+            None => self.next(),
+            Some(len) => min + self.next() % len,
+        }
+    }
+}
+
+
+/// Run `workload` against `mman`, keeping up to `live.len()` blocks at once, and free every block left at the end.
+/// The state of the heap is written into `samples` every `interval` steps until it is full.
+/// Nothing is run and an empty report is returned if `live` is empty, `interval` is zero or the pattern is invalid,
+/// such as a `min` above `max` or a queue of no depth.
+pub fn simulate(mman: &mut MemoryManager, workload: &Workload, live: &mut [Option<usize>], samples: &mut [Sample], interval: usize) -> SimReport
+{
+    if live.is_empty() || interval == 0 || !workload.pattern.is_valid() {
+        return SimReport::default();
+    }
+
+    // A zero seed would make xorshift return zeros forever.
+    let mut rng    = Rng(cmp::max(workload.seed, 1));
+    let mut report = SimReport::default();
+    // The oldest slot of the queue of `ProducerConsumer` and the number of the blocks in it.
+    let mut head   = 0;
+    let mut queued = 0;
+
+    for step in 0..workload.steps {
+        let (slot, size) =
+            match workload.pattern {
+                Pattern::Bimodal { small, large, large_percent } => {
+                    let size = if rng.next() % 100 < large_percent { large } else { small };
+                    (rng.next() % live.len(), size)
+                },
+                Pattern::Ramp { min, max } => {
+                    let size = min + (max - min) * step / cmp::max(workload.steps, 1);
+                    (step % live.len(), size)
+                },
+                Pattern::Churn { min, max } => (rng.next() % live.len(), rng.between(min, max)),
+                Pattern::ProducerConsumer { size, depth } => {
+                    let depth = cmp::min(depth, live.len());
+                    if queued == depth || (queued != 0 && rng.next().is_multiple_of(2)) {
+                        let slot = head;
+                        head    = (head + 1) % depth;
+                        queued -= 1;
+                        (slot, 0)
+                    } else {
+                        queued += 1;
+                        ((head + queued - 1) % depth, size)
+                    }
+                },
+            };
+
+        // Only `Ramp` allocates into an occupied slot, replacing the block.
+        let replace = matches!(workload.pattern, Pattern::Ramp { .. });
+        match live[slot].take() {
+            Some(addr) => {
+                mman.free_bytes(addr);
+                report.frees += 1;
+                if replace {
+                    allocate(mman, size, &mut live[slot], &mut report);
+                }
+            },
+            None if size != 0 => allocate(mman, size, &mut live[slot], &mut report),
+            // The consumer found the producer failed to allocate the message.
+            None => (),
+        }
+
+        let used_bytes    = mman.stats().used_bytes;
+        let fragmentation = mman.fragmentation();
+        report.peak_used_bytes    = cmp::max(report.peak_used_bytes, used_bytes);
+        report.peak_fragmentation = cmp::max(report.peak_fragmentation, fragmentation);
+        if step % interval == 0 && report.num_samples < samples.len() {
+            samples[report.num_samples] = Sample { step, used_bytes, fragmentation };
+            report.num_samples += 1;
+        }
+    }
+
+    for slot in live.iter_mut() {
+        if let Some(addr) = slot.take() {
+            mman.free_bytes(addr);
+            report.frees += 1;
+        }
+    }
+
+    report
+}
+
+
+fn allocate(mman: &mut MemoryManager, size: usize, slot: &mut Option<usize>, report: &mut SimReport)
+{
+    *slot = mman.alloc_bytes(size);
+    if slot.is_some() {
+        report.allocs += 1;
+    } else {
+        report.failed += 1;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use BoundaryTag;
+    use HostRegion;
+
+    #[test]
+    fn test_simulate()
+    {
+        let host = HostRegion::new(128 * 1024, 16).unwrap();
+        let patterns = [
+            Pattern::Bimodal { small: 32, large: 1024, large_percent: 10 },
+            Pattern::Ramp { min: 16, max: 1024 },
+            Pattern::Churn { min: 8, max: 512 },
+            Pattern::ProducerConsumer { size: 256, depth: 16 },
+        ];
+
+        for &pattern in patterns.iter() {
+            let mut tags    = [BoundaryTag::from_memory(host.addr(), host.size())];
+            let mut mman    = MemoryManager::new(&mut tags);
+            let initial     = mman.stats();
+            let workload    = Workload { pattern, steps: 1000, seed: 7 };
+            let mut live    = [None; 32];
+            let mut samples = [Sample::default(); 8];
+
+            let report = simulate(&mut mman, &workload, &mut live, &mut samples, 100);
+            assert_eq!(report.allocs, report.frees);
+            assert_eq!(report.failed, 0);
+            assert_eq!(report.num_samples, 8);
+            assert_eq!(samples[7].step, 700);
+            assert!(samples.iter().all(|sample| sample.used_bytes <= report.peak_used_bytes));
+            assert!(report.peak_fragmentation < 100);
+
+            // The same seed makes the same operations.
+            let mut again = [Sample::default(); 8];
+            assert_eq!(simulate(&mut mman, &workload, &mut live, &mut again, 100), report);
+            assert_eq!(again, samples);
+
+            assert_eq!(mman.stats(), initial);
+            assert_eq!(mman.check_integrity(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_simulate_invalid()
+    {
+        let host = HostRegion::new(4096, 16).unwrap();
+        let mut tags    = [BoundaryTag::from_memory(host.addr(), host.size())];
+        let mut mman    = MemoryManager::new(&mut tags);
+        let mut live    = [None; 4];
+        let mut samples = [Sample::default(); 4];
+        let patterns = [
+            Pattern::ProducerConsumer { size: 64, depth: 0 },
+            Pattern::Ramp { min: 64, max: 16 },
+            Pattern::Churn { min: 64, max: 16 },
+        ];
+
+        for &pattern in patterns.iter() {
+            let workload = Workload { pattern, steps: 100, seed: 7 };
+            assert_eq!(simulate(&mut mman, &workload, &mut live, &mut samples, 10), SimReport::default());
+        }
+        let workload = Workload { pattern: Pattern::Churn { min: 16, max: 64 }, steps: 100, seed: 7 };
+        assert_eq!(simulate(&mut mman, &workload, &mut [], &mut samples, 10), SimReport::default());
+        assert_eq!(simulate(&mut mman, &workload, &mut live, &mut samples, 0), SimReport::default());
+    }
+}
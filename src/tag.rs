@@ -0,0 +1,933 @@
+use core::mem;
+use core::ops::Range;
+#[cfg(feature = "track-caller")]
+use core::panic::Location;
+use core::ptr;
+use core::ptr::Unique;
+
+use super::round_up;
+use error::RegionError;
+use manager::MIN_PAYLOAD_SIZE;
+
+
+/// Key of the previous links stored by the tags of a heap.
+/// Every manager has its own key; the tags made before a manager takes them over are keyed with `LinkKey::NONE`.
+#[cfg(feature = "harden")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LinkKey(usize);
+
+#[cfg(not(feature = "harden"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LinkKey;
+
+
+#[cfg(feature = "harden")]
+impl LinkKey {
+    pub(crate) const NONE: LinkKey = LinkKey(0);
+
+    pub(crate) fn new(secret: usize) -> LinkKey
+    {
+        LinkKey(secret)
+    }
+
+    /// The key of a heap given no secret, derived from the address of its first region.
+    /// It only keeps the links of a heap from being valid in another one, since the address can be guessed.
+    pub(crate) fn of_addr(addr: usize) -> LinkKey
+    {
+        LinkKey(addr.rotate_left(23) ^ 0x2545_F491)
+    }
+}
+
+#[cfg(not(feature = "harden"))]
+impl LinkKey {
+    pub(crate) const NONE: LinkKey = LinkKey;
+
+    pub(crate) fn of_addr(_: usize) -> LinkKey
+    {
+        LinkKey
+    }
+}
+
+
+/// Encode or decode a link stored at `slot`.
+/// Mixing in the slot address means an overwritten link cannot be forged without knowing both the key and where it lives.
+#[cfg(feature = "harden")]
+fn mangle_link(key: LinkKey, slot: usize, addr: usize) -> usize
+{
+    addr ^ (slot >> 4) ^ key.0
+}
+
+#[cfg(not(feature = "harden"))]
+fn mangle_link(_: LinkKey, _: usize, addr: usize) -> usize
+{
+    addr
+}
+
+
+/// Encode or decode a distance to a neighboring tag stored at `slot` under `u32-sizes`, mangled like a link.
+#[cfg(feature = "u32-sizes")]
+fn mangle_distance(key: LinkKey, slot: usize, distance: u32) -> u32
+{
+    distance ^ mangle_link(key, slot, 0) as u32
+}
+
+
+/// Word of the sizes in a tag; `u32-sizes` halves the header of a heap smaller than 4 GiB on a 64-bit target.
+#[cfg(feature = "u32-sizes")]
+pub(crate) type SizeWord = u32;
+
+#[cfg(not(feature = "u32-sizes"))]
+pub(crate) type SizeWord = usize;
+
+
+/// Header of a block, placed right before its payload.
+/// The links of the free lists are not part of it: a free block keeps them in its unused payload,
+/// which is why every payload has `MIN_PAYLOAD_SIZE` bytes at least, so an allocated block carries only its size, flags and previous neighbor.
+/// The next tag always starts right after the payload, so it is derived from the size and only the end of the chain is flagged.
+#[repr(C)]
+// Under `mte` the tags and the payloads start on the granules of the allocation tags.
+#[cfg_attr(all(feature = "mte", target_arch = "aarch64"), repr(align(16)))]
+// The payloads keep the alignment of a word even though the header holds no word under `u32-sizes`.
+#[cfg_attr(all(feature = "u32-sizes", not(all(feature = "mte", target_arch = "aarch64"))), repr(align(8)))]
+#[derive(Debug)]
+pub struct BoundaryTag<M = ()> {
+    pub(crate) is_alloc: bool,
+    pub(crate) is_movable: bool,
+    /// The last page before the end of the payload is unmapped by the `VmHook` of the manager.
+    pub(crate) is_guarded: bool,
+    /// The block covers a hole of the region given to `with_holes`, so it is never freed, moved nor reported.
+    pub(crate) is_reserved: bool,
+    /// The block is a whole region of the huge allocation source, given back to the source when it is freed.
+    pub(crate) is_huge: bool,
+    /// The payload is wiped when the block is freed.
+    #[cfg(feature = "zeroize")]
+    pub(crate) is_secret: bool,
+    /// The block is the first of its chain, so the previous link means nothing.
+    pub(crate) is_first: bool,
+    /// The block is the last of its chain, so nothing follows its payload.
+    pub(crate) is_last: bool,
+    pub(crate) free_area_size: SizeWord,
+    #[cfg(not(feature = "u32-sizes"))]
+    pub(crate) prev_tag_addr: usize,
+    /// The distance to the previous tag instead of its address.
+    #[cfg(feature = "u32-sizes")]
+    pub(crate) prev_tag_distance: u32,
+    #[cfg(feature = "checksum")]
+    pub(crate) checksum: usize,
+    /// Where the block was allocated, for the leak and the corruption reports.
+    #[cfg(feature = "track-caller")]
+    pub(crate) location: Option<&'static Location<'static>>,
+    /// Word of the embedder, such as an owner ID or a type code; it is not covered by the checksum.
+    pub(crate) user_data: M,
+}
+
+
+/// Tag of a region made by `BoundaryTag::try_from_memory`, with the bytes cut off from both ends of the region to align it.
+pub struct RegionTag<M = ()> {
+    pub tag: Unique<BoundaryTag<M>>,
+    pub head_trimmed: usize,
+    pub tail_trimmed: usize,
+}
+
+
+impl BoundaryTag {
+    /// Create the tag of a region; `addr` must be aligned for the tag and `size` must leave room for a block.
+    /// See `try_from_memory` for any other region.
+    pub fn from_memory(addr: usize, size: usize) -> Unique<BoundaryTag>
+    {
+        BoundaryTag::with_user_data(addr, size)
+    }
+
+    /// Create the tag of a region after aligning its start up and its end down,
+    /// or tell why the region cannot hold even the smallest block.
+    pub fn try_from_memory(addr: usize, size: usize) -> Result<RegionTag, RegionError>
+    {
+        BoundaryTag::try_with_user_data(addr, size)
+    }
+
+    /// Create the tag of a region containing `holes`; see `with_holes`.
+    pub fn from_memory_with_holes(addr: usize, size: usize, holes: &[Range<usize>]) -> Result<Unique<BoundaryTag>, RegionError>
+    {
+        BoundaryTag::with_holes(addr, size, holes)
+    }
+}
+
+
+impl<M: Copy + Default> BoundaryTag<M> {
+    pub(crate) fn addr(&self) -> usize
+    {
+        (self as *const _) as usize
+    }
+
+    pub(crate) fn addr_free_area(&self) -> usize
+    {
+        self.addr() + mem::size_of::<BoundaryTag<M>>()
+    }
+
+    /// The payload bytes of the block.
+    // The cast is only needed under `u32-sizes`.
+    #[allow(clippy::unnecessary_cast)]
+    pub(crate) fn free_area_size(&self) -> usize
+    {
+        self.free_area_size as usize
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    pub(crate) fn set_free_area_size(&mut self, size: usize)
+    {
+        debug_check!(size <= SizeWord::MAX as usize, "block too large for the size word");
+        self.free_area_size = size as SizeWord;
+    }
+
+    #[cfg(not(feature = "u32-sizes"))]
+    pub(crate) fn prev_tag_addr(&self, key: LinkKey) -> Option<usize>
+    {
+        if self.is_first { None } else { Some(mangle_link(key, self.addr(), self.prev_tag_addr)) }
+    }
+
+    #[cfg(not(feature = "u32-sizes"))]
+    pub(crate) fn set_prev_tag_addr(&mut self, key: LinkKey, addr: Option<usize>)
+    {
+        let slot = self.addr();
+        self.is_first      = addr.is_none();
+        self.prev_tag_addr = mangle_link(key, slot, addr.unwrap_or(0));
+    }
+
+    #[cfg(feature = "u32-sizes")]
+    pub(crate) fn prev_tag_addr(&self, key: LinkKey) -> Option<usize>
+    {
+        let slot = self.addr();
+        if self.is_first { None } else { Some(slot - mangle_distance(key, slot, self.prev_tag_distance) as usize) }
+    }
+
+    #[cfg(feature = "u32-sizes")]
+    pub(crate) fn set_prev_tag_addr(&mut self, key: LinkKey, addr: Option<usize>)
+    {
+        let slot = self.addr();
+        debug_check!(addr.is_none_or(|a| a < slot && slot - a <= u32::MAX as usize));
+        self.is_first          = addr.is_none();
+        self.prev_tag_distance = mangle_distance(key, slot, addr.map_or(0, |a| (slot - a) as u32));
+    }
+
+    /// The tag right after the payload, unless the block ends its chain.
+    pub(crate) fn next_tag_addr(&self) -> Option<usize>
+    {
+        if self.is_last { None } else { Some(self.addr_free_area() + self.free_area_size()) }
+    }
+
+    /// Mark the block as the end of its chain or not; the size must already reach the next tag.
+    pub(crate) fn set_last(&mut self, is_last: bool)
+    {
+        self.is_last = is_last;
+    }
+
+    /// The stored link as it is, for the checksum.
+    #[cfg(all(feature = "checksum", not(feature = "u32-sizes")))]
+    fn raw_link(&self) -> usize
+    {
+        self.prev_tag_addr
+    }
+
+    #[cfg(all(feature = "checksum", feature = "u32-sizes"))]
+    fn raw_link(&self) -> usize
+    {
+        self.prev_tag_distance as usize
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_last(&self) -> bool
+    {
+        self.is_last
+    }
+
+    /// The last tag of the chain this tag belongs to.
+    pub(crate) fn last_of_chain(&self) -> &BoundaryTag<M>
+    {
+        let mut last = self;
+        while let Some(addr) = last.next_tag_addr() {
+            last = unsafe { &*(addr as *const BoundaryTag<M>) };
+        }
+
+        last
+    }
+
+    /// Read the link of an internal list stored in the payload of the block.
+    pub(crate) fn payload_link(&self) -> Option<usize>
+    {
+        let addr = unsafe { ptr::read_unaligned(self.addr_free_area() as *const usize) };
+        if addr == 0 { None } else { Some(addr) }
+    }
+
+    pub(crate) fn set_payload_link(&self, addr: Option<usize>)
+    {
+        unsafe { ptr::write_unaligned(self.addr_free_area() as *mut usize, addr.unwrap_or(0)) };
+    }
+
+    /// Read the second link stored in the payload, used by the doubly linked free lists.
+    pub(crate) fn payload_back_link(&self) -> Option<usize>
+    {
+        let addr = unsafe { ptr::read_unaligned((self.addr_free_area() + mem::size_of::<usize>()) as *const usize) };
+        if addr == 0 { None } else { Some(addr) }
+    }
+
+    pub(crate) fn set_payload_back_link(&self, addr: Option<usize>)
+    {
+        unsafe { ptr::write_unaligned((self.addr_free_area() + mem::size_of::<usize>()) as *mut usize, addr.unwrap_or(0)) };
+    }
+
+    #[cfg(feature = "track-caller")]
+    pub(crate) fn clear_location(&mut self)
+    {
+        self.location = None;
+    }
+
+    #[cfg(not(feature = "track-caller"))]
+    pub(crate) fn clear_location(&mut self)
+    {
+    }
+
+    #[cfg(feature = "checksum")]
+    fn compute_checksum(&self) -> usize
+    {
+        const SEED: usize = 0x5A5A_A5A5;
+
+        let flags = (self.is_alloc as usize) | ((self.is_movable as usize) << 1) | ((self.is_guarded as usize) << 2) | ((self.is_reserved as usize) << 4) | ((self.is_huge as usize) << 5) | ((self.is_first as usize) << 6) | ((self.is_last as usize) << 7);
+        #[cfg(feature = "zeroize")]
+        let flags = flags | ((self.is_secret as usize) << 3);
+
+        SEED ^ self.addr() ^ flags ^ self.free_area_size().rotate_left(3) ^ self.raw_link().rotate_left(7)
+    }
+
+    /// Recompute the checksum after any header field has been changed.
+    #[cfg(feature = "checksum")]
+    pub(crate) fn update_checksum(&mut self)
+    {
+        self.checksum = self.compute_checksum();
+    }
+
+    #[cfg(not(feature = "checksum"))]
+    pub(crate) fn update_checksum(&mut self)
+    {
+    }
+
+    /// Panic with the location of the tag if its header was overwritten since the last update.
+    #[cfg(feature = "checksum")]
+    pub(crate) fn verify_checksum(&self)
+    {
+        let expected = self.compute_checksum();
+        if self.checksum != expected {
+            #[cfg(feature = "log")]
+            log::error!("BoundaryTag at 0x{:x} is corrupted: checksum 0x{:x}, expected 0x{:x}", self.addr(), self.checksum, expected);
+
+            #[cfg(feature = "track-caller")]
+            {
+                if let Some(location) = self.location {
+                    panic!("BoundaryTag at 0x{:x} allocated at {} is corrupted: checksum 0x{:x}, expected 0x{:x}", self.addr(), location, self.checksum, expected);
+                }
+            }
+            panic!("BoundaryTag at 0x{:x} is corrupted: checksum 0x{:x}, expected 0x{:x}", self.addr(), self.checksum, expected);
+        }
+    }
+
+    #[cfg(not(feature = "checksum"))]
+    pub(crate) fn verify_checksum(&self)
+    {
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_next_of(&self, tag: &Unique<BoundaryTag<M>>) -> bool
+    {
+        match BoundaryTag::<M>::next_tag_of(tag) {
+            Some(next_tag) => next_tag.as_ptr() as usize == self.addr(),
+            None => false,
+        }
+    }
+
+    pub(crate) fn is_prev_of(&self, tag: &Unique<BoundaryTag<M>>, key: LinkKey) -> bool
+    {
+        match BoundaryTag::<M>::prev_tag_of(tag, key) {
+            Some(prev_tag) => prev_tag.as_ptr() as usize == self.addr(),
+            None => false,
+        }
+    }
+
+    pub(crate) unsafe fn new_from_addr(addr: usize) -> Unique<BoundaryTag<M>>
+    {
+        Unique::new_unchecked(addr as *mut BoundaryTag<M>)
+    }
+
+    /// `try_from_memory` for the regions whose blocks carry a user data of type `M`.
+    pub fn try_with_user_data(addr: usize, size: usize) -> Result<RegionTag<M>, RegionError>
+    {
+        let align  = mem::align_of::<BoundaryTag<M>>();
+        let end    = addr.saturating_add(size);
+        let begin  = round_up(addr, align);
+        let usable = (end & !(align - 1)).saturating_sub(begin);
+        if usable < mem::size_of::<BoundaryTag<M>>() + MIN_PAYLOAD_SIZE {
+            return Err(RegionError::TooSmall(usable));
+        }
+
+        Ok(RegionTag {
+            tag: BoundaryTag::<M>::with_user_data(begin, usable),
+            head_trimmed: begin - addr,
+            tail_trimmed: end - (begin + usable),
+        })
+    }
+
+    /// Create the tag of a region whose blocks carry a user data of type `M`, initially `M::default()`.
+    pub fn with_user_data(addr: usize, size: usize) -> Unique<BoundaryTag<M>>
+    {
+        let mut tag = unsafe { BoundaryTag::<M>::new_from_addr(addr) };
+        {
+            let tag_mut        = unsafe {tag.as_mut()};
+            tag_mut.is_alloc       = false;
+            tag_mut.is_movable     = false;
+            tag_mut.is_guarded     = false;
+            tag_mut.is_reserved    = false;
+            tag_mut.is_huge        = false;
+            #[cfg(feature = "zeroize")]
+            {
+                tag_mut.is_secret = false;
+            }
+            tag_mut.set_free_area_size(size - mem::size_of::<BoundaryTag<M>>());
+            tag_mut.user_data      = M::default();
+            tag_mut.clear_location();
+            tag_mut.set_prev_tag_addr(LinkKey::NONE, None);
+            tag_mut.set_last(true);
+            tag_mut.update_checksum();
+        }
+
+        tag
+    }
+
+    /// Create the tag of a region containing `holes`, such as MMIO windows or a framebuffer inside the RAM,
+    /// instead of describing every fragment around them as a region of its own.
+    /// Each hole is covered by a block allocated for good, and a gap too small to be a block next to a hole is covered with it.
+    /// The holes must be sorted, apart from each other and inside the region, leaving room for a tag before each of them.
+    pub fn with_holes(addr: usize, size: usize, holes: &[Range<usize>]) -> Result<Unique<BoundaryTag<M>>, RegionError>
+    {
+        let align     = mem::align_of::<BoundaryTag<M>>();
+        let tag_size  = mem::size_of::<BoundaryTag<M>>();
+        let min_block = tag_size + MIN_PAYLOAD_SIZE;
+        let end       = addr + size;
+
+        let head = BoundaryTag::<M>::with_user_data(addr, size);
+        // Start of the free block at the end of the chain, or `end` once a hole reaches the end.
+        let mut free_begin = addr;
+        for (i, hole) in holes.iter().enumerate() {
+            if hole.end <= hole.start || hole.start < free_begin + tag_size || end < round_up(hole.end, align) {
+                return Err(RegionError::BadHole(i));
+            }
+
+            let tag_addr = (hole.start & !(align - 1)) - tag_size;
+            let tag_addr = if tag_addr < free_begin + min_block { free_begin } else { tag_addr };
+            let hole_end = round_up(hole.end, align);
+            let hole_end = if end - hole_end < min_block { end } else { hole_end };
+
+            let mut hole_tag =
+                if tag_addr == free_begin {
+                    unsafe { BoundaryTag::<M>::new_from_addr(tag_addr) }
+                } else {
+                    let mut free_tag = unsafe { BoundaryTag::<M>::new_from_addr(free_begin) };
+                    {
+                        let free_tag_mut = unsafe { free_tag.as_mut() };
+                        free_tag_mut.set_free_area_size(tag_addr - free_begin - tag_size);
+                        free_tag_mut.set_last(false);
+                        free_tag_mut.update_checksum();
+                    }
+                    let mut hole_tag = BoundaryTag::<M>::with_user_data(tag_addr, hole_end - tag_addr);
+                    unsafe { hole_tag.as_mut() }.set_prev_tag_addr(LinkKey::NONE, Some(free_begin));
+                    hole_tag
+                };
+
+            {
+                let hole_tag_mut = unsafe { hole_tag.as_mut() };
+                hole_tag_mut.is_alloc       = true;
+                hole_tag_mut.is_reserved    = true;
+                hole_tag_mut.set_free_area_size(hole_end - tag_addr - tag_size);
+                hole_tag_mut.set_last(true);
+                if hole_end < end {
+                    let mut free_tag = BoundaryTag::<M>::with_user_data(hole_end, end - hole_end);
+                    let free_tag_mut = unsafe { free_tag.as_mut() };
+                    free_tag_mut.set_prev_tag_addr(LinkKey::NONE, Some(tag_addr));
+                    free_tag_mut.update_checksum();
+                    hole_tag_mut.set_last(false);
+                }
+                hole_tag_mut.update_checksum();
+            }
+
+            free_begin = hole_end;
+        }
+
+        Ok(head)
+    }
+
+    pub(crate) fn divide(mut tag: Unique<BoundaryTag<M>>, request_size: usize, key: LinkKey) -> (Unique<BoundaryTag<M>>, Option<Unique<BoundaryTag<M>>>)
+    {
+        let new_tag =
+        {
+            let tag_mut = unsafe {tag.as_mut()};
+            let required_size = request_size + mem::size_of::<BoundaryTag<M>>();
+            if tag_mut.free_area_size() <= required_size {
+                None
+            } else {
+                let free_area_size     = tag_mut.free_area_size();
+                let next_tag_addr      = tag_mut.next_tag_addr();
+                let new_size = tag_mut.free_area_size() - required_size;
+                tag_mut.set_free_area_size(new_size);
+
+                // Create new block at the tail of the tag.
+                let new_tag_addr = tag_mut.addr_free_area() + free_area_size - required_size;
+                tag_mut.set_last(false);
+                tag_mut.update_checksum();
+
+                // The new block takes over the position of the tag in the chain.
+                let mut new_tag = BoundaryTag::<M>::with_user_data(new_tag_addr, required_size);
+                {
+                    let new_tag_mut = unsafe {new_tag.as_mut()};
+                    new_tag_mut.set_prev_tag_addr(key, Some(tag_mut.addr()));
+                    new_tag_mut.set_last(next_tag_addr.is_none());
+                    new_tag_mut.update_checksum();
+                }
+
+                if let Some(addr) = next_tag_addr {
+                    let mut next_tag = unsafe { BoundaryTag::<M>::new_from_addr(addr) };
+                    let next_tag_mut = unsafe { next_tag.as_mut() };
+                    next_tag_mut.set_prev_tag_addr(key, Some(new_tag_addr));
+                    next_tag_mut.update_checksum();
+                }
+
+                Some(new_tag)
+            }
+        };
+
+        (tag, new_tag)
+    }
+
+    pub(crate) fn merge(tag_x: Unique<BoundaryTag<M>>, tag_y: Unique<BoundaryTag<M>>, key: LinkKey) -> Unique<BoundaryTag<M>>
+    {
+        // The tags are neighbors, so the lower one comes first.
+        let (mut tag_prev, tag_next) =
+            if tag_x.as_ptr() < tag_y.as_ptr() {
+                (tag_x, tag_y)
+            } else {
+                (tag_y, tag_x)
+            };
+        debug_check!(unsafe { tag_prev.as_ref() }.is_prev_of(&tag_next, key), "merging the tags which are not neighbors");
+
+        {
+            let tag_next_ref = unsafe { tag_next.as_ref() };
+            let tag_prev_mut = unsafe { tag_prev.as_mut() };
+            let new_size = tag_prev_mut.free_area_size() + mem::size_of::<BoundaryTag<M>>() + tag_next_ref.free_area_size();
+            tag_prev_mut.set_free_area_size(new_size);
+            tag_prev_mut.set_last(tag_next_ref.is_last);
+            tag_prev_mut.update_checksum();
+        }
+
+        if let Some(mut next_next_tag) = BoundaryTag::<M>::next_tag_of(&tag_prev) {
+            let tag_prev_addr     = unsafe { tag_prev.as_ref() }.addr();
+            let next_next_tag_mut = unsafe { next_next_tag.as_mut() };
+            next_next_tag_mut.set_prev_tag_addr(key, Some(tag_prev_addr));
+            next_next_tag_mut.update_checksum();
+        }
+
+        tag_prev
+    }
+
+    /// Move the allocated block into the place of the free block before it and move the free block behind it.
+    /// Return the old and the new payload addresses, the payload size and the moved free block.
+    pub(crate) fn swap_with_prev_free(prev_tag: Unique<BoundaryTag<M>>, tag: Unique<BoundaryTag<M>>, key: LinkKey) -> (usize, usize, usize, Unique<BoundaryTag<M>>)
+    {
+        let prev_addr = unsafe { prev_tag.as_ref() }.addr();
+        let (free_area_size, next_tag_addr, old_addr, size, user_data) = {
+            let tag_ref = unsafe { tag.as_ref() };
+            debug_check!(tag_ref.prev_tag_addr(key) == Some(prev_addr));
+            debug_check!(!tag_ref.is_guarded, "moving a guarded block");
+            (tag_ref.free_area_size(), tag_ref.next_tag_addr(), tag_ref.addr_free_area(), tag_ref.free_area_size(), tag_ref.user_data)
+        };
+        #[cfg(feature = "track-caller")]
+        let location = unsafe { tag.as_ref() }.location;
+        #[cfg(feature = "zeroize")]
+        let is_secret = unsafe { tag.as_ref() }.is_secret;
+        let (prev_prev_tag_addr, prev_free_area_size) = {
+            let prev_tag_ref = unsafe { &*(prev_addr as *const BoundaryTag<M>) };
+            debug_check!(!prev_tag_ref.is_alloc);
+            (prev_tag_ref.prev_tag_addr(key), prev_tag_ref.free_area_size())
+        };
+
+        let new_addr = prev_addr + mem::size_of::<BoundaryTag<M>>();
+        unsafe { ptr::copy(old_addr as *const u8, new_addr as *mut u8, size) };
+
+        let mut moved_tag = BoundaryTag::<M>::with_user_data(prev_addr, mem::size_of::<BoundaryTag<M>>() + free_area_size);
+        let free_tag_addr = new_addr + free_area_size;
+        let mut free_tag  = BoundaryTag::<M>::with_user_data(free_tag_addr, mem::size_of::<BoundaryTag<M>>() + prev_free_area_size);
+        {
+            let moved_tag_mut = unsafe { moved_tag.as_mut() };
+            moved_tag_mut.is_alloc   = true;
+            moved_tag_mut.is_movable = true;
+            moved_tag_mut.user_data  = user_data;
+            #[cfg(feature = "track-caller")]
+            {
+                moved_tag_mut.location = location;
+            }
+            #[cfg(feature = "zeroize")]
+            {
+                moved_tag_mut.is_secret = is_secret;
+            }
+            moved_tag_mut.set_prev_tag_addr(key, prev_prev_tag_addr);
+            moved_tag_mut.set_last(false);
+            moved_tag_mut.update_checksum();
+
+            let free_tag_mut = unsafe { free_tag.as_mut() };
+            free_tag_mut.set_prev_tag_addr(key, Some(prev_addr));
+            free_tag_mut.set_last(next_tag_addr.is_none());
+            free_tag_mut.update_checksum();
+        }
+
+        if let Some(addr) = next_tag_addr {
+            let mut next_tag = unsafe { BoundaryTag::<M>::new_from_addr(addr) };
+            let next_tag_mut = unsafe { next_tag.as_mut() };
+            next_tag_mut.set_prev_tag_addr(key, Some(free_tag_addr));
+            next_tag_mut.update_checksum();
+        }
+
+        (old_addr, new_addr, size, free_tag)
+    }
+
+    /// Re-encode the previous links of the chain from `head`, keyed with `from`, with `to`.
+    pub(crate) fn rekey_chain(head: Unique<BoundaryTag<M>>, from: LinkKey, to: LinkKey)
+    {
+        if from == to {
+            return;
+        }
+
+        let mut tag_addr = Some(unsafe { head.as_ref() }.addr());
+        while let Some(addr) = tag_addr {
+            let tag_mut = unsafe { &mut *(addr as *mut BoundaryTag<M>) };
+            let prev    = tag_mut.prev_tag_addr(from);
+            tag_mut.set_prev_tag_addr(to, prev);
+            tag_mut.update_checksum();
+            tag_addr = tag_mut.next_tag_addr();
+        }
+    }
+
+    pub(crate) fn next_tag_of(tag: &Unique<BoundaryTag<M>>) -> Option<Unique<BoundaryTag<M>>>
+    {
+        let tag_ref = unsafe{ tag.as_ref() };
+        tag_ref.verify_checksum();
+        tag_ref.next_tag_addr().map(|addr| unsafe { BoundaryTag::<M>::new_from_addr(addr) })
+    }
+
+    pub(crate) fn prev_tag_of(tag: &Unique<BoundaryTag<M>>, key: LinkKey) -> Option<Unique<BoundaryTag<M>>>
+    {
+        let tag_ref = unsafe{ tag.as_ref() };
+        tag_ref.verify_checksum();
+        tag_ref.prev_tag_addr(key).map(|addr| unsafe { BoundaryTag::<M>::new_from_addr(addr) })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use core::mem;
+
+    use super::{BoundaryTag, LinkKey};
+    use error::RegionError;
+
+    use HostRegion;
+
+    fn allocate_memory() -> (usize, usize)
+    {
+        const SIZE: usize = 4096;
+        let addr = HostRegion::new(SIZE, 16).unwrap().leak().addr();
+
+        (addr, SIZE)
+    }
+
+    // #[test]
+    // fn test_tag_size()
+    // {
+    //     let (addr, size) = allocate_memory();
+    //     let tag = BoundaryTag::from_memory(addr, size);
+    //     assert_eq!(tag.free_area_size(), size - mem::size_of::<BoundaryTag>());
+
+    //     let request_size = size / 2;
+    //     let (tag, new_tag_opt) = BoundaryTag::divide(tag, request_size);
+    //     let new_tag = new_tag_opt.unwrap();
+    //     assert_eq!(tag.free_area_size(), size - mem::size_of::<BoundaryTag>() * 2 - request_size);
+    //     assert_eq!(new_tag.free_area_size(), request_size);
+    //     assert_eq!(size, tag.free_area_size() + new_tag.free_area_size() + mem::size_of::<BoundaryTag>() * 2);
+    // }
+
+    // #[test]
+    // fn test_addr()
+    // {
+    //     let (addr, size) = allocate_memory();
+    //     let tag = BoundaryTag::from_memory(addr, size);
+    //     assert_eq!(addr, tag.addr());
+    // }
+
+    // #[test]
+    // fn test_boundary_tag_from_memory()
+    // {
+    //     let (addr, size) = allocate_memory();
+
+    //     let tag = BoundaryTag::from_memory(addr, size);
+    //     assert_eq!((tag as *const _) as usize, addr);
+    //     assert_eq!(tag.free_area_size(), size - mem::size_of::<BoundaryTag>());
+    //     assert_eq!(tag.is_alloc, false);
+    //     assert_eq!(tag.is_last(), true);
+    // }
+
+    // #[test]
+    // fn test_divide()
+    // {
+    //     let (addr, size) = allocate_memory();
+    //     let tag = BoundaryTag::from_memory(addr, size);
+    //     assert_eq!(tag.free_area_size(), size - mem::size_of::<BoundaryTag>());
+
+    //     let request_size = size;
+    //     let (tag, new_tag_opt) = BoundaryTag::divide(tag, request_size);
+    //     assert!(new_tag_opt.is_none());
+    //     assert_eq!(tag.free_area_size(), size - mem::size_of::<BoundaryTag>());
+
+    //     let request_size = size / 4;
+    //     let (tag, new_tag_opt) = BoundaryTag::divide(tag, request_size);
+    //     let new_tag = new_tag_opt.unwrap();
+    //     assert_eq!(tag.free_area_size(), size - mem::size_of::<BoundaryTag>() - request_size - mem::size_of::<BoundaryTag>());
+
+    //     assert_eq!(new_tag.addr(), addr + mem::size_of::<BoundaryTag>() + tag.free_area_size());
+    //     assert_eq!(new_tag.free_area_size(), request_size);
+    //     assert_eq!(new_tag.is_alloc, false);
+    //     assert_eq!(new_tag.is_last(), true);
+
+    //     assert_eq!(tag.free_area_size(), size - (new_tag.free_area_size() + mem::size_of::<BoundaryTag>() * 2));
+    //     assert_eq!(tag.is_alloc, false);
+    //     assert_eq!(tag.is_last(), false);
+
+    //     assert_eq!(size, tag.free_area_size() + new_tag.free_area_size() + mem::size_of::<BoundaryTag>() * 2);
+    // }
+
+    // #[test]
+    // fn test_merge()
+    // {
+    //     let (addr, size) = allocate_memory();
+    //     let tag = BoundaryTag::from_memory(addr, size);
+    //     let request_size = size / 4;
+    //     let (tag, new_tag_opt) = BoundaryTag::divide(tag, request_size);
+    //     let new_tag = new_tag_opt.unwrap();
+
+    //     let merged_tag = BoundaryTag::merge(tag, new_tag);
+    //     assert_eq!(merged_tag.free_area_size(), size - mem::size_of::<BoundaryTag>());
+    // }
+
+    #[test]
+    #[cfg(not(any(feature = "checksum", feature = "track-caller", feature = "u32-sizes", feature = "mte")))]
+    fn test_header_size()
+    {
+        // The flags, the size and the link to the previous tag; the next tag is found from the size.
+        assert_eq!(mem::size_of::<BoundaryTag>(), 3 * mem::size_of::<usize>());
+        assert_eq!(mem::size_of::<BoundaryTag<usize>>(), 4 * mem::size_of::<usize>());
+    }
+
+    #[test]
+    #[cfg(all(feature = "u32-sizes", not(any(feature = "checksum", feature = "track-caller"))))]
+    fn test_header_size_u32()
+    {
+        assert_eq!(mem::size_of::<BoundaryTag>(), 16);
+    }
+
+    #[test]
+    fn test_try_from_memory()
+    {
+        let (addr, size) = allocate_memory();
+        let align        = mem::align_of::<BoundaryTag>();
+
+        let region = BoundaryTag::try_from_memory(addr + 1, size - 2).unwrap();
+        assert_eq!(unsafe { region.tag.as_ref() }.addr(), addr + align);
+        assert_eq!((region.head_trimmed, region.tail_trimmed), (align - 1, align - 1));
+        assert_eq!(unsafe { region.tag.as_ref() }.free_area_size(), size - 2 * align - mem::size_of::<BoundaryTag>());
+
+        let region = BoundaryTag::try_from_memory(addr, size).unwrap();
+        assert_eq!((region.head_trimmed, region.tail_trimmed), (0, 0));
+
+        assert_eq!(BoundaryTag::try_from_memory(addr + 1, mem::size_of::<BoundaryTag>()).err(), Some(RegionError::TooSmall(mem::size_of::<BoundaryTag>() - align)));
+        assert_eq!(BoundaryTag::try_from_memory(addr + 1, 2).err(), Some(RegionError::TooSmall(0)));
+    }
+
+    #[test]
+    fn test_next_tag_of()
+    {
+        let (addr, size) = allocate_memory();
+        let tag          = BoundaryTag::from_memory(addr, size);
+        let next_tag_opt = BoundaryTag::next_tag_of(&tag);
+        assert!(next_tag_opt.is_none());
+
+        let request_size = size / 4;
+        let (tag, new_tag_opt) = BoundaryTag::divide(tag, request_size, LinkKey::NONE);
+        assert!(new_tag_opt.is_some());
+
+        let new_tag = new_tag_opt.unwrap();
+
+        let next_tag_opt = BoundaryTag::next_tag_of(&tag);
+        assert!(next_tag_opt.is_some());
+        let next_tag = next_tag_opt.unwrap();
+
+        {
+            let tag = unsafe { tag.as_ref() };
+            let new_tag = unsafe { new_tag.as_ref() };
+            let next_tag = unsafe { next_tag.as_ref() };
+            assert_eq!(new_tag.addr(), next_tag.addr());
+            assert_eq!(new_tag.free_area_size(), next_tag.free_area_size());
+            assert_eq!(new_tag.is_alloc, next_tag.is_alloc);
+            assert_eq!(new_tag.is_last(), next_tag.is_last());
+            assert_eq!(tag.addr(), addr);
+        }
+
+        let next_next_tag_opt = BoundaryTag::next_tag_of(&next_tag);
+        assert!(next_next_tag_opt.is_none());
+
+        assert_eq!(unsafe { next_tag.as_ref() }.free_area_size(), request_size);
+    }
+
+    #[test]
+    fn test_prev_tag_of()
+    {
+        let (addr, size) = allocate_memory();
+        let tag = BoundaryTag::from_memory(addr, size);
+
+        let none = BoundaryTag::prev_tag_of(&tag, LinkKey::NONE);
+        assert!(none.is_none());
+
+        let request_size = size / 4;
+        let (tag, new_tag_opt) = BoundaryTag::divide(tag, request_size, LinkKey::NONE);
+        assert!(new_tag_opt.is_some());
+
+        let new_tag = new_tag_opt.unwrap();
+        let prev_tag_opt = BoundaryTag::prev_tag_of(&new_tag, LinkKey::NONE);
+        assert!(prev_tag_opt.is_some());
+
+        let prev_tag = prev_tag_opt.unwrap();
+        let prev_tag = unsafe { prev_tag.as_ref() };
+
+        assert_eq!(prev_tag.addr(), addr);
+        assert_eq!(prev_tag.addr(), unsafe {tag.as_ref()}.addr());
+        assert!(!prev_tag.is_alloc);
+        assert!(!prev_tag.is_last());
+        assert_eq!(prev_tag.free_area_size(), size - (request_size + 2 * mem::size_of::<BoundaryTag>()));
+    }
+
+    #[test]
+    fn test_addr_free_area()
+    {
+        let (addr, size) = allocate_memory();
+        let tag = BoundaryTag::from_memory(addr, size);
+        {
+            let tag_ref = unsafe { tag.as_ref() };
+            assert_eq!(tag_ref.addr_free_area(), addr + mem::size_of::<BoundaryTag>());
+            assert_eq!(tag_ref.free_area_size(), size - mem::size_of::<BoundaryTag>());
+        }
+
+        let request_size = size / 4;
+        let (tag, new_tag_opt) = BoundaryTag::divide(tag, request_size, LinkKey::NONE);
+        {
+            let tag_ref = unsafe { tag.as_ref() };
+            assert_eq!(tag_ref.addr(), addr);
+            assert_eq!(tag_ref.addr_free_area(), addr + mem::size_of::<BoundaryTag>());
+            assert_eq!(tag_ref.free_area_size(), size - mem::size_of::<BoundaryTag>() - request_size - mem::size_of::<BoundaryTag>());
+        }
+
+        let new_tag     = new_tag_opt.unwrap();
+        let tag_ref     = unsafe { tag.as_ref() };
+        let new_tag_ref = unsafe { new_tag.as_ref() };
+        assert_eq!(new_tag_ref.addr_free_area(), new_tag_ref.addr() + mem::size_of::<BoundaryTag>());
+        assert_eq!(new_tag_ref.free_area_size(), request_size);
+        assert_eq!(new_tag_ref.addr(), tag_ref.addr() + mem::size_of::<BoundaryTag>() + tag_ref.free_area_size());
+        assert_eq!(new_tag_ref.addr_free_area(), tag_ref.addr_free_area() + tag_ref.free_area_size() + mem::size_of::<BoundaryTag>());
+
+        assert_eq!(tag_ref.addr(), new_tag_ref.addr() - tag_ref.free_area_size() - mem::size_of::<BoundaryTag>());
+        assert_eq!(tag_ref.addr(), new_tag_ref.addr_free_area() - tag_ref.free_area_size() - mem::size_of::<BoundaryTag>() * 2);
+    }
+
+    #[test]
+    fn test_is_next_of()
+    {
+        let (addr, size) = allocate_memory();
+        let tag = BoundaryTag::from_memory(addr, size);
+        let request_size = size / 4;
+        let (tag, new_tag_opt) = BoundaryTag::divide(tag, request_size, LinkKey::NONE);
+        let new_tag = new_tag_opt.unwrap();
+
+        unsafe {
+            assert!(new_tag.as_ref().is_next_of(&tag));
+            assert!(!tag.as_ref().is_next_of(&new_tag));
+        }
+    }
+
+    #[test]
+    fn test_is_prev_of()
+    {
+        let (addr, size) = allocate_memory();
+        let tag = BoundaryTag::from_memory(addr, size);
+        let request_size = size / 4;
+        let (tag, new_tag_opt) = BoundaryTag::divide(tag, request_size, LinkKey::NONE);
+        let new_tag = new_tag_opt.unwrap();
+
+        unsafe {
+            assert!(!new_tag.as_ref().is_prev_of(&tag, LinkKey::NONE));
+            assert!(tag.as_ref().is_prev_of(&new_tag, LinkKey::NONE));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "checksum")]
+    #[should_panic]
+    fn test_checksum_detects_corruption()
+    {
+        let (addr, size) = allocate_memory();
+        let tag = BoundaryTag::from_memory(addr, size);
+        let request_size = size / 4;
+        let (mut tag, _) = BoundaryTag::divide(tag, request_size, LinkKey::NONE);
+
+        // Simulate a wild write into the header.
+        unsafe { tag.as_mut() }.free_area_size += 8;
+        let _ = BoundaryTag::next_tag_of(&tag);
+    }
+
+    #[test]
+    #[cfg(feature = "u32-sizes")]
+    fn test_u32_sizes()
+    {
+        let (addr, size) = allocate_memory();
+        let tag = BoundaryTag::from_memory(addr, size);
+        let (tag, new_tag_opt) = BoundaryTag::divide(tag, size / 4, LinkKey::NONE);
+        let new_tag = new_tag_opt.unwrap();
+        assert_eq!(mem::size_of::<super::SizeWord>(), 4);
+
+        unsafe {
+            let new_tag_addr = new_tag.as_ref().addr();
+            assert_eq!(tag.as_ref().prev_tag_addr(LinkKey::NONE), None);
+            assert_eq!(tag.as_ref().next_tag_addr(), Some(new_tag_addr));
+            assert_eq!(new_tag.as_ref().prev_tag_addr(LinkKey::NONE), Some(addr));
+            assert_eq!(new_tag.as_ref().next_tag_addr(), None);
+            assert_eq!(new_tag.as_ref().addr_free_area() + new_tag.as_ref().free_area_size(), addr + size);
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "harden", not(feature = "u32-sizes")))]
+    fn test_link_mangling()
+    {
+        let (addr, size) = allocate_memory();
+        let tag = BoundaryTag::from_memory(addr, size);
+        let (tag, new_tag_opt) = BoundaryTag::divide(tag, size / 4, LinkKey::NONE);
+        let new_tag = new_tag_opt.unwrap();
+
+        unsafe {
+            let new_tag_addr = new_tag.as_ref().addr();
+            assert_eq!(tag.as_ref().next_tag_addr(), Some(new_tag_addr));
+            assert_eq!(new_tag.as_ref().prev_tag_addr(LinkKey::NONE), Some(addr));
+            assert!(new_tag.as_ref().prev_tag_addr != addr);
+        }
+    }
+}
@@ -0,0 +1,452 @@
+use clock::Clock;
+use manager::{AllocObserver, MemoryManager};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceOp {
+    Alloc,
+    Free,
+}
+
+
+/// Allocation or free in a trace; the blocks are identified by the order of their allocations, not by their addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEvent {
+    pub op: TraceOp,
+    pub size: u32,
+    pub align: u32,
+    pub id: u32,
+}
+
+
+/// Size of an encoded `TraceEvent`.
+pub const TRACE_EVENT_SIZE: usize = 12;
+
+
+impl TraceEvent {
+    /// Encode the event into the little endian trace format: the op, the log2 of the align, two reserved bytes, the size and the id.
+    pub fn to_bytes(&self) -> [u8; TRACE_EVENT_SIZE]
+    {
+        let mut bytes = [0; TRACE_EVENT_SIZE];
+        bytes[0] = match self.op { TraceOp::Alloc => 0, TraceOp::Free => 1 };
+        bytes[1] = self.align.trailing_zeros() as u8;
+        bytes[4..8].copy_from_slice(&self.size.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.id.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; TRACE_EVENT_SIZE]) -> Option<TraceEvent>
+    {
+        let op =
+            match bytes[0] {
+                0 => TraceOp::Alloc,
+                1 => TraceOp::Free,
+                _ => return None,
+            };
+
+        if 32 <= bytes[1] {
+            return None;
+        }
+
+        let mut size = [0; 4];
+        let mut id   = [0; 4];
+        size.copy_from_slice(&bytes[4..8]);
+        id.copy_from_slice(&bytes[8..12]);
+
+        Some(TraceEvent {
+            op,
+            size: u32::from_le_bytes(size),
+            align: 1 << bytes[1],
+            id: u32::from_le_bytes(id),
+        })
+    }
+}
+
+
+/// `AllocObserver` recording the events of a heap into a caller-provided buffer.
+/// `addrs` keeps the address of every live block next to its allocation event, so it must be as long as `events`.
+pub struct TraceRecorder<'t> {
+    events: &'t mut [TraceEvent],
+    addrs: &'t mut [usize],
+    len: usize,
+    next_id: u32,
+    is_truncated: bool,
+}
+
+
+impl<'t> TraceRecorder<'t> {
+    pub fn new(events: &'t mut [TraceEvent], addrs: &'t mut [usize]) -> TraceRecorder<'t>
+    {
+        debug_check!(events.len() <= addrs.len());
+
+        TraceRecorder {
+            events,
+            addrs,
+            len: 0,
+            next_id: 0,
+            is_truncated: false,
+        }
+    }
+
+    pub fn events(&self) -> &[TraceEvent]
+    {
+        &self.events[..self.len]
+    }
+
+    /// Whether some events were dropped because the buffer was full.
+    pub fn is_truncated(&self) -> bool
+    {
+        self.is_truncated
+    }
+
+    fn push(&mut self, event: TraceEvent, addr: usize)
+    {
+        if self.len == self.events.len() {
+            self.is_truncated = true;
+            return;
+        }
+
+        self.events[self.len] = event;
+        self.addrs[self.len]  = addr;
+        self.len += 1;
+    }
+
+    /// Index of the allocation event of the live block at `addr`.
+    fn find_live(&self, addr: usize) -> Option<usize>
+    {
+        (0..self.len).rev().find(|&i| self.events[i].op == TraceOp::Alloc && self.addrs[i] == addr)
+    }
+}
+
+
+impl<'t> AllocObserver for TraceRecorder<'t> {
+    fn on_alloc(&mut self, addr: usize, size: usize, align: usize)
+    {
+        let event = TraceEvent {
+            op: TraceOp::Alloc,
+            size: size as u32,
+            align: align as u32,
+            id: self.next_id,
+        };
+        self.next_id += 1;
+        self.push(event, addr);
+    }
+
+    fn on_free(&mut self, addr: usize)
+    {
+        // The blocks allocated before the recorder was installed are not traced.
+        if let Some(i) = self.find_live(addr) {
+            let event = TraceEvent {
+                op: TraceOp::Free,
+                size: 0,
+                align: 1,
+                id: self.events[i].id,
+            };
+            self.addrs[i] = 0;
+            self.push(event, 0);
+        }
+    }
+
+    fn on_move(&mut self, old_addr: usize, new_addr: usize)
+    {
+        if let Some(i) = self.find_live(old_addr) {
+            self.addrs[i] = new_addr;
+        }
+    }
+}
+
+
+/// Number of the buckets of `LifetimeStats`.
+pub const NUM_LIFETIME_BUCKETS: usize = 32;
+
+
+/// Distribution of the ages of the blocks at their frees.
+/// The bucket 0 counts the blocks freed in the tick of their allocations and the bucket `b` counts the ages in `[2^(b - 1), 2^b)` ticks;
+/// the last bucket takes every longer age too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LifetimeStats {
+    pub buckets: [usize; NUM_LIFETIME_BUCKETS],
+    pub freed_blocks: usize,
+    pub total_ticks: u64,
+}
+
+
+impl LifetimeStats {
+    pub fn bucket_of(age: u64) -> usize
+    {
+        let bucket = (64 - age.leading_zeros()) as usize;
+        if bucket < NUM_LIFETIME_BUCKETS { bucket } else { NUM_LIFETIME_BUCKETS - 1 }
+    }
+
+    pub fn mean_age(&self) -> Option<u64>
+    {
+        if self.freed_blocks == 0 { None } else { Some(self.total_ticks / self.freed_blocks as u64) }
+    }
+
+    /// The number of the blocks freed younger than `2^(bucket - 1)` ticks, which are candidates for an arena or a pool.
+    pub fn freed_before(&self, bucket: usize) -> usize
+    {
+        self.buckets[..bucket].iter().sum()
+    }
+}
+
+
+/// `AllocObserver` timing every block from its allocation to its free by `clock`.
+/// `births` keeps the address and the timestamp of every live block; the blocks that do not fit are not timed.
+pub struct LifetimeRecorder<'t, C> {
+    clock: C,
+    births: &'t mut [(usize, u64)],
+    len: usize,
+    stats: LifetimeStats,
+    is_truncated: bool,
+}
+
+
+impl<'t, C: Clock> LifetimeRecorder<'t, C> {
+    pub fn new(clock: C, births: &'t mut [(usize, u64)]) -> LifetimeRecorder<'t, C>
+    {
+        LifetimeRecorder {
+            clock,
+            births,
+            len: 0,
+            stats: LifetimeStats {
+                buckets: [0; NUM_LIFETIME_BUCKETS],
+                freed_blocks: 0,
+                total_ticks: 0,
+            },
+            is_truncated: false,
+        }
+    }
+
+    pub fn stats(&self) -> &LifetimeStats
+    {
+        &self.stats
+    }
+
+    /// Whether some blocks were not timed because `births` was full.
+    pub fn is_truncated(&self) -> bool
+    {
+        self.is_truncated
+    }
+
+    fn find_live(&self, addr: usize) -> Option<usize>
+    {
+        (0..self.len).find(|&i| self.births[i].0 == addr)
+    }
+}
+
+
+impl<'t, C: Clock> AllocObserver for LifetimeRecorder<'t, C> {
+    fn on_alloc(&mut self, addr: usize, _size: usize, _align: usize)
+    {
+        if self.len == self.births.len() {
+            self.is_truncated = true;
+            return;
+        }
+
+        self.births[self.len] = (addr, self.clock.now());
+        self.len += 1;
+    }
+
+    fn on_free(&mut self, addr: usize)
+    {
+        if let Some(i) = self.find_live(addr) {
+            let age = self.clock.elapsed(self.births[i].1);
+            self.stats.buckets[LifetimeStats::bucket_of(age)] += 1;
+            self.stats.freed_blocks += 1;
+            self.stats.total_ticks  += age;
+
+            self.len -= 1;
+            self.births.swap(i, self.len);
+        }
+    }
+
+    fn on_move(&mut self, old_addr: usize, new_addr: usize)
+    {
+        if let Some(i) = self.find_live(old_addr) {
+            self.births[i].0 = new_addr;
+        }
+    }
+}
+
+
+/// Drive `mman` through `events`, keeping the address of every block by its id in `live`.
+/// Return the index of the first allocation that fails.
+pub fn replay(mman: &mut MemoryManager, events: &[TraceEvent], live: &mut [Option<usize>]) -> Result<(), usize>
+{
+    for (i, event) in events.iter().enumerate() {
+        let id = event.id as usize;
+        match event.op {
+            TraceOp::Alloc => {
+                match mman.alloc_aligned_bytes(event.size as usize, event.align as usize) {
+                    None => return Err(i),
+                    Some(addr) => live[id] = Some(addr),
+                }
+            },
+            TraceOp::Free => {
+                if let Some(addr) = live[id].take() {
+                    mman.free_bytes(addr);
+                }
+            },
+        }
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use super::*;
+    use BoundaryTag;
+    use HostRegion;
+    use RegionId;
+
+    #[test]
+    fn test_encoding()
+    {
+        let event = TraceEvent { op: TraceOp::Alloc, size: 4000, align: 64, id: 7 };
+        assert_eq!(TraceEvent::from_bytes(&event.to_bytes()), Some(event));
+
+        let event = TraceEvent { op: TraceOp::Free, size: 0, align: 1, id: 7 };
+        assert_eq!(TraceEvent::from_bytes(&event.to_bytes()), Some(event));
+        assert_eq!(TraceEvent::from_bytes(&[2; TRACE_EVENT_SIZE]), None);
+    }
+
+    #[test]
+    fn test_record_and_replay()
+    {
+        let mut events = [TraceEvent { op: TraceOp::Free, size: 0, align: 1, id: 0 }; 16];
+        let mut addrs  = [0; 16];
+        let mut recorder = TraceRecorder::new(&mut events, &mut addrs);
+
+        // Both heaps start on the same boundary of the 64-byte alignment, so that the replay splits the blocks the same way.
+        let host1 = HostRegion::new(4096, 64).unwrap();
+        let mut tags1 = [BoundaryTag::from_memory(host1.addr(), host1.size())];
+        let stats;
+        {
+            let mut mman = MemoryManager::new(&mut tags1);
+            mman.set_alloc_observer(&mut recorder);
+
+            let obj1 = mman.alloc_bytes(100).unwrap();
+            let obj2 = mman.alloc_aligned_bytes(200, 64).unwrap();
+            mman.free_bytes(obj1);
+            mman.alloc_bytes(300).unwrap();
+            mman.free_bytes(obj2);
+            stats = mman.stats();
+        }
+
+        assert!(!recorder.is_truncated());
+        assert_eq!(recorder.events().len(), 5);
+        assert_eq!(recorder.events()[1], TraceEvent { op: TraceOp::Alloc, size: 200, align: 64, id: 1 });
+        assert_eq!(recorder.events()[4], TraceEvent { op: TraceOp::Free, size: 0, align: 1, id: 1 });
+
+        let host2 = HostRegion::new(4096, 64).unwrap();
+        let mut tags2 = [BoundaryTag::from_memory(host2.addr(), host2.size())];
+        let mut mman  = MemoryManager::new(&mut tags2);
+        let mut live  = [None; 16];
+        assert_eq!(replay(&mut mman, recorder.events(), &mut live), Ok(()));
+        assert_eq!(mman.stats(), stats);
+        assert!(live[0].is_none() && live[1].is_none() && live[2].is_some());
+    }
+
+    #[test]
+    fn test_migrate_is_a_move()
+    {
+        let mut events = [TraceEvent { op: TraceOp::Free, size: 0, align: 1, id: 0 }; 16];
+        let mut addrs  = [0; 16];
+        let mut recorder = TraceRecorder::new(&mut events, &mut addrs);
+
+        let host1 = HostRegion::new(4096, 16).unwrap();
+        let host2 = HostRegion::new(4096, 16).unwrap();
+        let preference = [RegionId(1)];
+        let mut tags = [BoundaryTag::from_memory(host1.addr(), host1.size()), BoundaryTag::from_memory(host2.addr(), host2.size())];
+        {
+            let mut mman = MemoryManager::new(&mut tags);
+            mman.set_region_preference(&preference);
+            mman.set_alloc_observer(&mut recorder);
+
+            let obj   = mman.alloc_movable_bytes(128).unwrap();
+            let stats = mman.stats();
+            let mut moved = 0;
+            assert_eq!(mman.migrate_region(RegionId(1), |_, new_addr, _| moved = new_addr), 1);
+            assert_ne!(moved, obj);
+            assert_eq!((mman.stats().used_bytes, mman.stats().used_blocks), (stats.used_bytes, stats.used_blocks));
+            mman.free_bytes(moved);
+        }
+
+        // The trace sees a single block, freed at its new address.
+        assert_eq!(recorder.events().len(), 2);
+        assert_eq!(recorder.events()[1], TraceEvent { op: TraceOp::Free, size: 0, align: 1, id: 0 });
+    }
+
+    struct TestClock<'c>(&'c Cell<u64>);
+
+    impl<'c> Clock for TestClock<'c> {
+        fn now(&self) -> u64
+        {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn test_lifetimes()
+    {
+        let time       = Cell::new(0);
+        let mut births = [(0, 0); 2];
+        let mut recorder = LifetimeRecorder::new(TestClock(&time), &mut births);
+
+        let host = HostRegion::new(4096, 16).unwrap();
+        let mut tags = [BoundaryTag::from_memory(host.addr(), host.size())];
+        {
+            let mut mman = MemoryManager::new(&mut tags);
+            mman.set_alloc_observer(&mut recorder);
+
+            let short = mman.alloc_bytes(32).unwrap();
+            let long  = mman.alloc_bytes(32).unwrap();
+            let extra = mman.alloc_bytes(32).unwrap();
+            mman.free_bytes(short);
+            time.set(100);
+            mman.free_bytes(long);
+            mman.free_bytes(extra);
+        }
+
+        assert!(recorder.is_truncated());
+        let stats = recorder.stats();
+        assert_eq!(stats.freed_blocks, 2);
+        assert_eq!(stats.buckets[0], 1);
+        assert_eq!(stats.buckets[LifetimeStats::bucket_of(100)], 1);
+        assert_eq!(LifetimeStats::bucket_of(100), 7);
+        assert_eq!(stats.freed_before(1), 1);
+        assert_eq!(stats.mean_age(), Some(50));
+    }
+
+    #[test]
+    fn test_composite_observers()
+    {
+        let mut events = [TraceEvent { op: TraceOp::Free, size: 0, align: 1, id: 0 }; 16];
+        let mut addrs  = [0; 16];
+        let mut tracer = TraceRecorder::new(&mut events, &mut addrs);
+        let time       = Cell::new(0);
+        let mut births = [(0, 0); 4];
+        let mut ages   = LifetimeRecorder::new(TestClock(&time), &mut births);
+
+        let host = HostRegion::new(4096, 16).unwrap();
+        let mut tags = [BoundaryTag::from_memory(host.addr(), host.size())];
+        {
+            let mut observers = (&mut tracer, &mut ages);
+            let mut mman      = MemoryManager::new(&mut tags);
+            mman.set_alloc_observer(&mut observers);
+
+            let obj = mman.alloc_bytes(32).unwrap();
+            time.set(10);
+            mman.free_bytes(obj);
+        }
+
+        assert_eq!(tracer.events().len(), 2);
+        assert_eq!(ages.stats().freed_blocks, 1);
+        assert_eq!(ages.stats().mean_age(), Some(10));
+    }
+}
@@ -0,0 +1,90 @@
+use core::arch::wasm32;
+use core::ptr;
+
+use region::{MemoryRegion, MemorySource};
+
+
+/// Size of a page of the WebAssembly linear memory.
+pub const WASM_PAGE_SIZE: usize = 64 * 1024;
+
+
+/// `MemorySource` growing the linear memory of a WebAssembly module.
+/// The linear memory cannot shrink, so the released regions are kept in a list threaded through themselves and reused.
+pub struct WasmSource {
+    released_addr: Option<usize>,
+}
+
+
+impl WasmSource {
+    pub const fn new() -> WasmSource
+    {
+        WasmSource {
+            released_addr: None,
+        }
+    }
+
+    /// Take the first released region of `size` bytes at least out of the list.
+    fn reuse(&mut self, size: usize) -> Option<MemoryRegion>
+    {
+        let mut prev_addr: Option<usize> = None;
+        let mut addr = self.released_addr;
+        while let Some(a) = addr {
+            let (region_size, next_addr) = unsafe { ptr::read(a as *const (usize, Option<usize>)) };
+            if size <= region_size {
+                match prev_addr {
+                    None => self.released_addr = next_addr,
+                    Some(p) => unsafe { (*(p as *mut (usize, Option<usize>))).1 = next_addr },
+                }
+                return Some(MemoryRegion::new(a, region_size));
+            }
+
+            prev_addr = addr;
+            addr      = next_addr;
+        }
+
+        None
+    }
+}
+
+
+impl MemorySource for WasmSource {
+    fn request(&mut self, size: usize) -> Option<MemoryRegion>
+    {
+        if let Some(region) = self.reuse(size) {
+            return Some(region);
+        }
+
+        let num_pages = (size + WASM_PAGE_SIZE - 1) / WASM_PAGE_SIZE;
+        let prev      = wasm32::memory_grow(0, num_pages);
+        if prev == usize::MAX {
+            None
+        } else {
+            Some(MemoryRegion::new(prev * WASM_PAGE_SIZE, num_pages * WASM_PAGE_SIZE))
+        }
+    }
+
+    fn release(&mut self, region: MemoryRegion)
+    {
+        unsafe { ptr::write(region.addr() as *mut (usize, Option<usize>), (region.size(), self.released_addr)) };
+        self.released_addr = Some(region.addr());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuse()
+    {
+        let mut source = WasmSource::new();
+        let region     = source.request(100).unwrap();
+        let addr       = region.addr();
+        assert_eq!(addr % WASM_PAGE_SIZE, 0);
+        assert_eq!(region.size(), WASM_PAGE_SIZE);
+
+        source.release(region);
+        assert_eq!(source.request(WASM_PAGE_SIZE).unwrap().addr(), addr);
+    }
+}